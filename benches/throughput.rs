@@ -0,0 +1,290 @@
+//! Measures how fast a single connection forwards Play state traffic from a
+//! backend to a player: uncompressed, compressed alike on both sides, and
+//! after a switch to a server that compresses differently, where every
+//! packet is inflated and compressed again. A listener inspecting every
+//! packet shows what leaving the fast path costs.
+//!
+//! Run with `cargo bench --bench throughput`.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+use rustyproxy::{
+    event::{EventBus, ServerSentPacket},
+    packet::{
+        self,
+        data::{ByteBuf, ByteBufMut},
+        FrameLimits, RawPacket,
+    },
+    ProxyConfiguration, ProxyInstance,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const PROTOCOL: u32 = 769;
+const PACKET_SIZE: usize = 8 * 1024;
+/// Number of 16-packet batches the backend sends, about 512 MiB.
+const BATCHES: usize = 4096;
+const PACKETS_PER_BATCH: usize = 16;
+
+const CHUNK_DATA: u32 = 0x28;
+const START_CONFIGURATION: u32 = 0x70;
+const CONFIGURATION_ACKNOWLEDGED: u32 = 0x0E;
+const CHAT_COMMAND: u32 = 0x05;
+
+const LIMITS: FrameLimits = FrameLimits {
+    max_frame_length: 2097151,
+    max_uncompressed_length: 8388608,
+};
+
+struct Case {
+    name: &'static str,
+    /// Whether a listener asks for every packet from the server.
+    inspect: bool,
+    /// The compression threshold of the server the player joins, 0 for none.
+    threshold: u32,
+    /// The threshold of a server the player switches to before the traffic
+    /// starts, so that it is compressed differently than the player expects.
+    switch_to: Option<u32>,
+}
+
+#[tokio::main]
+async fn main() {
+    let cases = [
+        Case { name: "fast path", inspect: false, threshold: 0, switch_to: None },
+        Case { name: "listener on every packet", inspect: true, threshold: 0, switch_to: None },
+        Case { name: "compressed fast path", inspect: false, threshold: 256, switch_to: None },
+        Case { name: "compressed with listener", inspect: true, threshold: 256, switch_to: None },
+        Case { name: "transcoded 1024 -> 256", inspect: false, threshold: 256, switch_to: Some(1024) },
+    ];
+
+    for (i, case) in cases.iter().enumerate() {
+        run(case, 25611 + i as u16).await;
+    }
+}
+
+async fn run(case: &Case, proxy_port: u16) {
+    let (first_port, second_port) = (proxy_port + 100, proxy_port + 200);
+    let config: ProxyConfiguration = toml::from_str(&format!(
+        r#"
+            proxy_port = {proxy_port}
+            address = "127.0.0.1"
+            try = ["a"]
+
+            [servers]
+            a = {{ address = "127.0.0.1", port = {first_port}, name = "a" }}
+            b = {{ address = "127.0.0.1", port = {second_port}, name = "b" }}
+        "#
+    ))
+    .unwrap();
+
+    let instance = rustyproxy::new_instance(config).unwrap();
+    let event_bus = EventBus::new(&instance);
+
+    if case.inspect {
+        event_bus
+            .listen::<ServerSentPacket, _, _, _>(false, |_, _| async move { None })
+            .await;
+    }
+
+    let first = TcpListener::bind(("127.0.0.1", first_port)).await.unwrap();
+    let second = TcpListener::bind(("127.0.0.1", second_port)).await.unwrap();
+    match case.switch_to {
+        Some(threshold) => {
+            tokio::spawn(serve_backend(first, case.threshold, false));
+            tokio::spawn(serve_backend(second, threshold, true));
+        }
+        None => {
+            tokio::spawn(serve_backend(first, case.threshold, true));
+        }
+    }
+    tokio::spawn(ProxyInstance::start(instance, event_bus));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = TcpStream::connect(("127.0.0.1", proxy_port)).await.unwrap();
+    let threshold = log_in(&mut client).await;
+    if case.switch_to.is_some() {
+        switch_server(&mut client, threshold).await;
+    }
+
+    let started = Instant::now();
+    let (packets, received) = count_packets(&mut client, threshold).await;
+    let elapsed = started.elapsed();
+
+    let payload = (packets * PACKET_SIZE) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<26} {:>8.1} MiB in {:>6.2?} = {:>8.1} MiB/s ({:.1} MiB on the wire)",
+        case.name,
+        payload,
+        elapsed,
+        payload / elapsed.as_secs_f64(),
+        received as f64 / (1024.0 * 1024.0),
+    );
+}
+
+/// Logs in like a vanilla server would, compressing from `threshold` on,
+/// then streams Play packets if `stream`, or waits for the player to leave.
+async fn serve_backend(listener: TcpListener, threshold: u32, stream_play: bool) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+
+    read_packet(&mut stream, 0).await; // Handshake
+    read_packet(&mut stream, 0).await; // Login Start
+
+    if threshold > 0 {
+        let mut set_compression = ByteBufMut::new();
+        set_compression.write_varint(threshold);
+        write_packet(&mut stream, 0, 0x03, set_compression.as_slice()).await;
+    }
+
+    let mut login_success = ByteBufMut::new();
+    login_success.write_uuid(&uuid::Uuid::nil());
+    login_success.write_string("bench");
+    login_success.write_varint(0);
+    write_packet(&mut stream, threshold, 0x02, login_success.as_slice()).await;
+    read_packet(&mut stream, threshold).await; // Login Acknowledged
+
+    write_packet(&mut stream, threshold, 0x03, &[]).await; // Finish Configuration
+    read_packet(&mut stream, threshold).await; // Acknowledge Finish Configuration
+
+    if !stream_play {
+        let _ = stream.read_to_end(&mut Vec::new()).await;
+        return;
+    }
+
+    let batch = play_batch(threshold);
+    for _ in 0..BATCHES {
+        stream.write_all(&batch).await.unwrap();
+    }
+
+    stream.shutdown().await.unwrap();
+}
+
+/// 16 Chunk Data and Update Light packets, which nothing listens for. The
+/// bodies compress about as well as real chunks.
+fn play_batch(threshold: u32) -> Vec<u8> {
+    let mut seed = 0x2545F491u32;
+    let body: Vec<u8> = (0..PACKET_SIZE)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            (seed % 16) as u8
+        })
+        .collect();
+
+    (0..PACKETS_PER_BATCH)
+        .flat_map(|_| frame(threshold, CHUNK_DATA, &body))
+        .collect()
+}
+
+/// Logs in and returns the compression threshold the proxy set.
+async fn log_in(stream: &mut TcpStream) -> u32 {
+    let mut handshake = ByteBufMut::new();
+    handshake.write_varint(PROTOCOL);
+    handshake.write_string("127.0.0.1");
+    handshake.write_u16(25565);
+    handshake.write_varint(2);
+    write_packet(stream, 0, 0x00, handshake.as_slice()).await;
+
+    let mut login_start = ByteBufMut::new();
+    login_start.write_string("bench");
+    login_start.write_uuid(&uuid::Uuid::nil());
+    write_packet(stream, 0, 0x00, login_start.as_slice()).await;
+
+    let mut threshold = 0;
+    let mut packet = read_packet(stream, threshold).await;
+    if packet.id == 0x03 {
+        threshold = ByteBuf::new(&packet.data).read_varint().unwrap();
+        packet = read_packet(stream, threshold).await;
+    }
+    assert_eq!(packet.id, 0x02, "expected Login Success");
+
+    write_packet(stream, threshold, 0x03, &[]).await; // Login Acknowledged
+    read_packet(stream, threshold).await; // Finish Configuration
+    write_packet(stream, threshold, 0x03, &[]).await; // Acknowledge Finish Configuration
+
+    threshold
+}
+
+/// Switches to server `b` with a command, going through the Configuration
+/// state again.
+async fn switch_server(stream: &mut TcpStream, threshold: u32) {
+    let mut command = ByteBufMut::new();
+    command.write_string("server b");
+    write_packet(stream, threshold, CHAT_COMMAND, command.as_slice()).await;
+
+    while read_packet(stream, threshold).await.id != START_CONFIGURATION {}
+    write_packet(stream, threshold, CONFIGURATION_ACKNOWLEDGED, &[]).await;
+
+    while read_packet(stream, threshold).await.id != 0x03 {} // Finish Configuration
+    write_packet(stream, threshold, 0x03, &[]).await; // Acknowledge Finish Configuration
+}
+
+/// Reads until the backend's Play packets all arrived or the connection
+/// closed, returning how many arrived and the bytes received. They are told
+/// apart by size, so the player side inflates nothing and costs little next
+/// to the proxy.
+async fn count_packets(stream: &mut TcpStream, threshold: u32) -> (usize, usize) {
+    let expected = BATCHES * PACKETS_PER_BATCH;
+    let mut buffer = Vec::with_capacity(256 * 1024);
+    let mut chunk = vec![0u8; 64 * 1024];
+    let (mut packets, mut received) = (0, 0);
+
+    while packets < expected {
+        let n = stream.read(&mut chunk).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        received += n;
+        buffer.extend_from_slice(&chunk[..n]);
+
+        let mut consumed = 0;
+        while let Some(length) = packet::frame_length(&buffer[consumed..], LIMITS).unwrap() {
+            let frame = &buffer[consumed..consumed + length];
+            if packet::peek_uncompressed_length(frame, threshold).unwrap_or(length) > PACKET_SIZE {
+                packets += 1;
+            }
+            consumed += length;
+        }
+        buffer.drain(..consumed);
+    }
+
+    (packets, received)
+}
+
+/// A whole frame, compressed as a connection with `threshold` expects.
+fn frame(threshold: u32, id: u32, body: &[u8]) -> Vec<u8> {
+    let mut packet = ByteBufMut::new();
+    packet.write_varint(id);
+    packet.write_bytes(body);
+
+    let mut frame = ByteBufMut::new();
+    if threshold == 0 {
+        frame.write_bytes(packet.as_slice());
+    } else if packet.len() < threshold as usize {
+        frame.write_varint(0);
+        frame.write_bytes(packet.as_slice());
+    } else {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(packet.as_slice()).unwrap();
+        frame.write_varint(packet.len() as u32);
+        frame.write_bytes(&encoder.finish().unwrap());
+    }
+
+    let mut framed = ByteBufMut::new();
+    framed.write_prefixed_bytes(frame.as_slice());
+    framed.into_inner()
+}
+
+async fn write_packet(stream: &mut TcpStream, threshold: u32, id: u32, body: &[u8]) {
+    stream.write_all(&frame(threshold, id, body)).await.unwrap();
+}
+
+async fn read_packet(stream: &mut TcpStream, threshold: u32) -> RawPacket {
+    packet::read_packet(stream, threshold, LIMITS).await.unwrap()
+}
@@ -0,0 +1,10 @@
+-- Loaded when rustyproxy is built with `--features lua`.
+-- Send SIGHUP to the proxy to reload scripts without restarting.
+
+proxy.on("ProxyPinged", function(event)
+    event.status.motd = "§brustyproxy §7- §a" .. #proxy.servers() .. " server(s) online"
+end)
+
+proxy.on("PlayerJoinedProxy", function(event)
+    proxy.log(event.player.username .. " joined from " .. event.player.address)
+end)
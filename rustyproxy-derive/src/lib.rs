@@ -0,0 +1,269 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, GenericArgument, Ident,
+    PathArguments, Type,
+};
+
+/// Derives `rustyproxy::packet::Packet` for a struct with named fields.
+///
+/// The packet is identified with `#[packet(kind = SystemChatMessage)]`, naming a
+/// `PacketKind` variant. Fields are encoded in declaration order through
+/// `PacketField` unless annotated:
+///
+/// - `#[varint]` for a `u32` sent as a VarInt
+/// - `#[nbt_text]` / `#[json_text]` for a `FormattedText` sent as NBT or as a JSON string
+/// - `#[prefixed_array]` for a `Vec<T>` preceded by its VarInt length
+///
+/// An `Option<T>` field is preceded by a boolean telling whether it is present,
+/// and its value follows the same rules as `T`.
+#[proc_macro_derive(Packet, attributes(packet, varint, nbt_text, json_text, prefixed_array))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Field,
+    VarInt,
+    NbtText,
+    JsonText,
+    PrefixedArray,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let kind = packet_kind(&input)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect::<Vec<_>>(),
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(fields) => {
+                return Err(Error::new(
+                    fields.span(),
+                    "#[derive(Packet)] needs named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "#[derive(Packet)] only supports structs",
+            ))
+        }
+    };
+
+    let mut writes = Vec::new();
+    let mut reads = Vec::new();
+    let mut names = Vec::new();
+
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let encoding = encoding(&field.attrs)?;
+
+        let value = format_ident!("value");
+        let (write, read) = match option_inner(&field.ty) {
+            Some(inner) => {
+                let write = write_value(&value, inner, encoding)?;
+                let read = read_value(inner, encoding)?;
+                (
+                    quote! {
+                        buffer.write_bool(self.#ident.is_some());
+                        if let Some(#value) = &self.#ident {
+                            #write
+                        }
+                    },
+                    quote! {
+                        if buffer.read_bool()? {
+                            Some(#read)
+                        } else {
+                            None
+                        }
+                    },
+                )
+            }
+            None => {
+                let write = write_value(&value, &field.ty, encoding)?;
+                let read = read_value(&field.ty, encoding)?;
+                (
+                    quote! {
+                        let #value = &self.#ident;
+                        #write
+                    },
+                    read,
+                )
+            }
+        };
+
+        writes.push(quote! { { #write } });
+        reads.push(quote! { let #ident = #read; });
+        names.push(ident);
+    }
+
+    let construct = match &input.data {
+        Data::Struct(data) if matches!(data.fields, Fields::Unit) => quote! { #name },
+        _ => quote! { #name { #(#names),* } },
+    };
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::rustyproxy::packet::Packet for #name #type_generics #where_clause {
+            fn kind() -> ::rustyproxy::packet::registry::PacketKind {
+                ::rustyproxy::packet::registry::PacketKind::#kind
+            }
+
+            #[allow(unused_variables)]
+            fn write_to(
+                &self,
+                buffer: &mut ::rustyproxy::packet::data::ByteBufMut,
+            ) -> Result<(), ::rustyproxy::packet::PacketError> {
+                #(#writes)*
+                Ok(())
+            }
+
+            #[allow(unused_variables)]
+            fn read_body(
+                buffer: &mut ::rustyproxy::packet::data::ByteBuf,
+            ) -> Result<Self, ::rustyproxy::packet::PacketError> {
+                #(#reads)*
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+fn packet_kind(input: &DeriveInput) -> Result<Ident, Error> {
+    let mut kind = None;
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("packet")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("kind") {
+                kind = Some(meta.value()?.parse::<Ident>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `kind = ...`"))
+            }
+        })?;
+    }
+
+    kind.ok_or_else(|| {
+        Error::new(
+            input.ident.span(),
+            "missing #[packet(kind = ...)] attribute",
+        )
+    })
+}
+
+fn encoding(attrs: &[syn::Attribute]) -> Result<Encoding, Error> {
+    let mut found = Encoding::Field;
+
+    for attr in attrs {
+        let encoding = if attr.path().is_ident("varint") {
+            Encoding::VarInt
+        } else if attr.path().is_ident("nbt_text") {
+            Encoding::NbtText
+        } else if attr.path().is_ident("json_text") {
+            Encoding::JsonText
+        } else if attr.path().is_ident("prefixed_array") {
+            Encoding::PrefixedArray
+        } else {
+            continue;
+        };
+
+        if found != Encoding::Field {
+            return Err(Error::new(
+                attr.span(),
+                "a field can only have one encoding attribute",
+            ));
+        }
+        found = encoding;
+    }
+
+    Ok(found)
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    generic_argument(ty, "Option")
+}
+
+fn generic_argument<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+
+    let segment = path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => match arguments.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn write_value(value: &Ident, ty: &Type, encoding: Encoding) -> Result<TokenStream2, Error> {
+    Ok(match encoding {
+        Encoding::Field => quote! {
+            ::rustyproxy::packet::data::PacketField::write(#value, buffer);
+        },
+        Encoding::VarInt => quote! {
+            buffer.write_varint(*#value);
+        },
+        Encoding::NbtText => quote! {
+            buffer.write_nbt_text(#value)?;
+        },
+        Encoding::JsonText => quote! {
+            buffer.write_json_text(#value);
+        },
+        Encoding::PrefixedArray => {
+            array_element(ty)?;
+            quote! {
+                buffer.write_prefixed_array(#value, |buffer, item| {
+                    ::rustyproxy::packet::data::PacketField::write(item, buffer)
+                });
+            }
+        }
+    })
+}
+
+fn read_value(ty: &Type, encoding: Encoding) -> Result<TokenStream2, Error> {
+    Ok(match encoding {
+        Encoding::Field => quote! {
+            <#ty as ::rustyproxy::packet::data::PacketField>::read(buffer)?
+        },
+        Encoding::VarInt => quote! {
+            buffer.read_varint()?
+        },
+        Encoding::NbtText => quote! {
+            buffer.read_nbt_text()?
+        },
+        Encoding::JsonText => quote! {
+            buffer.read_json_text()?
+        },
+        Encoding::PrefixedArray => {
+            let element = array_element(ty)?;
+            quote! {
+                buffer.read_prefixed_array(usize::MAX, |buffer| {
+                    <#element as ::rustyproxy::packet::data::PacketField>::read(buffer)
+                })?
+            }
+        }
+    })
+}
+
+fn array_element(ty: &Type) -> Result<&Type, Error> {
+    generic_argument(ty, "Vec")
+        .ok_or_else(|| Error::new(ty.span(), "#[prefixed_array] needs a Vec<T> field"))
+}
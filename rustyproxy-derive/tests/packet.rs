@@ -0,0 +1,132 @@
+//! Round trips packets derived with every field encoding through the
+//! generated `write_to` and `read_body`.
+
+use azalea_chat::FormattedText;
+use rustyproxy::packet::{
+    data::ByteBufMut,
+    registry::PacketKind,
+    Packet, PacketError,
+};
+use serde_json::{json, Value};
+
+#[derive(Clone, Packet)]
+#[packet(kind = SystemChatMessage)]
+struct Everything {
+    plain: i32,
+    name: String,
+    #[varint]
+    count: u32,
+    #[nbt_text]
+    nbt: FormattedText,
+    #[json_text]
+    json: FormattedText,
+    #[prefixed_array]
+    names: Vec<String>,
+    maybe_name: Option<String>,
+    #[varint]
+    maybe_count: Option<u32>,
+    #[nbt_text]
+    maybe_text: Option<FormattedText>,
+    #[prefixed_array]
+    maybe_names: Option<Vec<u16>>,
+}
+
+#[derive(Clone, Packet)]
+#[packet(kind = StatusRequest)]
+struct Empty;
+
+fn text(json: Value) -> FormattedText {
+    serde_json::from_value(json).unwrap()
+}
+
+fn json(text: &FormattedText) -> Value {
+    serde_json::to_value(text).unwrap()
+}
+
+fn encoded(packet: &impl Packet) -> Vec<u8> {
+    let mut buffer = ByteBufMut::new();
+    packet.write_to(&mut buffer).unwrap();
+    buffer.into_inner()
+}
+
+fn everything(present: bool) -> Everything {
+    Everything {
+        plain: -7,
+        name: "rustyproxy".to_owned(),
+        count: 300,
+        nbt: text(json!({ "text": "Hello", "color": "green", "bold": true })),
+        json: text(json!({ "text": "world", "extra": [{ "text": "!", "italic": true }] })),
+        names: vec!["a".to_owned(), "bc".to_owned()],
+        maybe_name: present.then(|| "maybe".to_owned()),
+        maybe_count: present.then_some(2_000_000),
+        maybe_text: present.then(|| text(json!({ "text": "optional" }))),
+        maybe_names: present.then(|| vec![1, 65535]),
+    }
+}
+
+fn assert_same(decoded: &Everything, expected: &Everything) {
+    assert_eq!(decoded.plain, expected.plain);
+    assert_eq!(decoded.name, expected.name);
+    assert_eq!(decoded.count, expected.count);
+    assert_eq!(json(&decoded.nbt), json(&expected.nbt));
+    assert_eq!(json(&decoded.json), json(&expected.json));
+    assert_eq!(decoded.names, expected.names);
+    assert_eq!(decoded.maybe_name, expected.maybe_name);
+    assert_eq!(decoded.maybe_count, expected.maybe_count);
+    assert_eq!(decoded.maybe_text.as_ref().map(json), expected.maybe_text.as_ref().map(json));
+    assert_eq!(decoded.maybe_names, expected.maybe_names);
+}
+
+#[test]
+fn packets_round_trip() {
+    for present in [true, false] {
+        let packet = everything(present);
+        let decoded = Everything::decode(&encoded(&packet)).unwrap();
+        assert_same(&decoded, &packet);
+    }
+}
+
+#[test]
+fn fields_are_encoded_as_annotated() {
+    let packet = everything(false);
+    let bytes = encoded(&packet);
+
+    // A plain i32, then a VarInt-prefixed string, then 300 as a VarInt.
+    assert_eq!(bytes[..4], (-7i32).to_be_bytes());
+    assert_eq!(bytes[4], 10);
+    assert_eq!(&bytes[5..15], b"rustyproxy");
+    assert_eq!(bytes[15..17], [0xAC, 0x02]);
+
+    // Absent options are a single false each, at the very end.
+    assert_eq!(bytes[bytes.len() - 4..], [0, 0, 0, 0]);
+}
+
+#[test]
+fn unit_packets_are_empty() {
+    assert!(encoded(&Empty).is_empty());
+    assert!(Empty::decode(&[]).is_ok());
+    assert_eq!(Empty::kind(), PacketKind::StatusRequest);
+    assert_eq!(Everything::kind(), PacketKind::SystemChatMessage);
+}
+
+#[test]
+fn truncated_packets_are_errors() {
+    for present in [true, false] {
+        let bytes = encoded(&everything(present));
+
+        for length in 0..bytes.len() {
+            assert!(
+                Everything::decode(&bytes[..length]).is_err(),
+                "decoded {} of {} bytes",
+                length,
+                bytes.len()
+            );
+        }
+    }
+
+    let bytes = encoded(&everything(true));
+    assert!(matches!(
+        Everything::decode(&bytes[..2]),
+        Err(PacketError::Truncated { needed: 4, remaining: 2 })
+    ));
+}
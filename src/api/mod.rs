@@ -0,0 +1,512 @@
+//! A local HTTP API to administer the proxy. Every request needs the
+//! configured token as `Authorization: Bearer <token>`; bodies and responses
+//! are JSON.
+//!
+//! - `GET /players` lists the players and the server each is on, or
+//!   whether it waits in limbo.
+//! - `POST /players/{name or uuid}/kick` with `{"reason": "..."}`.
+//! - `POST /players/{name or uuid}/send` with `{"server": "key"}`.
+//! - `POST /broadcast` with `{"message": "..."}`.
+//! - `GET /servers` lists the servers.
+//! - `PUT /servers/{key}` with `{"address": "...", "port": 25565, "name": "..."}`.
+//! - `DELETE /servers/{key}` stops routing players to a server.
+//! - `POST /servers/{key}/drain`, optionally with `{"to": "key"}`, moves the
+//!   server's players away and sends no new ones; `DELETE` on the same path
+//!   undoes it.
+//! - `GET /maintenance` tells whether maintenance is on; `POST` turns it
+//!   on, kicking players when configured to, and `DELETE` turns it off.
+//! - `POST /reload` reads the configuration file again.
+
+use std::{io::Error, sync::Arc};
+
+use azalea_chat::{text_component::TextComponent, FormattedText};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    task,
+};
+use tracing::{error, info};
+
+use crate::{
+    http::{self, Request, Response},
+    maintenance::set_maintenance,
+    player::PlayerConnection,
+    server::ProxiedServer,
+    find_player, players_by_server, reload, SharedProxyInstance,
+};
+
+/// Where to serve the API, e.g. `[api]` with `port = 9226` and a `token`.
+#[derive(Deserialize, Clone)]
+pub struct ApiConfiguration {
+    /// Defaults to `127.0.0.1`, as the API is not meant to be public.
+    pub address: Option<String>,
+    pub port: u16,
+    /// The bearer token every request has to carry. The API does not start
+    /// without one.
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct KickRequest {
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SendRequest {
+    server: String,
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    message: String,
+}
+
+#[derive(Deserialize, Default)]
+struct DrainRequest {
+    to: Option<String>,
+}
+
+/// Answers API requests on `listener` until the proxy stops.
+pub async fn serve(listener: TcpListener, instance: SharedProxyInstance, token: String) {
+    let token = Arc::new(token);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let instance = Arc::clone(&instance);
+                let token = Arc::clone(&token);
+
+                task::spawn(async move {
+                    let _ = respond(stream, &instance, &token).await;
+                });
+            }
+            Err(e) => error!("Failed to accept API connection: {:?}", e),
+        }
+    }
+}
+
+async fn respond(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    instance: &SharedProxyInstance,
+    token: &str,
+) -> Result<(), Error> {
+    let Some(request) = http::read_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    let response = if authorized(&request, token) {
+        handle(&request, instance).await
+    } else {
+        failure("401 Unauthorized", "missing or wrong token")
+    };
+
+    http::write_response(&mut stream, response).await
+}
+
+fn authorized(request: &Request, token: &str) -> bool {
+    let Some(given) = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    // Compared in full so the time taken does not tell how much matched
+    !token.is_empty()
+        && given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+async fn handle(request: &Request, instance: &SharedProxyInstance) -> Response {
+    match (request.method.as_str(), request.segments().as_slice()) {
+        ("GET", ["players"]) => list_players(instance).await,
+        ("POST", ["players", player, "kick"]) => match body::<KickRequest>(request) {
+            Ok(body) => kick(instance, player, body).await,
+            Err(response) => response,
+        },
+        ("POST", ["players", player, "send"]) => match body::<SendRequest>(request) {
+            Ok(body) => send(instance, player, &body.server).await,
+            Err(response) => response,
+        },
+        ("POST", ["broadcast"]) => match body::<BroadcastRequest>(request) {
+            Ok(body) => broadcast(instance, &body.message).await,
+            Err(response) => response,
+        },
+        ("GET", ["servers"]) => list_servers(instance).await,
+        ("PUT", ["servers", key]) => match body::<ProxiedServer>(request) {
+            Ok(server) => {
+                let replaced = {
+                    let mut instance = instance.write().await;
+                    let replaced = instance.servers.contains_key(*key);
+                    instance.add_server((*key).to_owned(), server);
+                    replaced
+                };
+                info!("Registered server {} through the API", key);
+
+                if replaced {
+                    success("200 OK", json!({ "server": key }))
+                } else {
+                    success("201 Created", json!({ "server": key }))
+                }
+            }
+            Err(response) => response,
+        },
+        ("DELETE", ["servers", key]) => match instance.write().await.remove_server(key) {
+            Some(_) => {
+                info!("Unregistered server {} through the API", key);
+                success("200 OK", json!({ "server": key }))
+            }
+            None => failure("404 Not Found", "no such server"),
+        },
+        ("POST", ["servers", key, "drain"]) => {
+            let body = if request.body.is_empty() {
+                Ok(DrainRequest::default())
+            } else {
+                body::<DrainRequest>(request)
+            };
+
+            match body {
+                Ok(body) => drain(instance, key, body.to.as_deref()).await,
+                Err(response) => response,
+            }
+        }
+        ("DELETE", ["servers", key, "drain"]) => {
+            if instance.write().await.draining.remove(*key) {
+                success("200 OK", json!({ "server": key }))
+            } else {
+                failure("404 Not Found", "the server is not draining")
+            }
+        }
+        ("GET", ["maintenance"]) => success("200 OK", json!({ "enabled": instance.read().await.maintenance })),
+        ("POST", ["maintenance"]) => match set_maintenance(instance, true).await {
+            Some(kicked) => success("200 OK", json!({ "kicked": kicked })),
+            None => failure("409 Conflict", "maintenance is already on"),
+        },
+        ("DELETE", ["maintenance"]) => match set_maintenance(instance, false).await {
+            Some(_) => success("200 OK", json!({})),
+            None => failure("409 Conflict", "maintenance is already off"),
+        },
+        ("POST", ["reload"]) => match reload(instance).await {
+            Ok(()) => success("200 OK", json!({})),
+            Err(e) => failure("500 Internal Server Error", &e.to_string()),
+        },
+        _ => failure("404 Not Found", "no such endpoint"),
+    }
+}
+
+async fn list_players(instance: &SharedProxyInstance) -> Response {
+    let (connected, servers) = {
+        let instance = instance.read().await;
+        let connected: Vec<_> = instance.players.iter().map(|(uuid, player)| (*uuid, player.clone())).collect();
+        (connected, instance.servers.clone())
+    };
+
+    let mut players = Vec::with_capacity(connected.len());
+    for (uuid, player) in connected {
+        let name = player.player_info.lock().await.as_ref().map(|info| info.username.clone());
+        let server = player.current_server().await;
+        let key = server.and_then(|server| {
+            servers
+                .iter()
+                .find(|(_, known)| Arc::ptr_eq(known, &server))
+                .map(|(key, _)| key.clone())
+        });
+
+        players.push(json!({
+            "uuid": uuid.to_string(),
+            "name": name,
+            "address": player.addr.to_string(),
+            "protocol": player.protocol,
+            "server": key,
+            "limbo": player.in_limbo(),
+        }));
+    }
+
+    success("200 OK", Value::Array(players))
+}
+
+async fn kick(instance: &SharedProxyInstance, query: &str, body: KickRequest) -> Response {
+    let Some(mut player) = find_player(instance, query).await else {
+        return failure("404 Not Found", "no such player");
+    };
+
+    let reason = body.reason.unwrap_or_else(|| "You were kicked from the proxy.".to_owned());
+
+    match player.kick(FormattedText::Text(TextComponent::new(reason))).await {
+        Ok(()) => success("200 OK", json!({})),
+        Err(e) => failure("500 Internal Server Error", &e.to_string()),
+    }
+}
+
+async fn send(instance: &SharedProxyInstance, query: &str, key: &str) -> Response {
+    let Some(player) = find_player(instance, query).await else {
+        return failure("404 Not Found", "no such player");
+    };
+
+    let uuid = player.uuid().await.unwrap_or_default();
+    let players = players_by_server(instance).await;
+    let server = instance.read().await.pick_server(key, uuid, &players);
+
+    let Some((key, server)) = server else {
+        return failure("404 Not Found", "no such server");
+    };
+
+    match player.send_to(&server).await {
+        Ok(()) => success("200 OK", json!({ "server": key })),
+        Err(e) => failure("409 Conflict", &e.to_string()),
+    }
+}
+
+async fn broadcast(instance: &SharedProxyInstance, message: &str) -> Response {
+    let players: Vec<PlayerConnection> = instance.read().await.players.values().cloned().collect();
+    let mut delivered = 0;
+
+    for player in &players {
+        let text = FormattedText::Text(TextComponent::new(message.to_owned()));
+        if player.send_message(text).await.is_ok() {
+            delivered += 1;
+        }
+    }
+
+    success("200 OK", json!({ "delivered": delivered }))
+}
+
+async fn list_servers(instance: &SharedProxyInstance) -> Response {
+    let players = players_by_server(instance).await;
+    let instance = instance.read().await;
+
+    let servers = instance
+        .servers
+        .iter()
+        .map(|(key, server)| {
+            let health = server.health();
+            json!({
+                "key": key,
+                "name": server.name,
+                "address": server.address,
+                "port": server.port,
+                "players": players.get(key).copied().unwrap_or(0),
+                "draining": instance.draining.contains(key),
+                "health": {
+                    "online": health.online,
+                    "latency_ms": health.latency.map(|latency| latency.as_millis() as u64),
+                    "players": health.players,
+                    "max_players": health.max_players,
+                    "motd": health.motd.map(|motd| motd.to_string()),
+                },
+            })
+        })
+        .collect();
+
+    success("200 OK", Value::Array(servers))
+}
+
+/// Marks the server as draining and moves its players to `to`, a server or
+/// group, or to where new players go.
+async fn drain(instance: &SharedProxyInstance, key: &str, to: Option<&str>) -> Response {
+    let (server, players) = {
+        let mut instance = instance.write().await;
+        let Some(server) = instance.servers.get(key).cloned() else {
+            return failure("404 Not Found", "no such server");
+        };
+
+        instance.draining.insert(key.to_owned());
+        info!("Draining server {}", key);
+
+        let players: Vec<PlayerConnection> = instance.players.values().cloned().collect();
+        (server, players)
+    };
+
+    let mut on_server = Vec::new();
+    for player in players {
+        if player
+            .current_server()
+            .await
+            .is_some_and(|current| Arc::ptr_eq(&current, &server))
+        {
+            let uuid = player.uuid().await.unwrap_or_default();
+            on_server.push((player, uuid));
+        }
+    }
+
+    let counts = players_by_server(instance).await;
+    let mut moves = Vec::new();
+    let mut failed = 0;
+    {
+        let instance = instance.read().await;
+
+        for (player, uuid) in on_server {
+            let target = match to {
+                Some(to) => instance.pick_server(to, uuid, &counts),
+                None => instance.initial_server(uuid, &counts),
+            };
+
+            match target.filter(|(_, target)| !Arc::ptr_eq(target, &server)) {
+                Some((_, target)) => moves.push(task::spawn(async move { player.send_to(&target).await })),
+                None => failed += 1,
+            }
+        }
+    }
+
+    let mut moved = 0;
+    for result in moves {
+        match result.await {
+            Ok(Ok(())) => moved += 1,
+            _ => failed += 1,
+        }
+    }
+
+    success("200 OK", json!({ "moved": moved, "failed": failed }))
+}
+
+fn body<T: DeserializeOwned>(request: &Request) -> Result<T, Response> {
+    serde_json::from_slice(&request.body).map_err(|e| failure("400 Bad Request", &e.to_string()))
+}
+
+fn success(status: &'static str, body: Value) -> Response {
+    Response::new(status, "application/json", body.to_string())
+}
+
+fn failure(status: &'static str, message: &str) -> Response {
+    Response::new(status, "application/json", json!({ "error": message }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::ProxyConfiguration;
+
+    const TOKEN: &str = "secret";
+
+    fn instance() -> SharedProxyInstance {
+        let config: ProxyConfiguration = toml::from_str(
+            r#"
+                proxy_port = 25565
+                address = "127.0.0.1"
+
+                [servers]
+                lobby = { address = "127.0.0.1", port = 25566, name = "Lobby" }
+            "#,
+        )
+        .unwrap();
+        crate::new_instance(config).unwrap()
+    }
+
+    async fn request(authorization: Option<&str>) -> Request {
+        let mut request = "GET /players HTTP/1.1\r\n".to_owned();
+        if let Some(authorization) = authorization {
+            request.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        request.push_str("\r\n");
+
+        http::read_request(&mut request.as_bytes()).await.unwrap().unwrap()
+    }
+
+    /// Sends `request` through `respond` and returns the status line and
+    /// the JSON body of the response.
+    async fn exchange(instance: &SharedProxyInstance, request: &str) -> (String, Value) {
+        let (mut client, server) = duplex(64 * 1024);
+        client.write_all(request.as_bytes()).await.unwrap();
+
+        respond(server, instance, TOKEN).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_owned();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn authorized_request(method_and_path: &str, body: &str) -> String {
+        format!(
+            "{} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+            method_and_path,
+            TOKEN,
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn only_the_token_is_authorized() {
+        assert!(authorized(&request(Some("Bearer secret")).await, TOKEN));
+
+        for authorization in [
+            None,
+            Some("Bearer secreT"),
+            Some("Bearer secre"),
+            Some("Bearer secrets"),
+            Some("Basic secret"),
+            Some("secret"),
+            Some("Bearer "),
+        ] {
+            assert!(!authorized(&request(authorization).await, TOKEN), "{:?}", authorization);
+        }
+
+        // An empty token would otherwise match an empty bearer token.
+        assert!(!authorized(&request(Some("Bearer ")).await, ""));
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_token_are_refused() {
+        let instance = instance();
+
+        let (status, body) = exchange(&instance, "GET /servers HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        assert_eq!(body, json!({ "error": "missing or wrong token" }));
+
+        // Nothing happens before the token is checked.
+        let request = "POST /maintenance HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n";
+        let (status, _) = exchange(&instance, request).await;
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        assert!(!instance.read().await.maintenance);
+    }
+
+    #[tokio::test]
+    async fn requests_are_routed() {
+        let instance = instance();
+
+        let (status, body) = exchange(&instance, &authorized_request("GET /servers", "")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body[0]["key"], "lobby");
+        assert_eq!(body[0]["players"], 0);
+
+        let (status, body) = exchange(&instance, &authorized_request("GET /players", "")).await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, json!([]));
+
+        let (status, body) = exchange(&instance, &authorized_request("POST /maintenance", "")).await;
+        assert_eq!((status.as_str(), body), ("HTTP/1.1 200 OK", json!({ "kicked": 0 })));
+        let (status, _) = exchange(&instance, &authorized_request("POST /maintenance", "")).await;
+        assert_eq!(status, "HTTP/1.1 409 Conflict");
+
+        let (status, _) = exchange(&instance, &authorized_request("POST /players/Notch/kick", "{}")).await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+        let (status, _) = exchange(&instance, &authorized_request("DELETE /nothing", "")).await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[tokio::test]
+    async fn bodies_must_be_json() {
+        let instance = instance();
+
+        for body in ["", "not json", "{\"message\": 1}"] {
+            let (status, body) = exchange(&instance, &authorized_request("POST /broadcast", body)).await;
+            assert_eq!(status, "HTTP/1.1 400 Bad Request");
+            assert!(body["error"].is_string());
+        }
+
+        let request = authorized_request("POST /broadcast", "{\"message\": \"hi\"}");
+        let (status, body) = exchange(&instance, &request).await;
+        assert_eq!((status.as_str(), body), ("HTTP/1.1 200 OK", json!({ "delivered": 0 })));
+    }
+}
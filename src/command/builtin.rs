@@ -0,0 +1,392 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Arc, time::Instant};
+
+use uuid::Uuid;
+
+use crate::{
+    maintenance::set_maintenance,
+    server::{
+        queue::{self, QueuedPlayer},
+        ProxiedServer,
+    },
+    find_player, players_by_server, ProxyInstance, SharedProxyInstance,
+};
+
+use super::{legacy_text, ArgumentKind, Command, CommandContext, CommandError, CommandRegistry, CommandResult, CommandSender};
+
+/// Registers the commands every proxy has.
+pub(super) fn register(registry: &CommandRegistry) {
+    registry.register(
+        Command::new("help", "Lists the commands you can use.", help)
+            .permission("rustyproxy.command.help"),
+    );
+
+    registry.register(
+        Command::new("list", "Lists the players on each server.", |instance, _| list(instance))
+            .alias("glist")
+            .permission("rustyproxy.command.list"),
+    );
+
+    registry.register(
+        Command::new("send", "Moves a player to another server.", send)
+            .usage("<player> <server>")
+            .permission("rustyproxy.command.send")
+            .argument(&[ArgumentKind::Player])
+            .argument(&[ArgumentKind::Server]),
+    );
+
+    registry.register(
+        Command::new("kick", "Disconnects a player from the proxy.", kick)
+            .usage("<player> [reason]")
+            .permission("rustyproxy.command.kick")
+            .argument(&[ArgumentKind::Player]),
+    );
+
+    registry.register(
+        Command::new("alert", "Shows a message to every player.", alert)
+            .usage("<message>")
+            .permission("rustyproxy.command.alert"),
+    );
+
+    registry.register(
+        Command::new("reload", "Reads the configuration file again.", |instance, _| reload(instance))
+            .permission("rustyproxy.command.reload"),
+    );
+
+    registry.register(
+        Command::new("maintenance", "Shows or toggles maintenance, which only lets some players join.", maintenance)
+            .usage("[on | off]")
+            .permission("rustyproxy.command.maintenance")
+            .argument(&[ArgumentKind::Literal(&["on", "off"])]),
+    );
+
+    registry.register(
+        Command::new("queue", "Shows your place in the queue for a full server, or leaves it.", queue)
+            .usage("[leave]")
+            .argument(&[ArgumentKind::Literal(&["leave"])]),
+    );
+
+    // Everyone may switch servers; managing them takes a permission checked
+    // by the command itself.
+    registry.register(
+        Command::new("server", "Shows or switches servers, or adds and removes them.", server)
+            .usage("[<server> | add <key> <address[:port]> [name] | remove <key>]")
+            .argument(&[ArgumentKind::Server, ArgumentKind::Literal(&["add", "remove"])])
+            .argument(&[ArgumentKind::Server]),
+    );
+}
+
+async fn help(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    let commands = Arc::clone(&instance.read().await.commands);
+    let mut lines = Vec::new();
+
+    for command in commands.commands() {
+        if command.allows(&context.sender).await {
+            let usage = match command.usage.as_str() {
+                "" => String::new(),
+                usage => format!(" {}", usage),
+            };
+            lines.push(format!("§e/{}{} §7- {}", command.name, usage, command.description));
+        }
+    }
+
+    Ok(lines.join("\n"))
+}
+
+async fn list(instance: SharedProxyInstance) -> CommandResult {
+    let (players, servers) = {
+        let instance = instance.read().await;
+        let players: Vec<_> = instance.players.values().cloned().collect();
+        let servers: Vec<_> = instance
+            .servers
+            .iter()
+            .map(|(key, server)| (key.clone(), Arc::clone(server)))
+            .collect();
+        (players, servers)
+    };
+
+    let mut by_server: BTreeMap<String, Vec<String>> = servers.iter().map(|(key, _)| (key.clone(), Vec::new())).collect();
+
+    for player in &players {
+        let Some(name) = player.player_info.lock().await.as_ref().map(|info| info.username.clone()) else {
+            continue;
+        };
+
+        let key = match player.current_server().await {
+            Some(current) => servers
+                .iter()
+                .find(|(_, server)| Arc::ptr_eq(server, &current))
+                .map_or_else(|| current.name.clone(), |(key, _)| key.clone()),
+            None if player.in_limbo() => "limbo".to_owned(),
+            None => "none".to_owned(),
+        };
+
+        by_server.entry(key).or_default().push(name);
+    }
+
+    let mut lines = Vec::with_capacity(by_server.len() + 1);
+    for (key, mut names) in by_server {
+        names.sort_by_key(|name| name.to_lowercase());
+        lines.push(format!("§a[{}] §e({}): §f{}", key, names.len(), names.join(", ")));
+    }
+
+    lines.push(format!("§eTotal players online: {}", players.len()));
+    Ok(lines.join("\n"))
+}
+
+async fn send(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    let [player, key] = context.args.as_slice() else {
+        return Err(CommandError::Usage);
+    };
+
+    let player = find_player(&instance, player)
+        .await
+        .ok_or_else(|| CommandError::Failed("That player is not online.".to_owned()))?;
+
+    let uuid = player.uuid().await.unwrap_or_default();
+    let players = players_by_server(&instance).await;
+    let server = resolve(&*instance.read().await, key, uuid, &players)?;
+
+    player
+        .send_to(&server)
+        .await
+        .map_err(|e| CommandError::Failed(format!("Could not send the player: {}", e)))?;
+
+    Ok(format!("§aSent the player to {}.", key))
+}
+
+async fn kick(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    let Some(name) = context.args.first() else {
+        return Err(CommandError::Usage);
+    };
+
+    let mut player = find_player(&instance, name)
+        .await
+        .ok_or_else(|| CommandError::Failed("That player is not online.".to_owned()))?;
+
+    let reason = context.rest(1).unwrap_or_else(|| "You were kicked from the proxy.".to_owned());
+    player
+        .kick(legacy_text(&reason))
+        .await
+        .map_err(|e| CommandError::Failed(format!("Could not kick the player: {}", e)))?;
+
+    Ok(format!("§aKicked {}.", name))
+}
+
+async fn alert(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    let Some(message) = context.rest(0) else {
+        return Err(CommandError::Usage);
+    };
+
+    let players: Vec<_> = instance.read().await.players.values().cloned().collect();
+    let mut delivered = 0;
+
+    for player in &players {
+        if player.send_message(legacy_text(&format!("§c[Alert] §f{}", message))).await.is_ok() {
+            delivered += 1;
+        }
+    }
+
+    Ok(format!("§aAlert shown to {} players.", delivered))
+}
+
+async fn reload(instance: SharedProxyInstance) -> CommandResult {
+    crate::reload(&instance)
+        .await
+        .map_err(|e| CommandError::Failed(format!("Could not reload the configuration: {}", e)))?;
+
+    Ok("§aReloaded the configuration.".to_owned())
+}
+
+async fn maintenance(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    let enabled = match context.args.first().map(String::as_str) {
+        None if instance.read().await.maintenance => return Ok("§eMaintenance is on.".to_owned()),
+        None => return Ok("§eMaintenance is off.".to_owned()),
+        Some("on") => true,
+        Some("off") => false,
+        Some(_) => return Err(CommandError::Usage),
+    };
+
+    match (enabled, set_maintenance(&instance, enabled).await.unwrap_or(0)) {
+        (false, _) => Ok("§aMaintenance is off.".to_owned()),
+        (true, 0) => Ok("§aMaintenance is on.".to_owned()),
+        (true, kicked) => Ok(format!("§aMaintenance is on; kicked {} players.", kicked)),
+    }
+}
+
+async fn server(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    match context.args.first().map(String::as_str) {
+        None => current_server(&instance, &context.sender).await,
+        Some("add") | Some("remove") => {
+            if !context.sender.has_permission("rustyproxy.command.server.manage").await {
+                return Err(CommandError::Failed("You may not manage servers.".to_owned()));
+            }
+
+            manage_servers(&instance, &context).await
+        }
+        Some(key) if context.args.len() == 1 => switch_server(&instance, &context.sender, key).await,
+        Some(_) => Err(CommandError::Usage),
+    }
+}
+
+async fn current_server(instance: &SharedProxyInstance, sender: &CommandSender) -> CommandResult {
+    let current = match sender {
+        CommandSender::Player(player) => player.current_server().await,
+        CommandSender::Console => None,
+    };
+
+    let instance = instance.read().await;
+    let mut keys: Vec<_> = instance.servers.keys().cloned().collect();
+    keys.sort();
+
+    let mut lines = Vec::new();
+    if let Some(current) = current {
+        let key = instance.server_key(&current).unwrap_or(&current.name);
+        lines.push(format!("§eYou are connected to {}.", key));
+    }
+
+    lines.push(format!("§eServers: §f{}", keys.join(", ")));
+
+    if let Some(groups) = &instance.config.groups {
+        let mut names: Vec<_> = groups.keys().cloned().collect();
+        names.sort();
+        lines.push(format!("§eGroups: §f{}", names.join(", ")));
+    }
+    Ok(lines.join("\n"))
+}
+
+async fn switch_server(instance: &SharedProxyInstance, sender: &CommandSender, key: &str) -> CommandResult {
+    let CommandSender::Player(player) = sender else {
+        return Err(CommandError::Failed("Only players can switch servers.".to_owned()));
+    };
+
+    let current = player.current_server().await;
+    let uuid = player.uuid().await.unwrap_or_default();
+    let players = players_by_server(instance).await;
+
+    let server = {
+        let instance = instance.read().await;
+
+        // Moving between the servers of a group would only shuffle the
+        // player around.
+        if let Some(group) = instance.group(key) {
+            let current_key = current.as_ref().and_then(|current| instance.server_key(current));
+            if current_key.is_some_and(|current_key| group.servers.iter().any(|member| member == current_key)) {
+                return Err(CommandError::Failed(format!("You are already connected to {}.", key)));
+            }
+        }
+
+        if instance.draining.contains(key) {
+            return Err(CommandError::Failed(format!("{} is not accepting players.", key)));
+        }
+
+        let on_server = instance.servers.get(key).zip(current.as_ref());
+        if on_server.is_some_and(|(server, current)| Arc::ptr_eq(server, current)) {
+            return Err(CommandError::Failed(format!("You are already connected to {}.", key)));
+        }
+
+        if let Some((target, position)) = instance.queues.position(uuid).filter(|(target, _)| *target == key) {
+            return Ok(format!("§eYou are #{} in the queue for {}.", position, target));
+        }
+
+        // Nobody skips the queue, even when a slot is free right now.
+        if instance.queues.len(key) > 0 || instance.is_full(key, &players) {
+            None
+        } else {
+            Some(resolve(&instance, key, uuid, &players)?)
+        }
+    };
+
+    let Some(server) = server else {
+        let priority = queue::priority(player, key).await;
+        let position = instance.write().await.queues.join(
+            key,
+            QueuedPlayer {
+                uuid,
+                priority,
+                since: Instant::now(),
+            },
+        );
+
+        return Ok(format!("§e{} is full. You are #{} in the queue.", key, position));
+    };
+
+    if !server.is_online() {
+        return Err(CommandError::Failed(format!("{} is offline.", key)));
+    }
+
+    if current.is_some_and(|current| Arc::ptr_eq(&current, &server)) {
+        return Err(CommandError::Failed(format!("You are already connected to {}.", key)));
+    }
+
+    player
+        .send_to(&server)
+        .await
+        .map_err(|e| CommandError::Failed(format!("Could not connect to {}: {}", key, e)))?;
+
+    instance.write().await.queues.leave(uuid);
+    Ok(String::new())
+}
+
+async fn queue(instance: SharedProxyInstance, context: CommandContext) -> CommandResult {
+    let CommandSender::Player(player) = &context.sender else {
+        return Err(CommandError::Failed("Only players can wait in a queue.".to_owned()));
+    };
+    let uuid = player.uuid().await.unwrap_or_default();
+
+    match context.args.first().map(String::as_str) {
+        None => match instance.read().await.queues.position(uuid) {
+            Some((target, position)) => Ok(format!("§eYou are #{} in the queue for {}.", position, target)),
+            None => Ok("§eYou are not in a queue.".to_owned()),
+        },
+        Some("leave") => match instance.write().await.queues.leave(uuid) {
+            Some(target) => Ok(format!("§aYou left the queue for {}.", target)),
+            None => Err(CommandError::Failed("You are not in a queue.".to_owned())),
+        },
+        Some(_) => Err(CommandError::Usage),
+    }
+}
+
+/// The server `target` names for the player `uuid`, telling an unknown
+/// name apart from a group without an available server.
+fn resolve(
+    instance: &ProxyInstance,
+    target: &str,
+    uuid: Uuid,
+    players: &HashMap<String, usize>,
+) -> Result<Arc<ProxiedServer>, CommandError> {
+    match instance.pick_server(target, uuid, players) {
+        Some((_, server)) => Ok(server),
+        None if instance.group(target).is_some() => {
+            Err(CommandError::Failed(format!("No server of {} is available.", target)))
+        }
+        None => Err(CommandError::Failed(format!("There is no server called {}.", target))),
+    }
+}
+
+async fn manage_servers(instance: &SharedProxyInstance, context: &CommandContext) -> CommandResult {
+    match context.args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["add", key, address, ..] => {
+            let (host, port) = match address.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    port.parse()
+                        .map_err(|_| CommandError::Failed(format!("{} is not a valid port.", port)))?,
+                ),
+                None => (*address, 25565),
+            };
+            let name = context.rest(3).unwrap_or_else(|| (*key).to_owned());
+
+            instance
+                .write()
+                .await
+                .add_server((*key).to_owned(), ProxiedServer::new(name, host.to_owned(), port));
+
+            Ok(format!("§aAdded {} at {}:{}.", key, host, port))
+        }
+        ["remove", key] => match instance.write().await.remove_server(key) {
+            Some(_) => Ok(format!("§aRemoved {}.", key)),
+            None => Err(CommandError::Failed(format!("There is no server called {}.", key))),
+        },
+        _ => Err(CommandError::Usage),
+    }
+}
@@ -0,0 +1,316 @@
+mod builtin;
+pub mod permission;
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock as StdRwLock},
+};
+
+use azalea_chat::{text_component::TextComponent, FormattedText};
+
+use crate::{player::PlayerConnection, SharedProxyInstance};
+
+/// What a command prints, or why it did not run.
+pub type CommandResult = Result<String, CommandError>;
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// No command by that name, or none the sender may use.
+    Unknown(String),
+    /// The arguments did not fit; the usage is shown instead.
+    Usage,
+    /// The command ran into a problem, described for the sender.
+    Failed(String),
+}
+
+/// Who runs a command.
+#[derive(Clone)]
+pub enum CommandSender {
+    /// The operator console or a remote console, with every permission.
+    Console,
+    Player(PlayerConnection),
+}
+
+impl CommandSender {
+    pub async fn has_permission(&self, permission: &str) -> bool {
+        match self {
+            CommandSender::Console => true,
+            CommandSender::Player(player) => player.has_permission(permission).await,
+        }
+    }
+
+    /// The name shown when the sender is mentioned, e.g. in logs.
+    pub async fn name(&self) -> String {
+        match self {
+            CommandSender::Console => "Console".to_owned(),
+            CommandSender::Player(player) => player
+                .player_info
+                .lock()
+                .await
+                .as_ref()
+                .map(|info| info.username.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// What may be typed at an argument's position, used for completion.
+#[derive(Clone, Copy)]
+pub enum ArgumentKind {
+    /// The name of an online player.
+    Player,
+    /// The key of a registered server, or the name of a group.
+    Server,
+    /// One of a fixed set of words.
+    Literal(&'static [&'static str]),
+}
+
+/// The arguments a command was run with.
+pub struct CommandContext {
+    pub sender: CommandSender,
+    pub args: Vec<String>,
+}
+
+impl CommandContext {
+    /// The arguments from `index` on, joined back together, e.g. a reason.
+    pub fn rest(&self, index: usize) -> Option<String> {
+        (index < self.args.len()).then(|| self.args[index..].join(" "))
+    }
+}
+
+type Handler = Box<
+    dyn Fn(SharedProxyInstance, CommandContext) -> Pin<Box<dyn Future<Output = CommandResult> + Send>>
+        + Send
+        + Sync,
+>;
+
+pub struct Command {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    /// Shown after the name when the arguments do not fit.
+    pub usage: String,
+    /// Needed to run the command; everyone may when `None`.
+    pub permission: Option<String>,
+    /// What each argument can be, by position, for completion.
+    pub arguments: Vec<&'static [ArgumentKind]>,
+    handler: Handler,
+}
+
+impl Command {
+    pub fn new<F, Fut>(name: &str, description: &str, handler: F) -> Command
+    where
+        F: Fn(SharedProxyInstance, CommandContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CommandResult> + Send + 'static,
+    {
+        Command {
+            name: name.to_owned(),
+            aliases: Vec::new(),
+            description: description.to_owned(),
+            usage: String::new(),
+            permission: None,
+            arguments: Vec::new(),
+            handler: Box::new(move |instance, context| Box::pin(handler(instance, context))),
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Command {
+        self.aliases.push(alias.to_owned());
+        self
+    }
+
+    pub fn usage(mut self, usage: &str) -> Command {
+        self.usage = usage.to_owned();
+        self
+    }
+
+    pub fn permission(mut self, permission: &str) -> Command {
+        self.permission = Some(permission.to_owned());
+        self
+    }
+
+    pub fn argument(mut self, kinds: &'static [ArgumentKind]) -> Command {
+        self.arguments.push(kinds);
+        self
+    }
+
+    pub async fn allows(&self, sender: &CommandSender) -> bool {
+        match &self.permission {
+            Some(permission) => sender.has_permission(permission).await,
+            None => true,
+        }
+    }
+}
+
+/// The commands of the proxy, shared by players, the console and remote
+/// consoles. Players run them by typing `/name`; commands they are not
+/// allowed to run are passed on to their server instead.
+pub struct CommandRegistry {
+    /// Commands by name and by alias.
+    commands: StdRwLock<BTreeMap<String, Arc<Command>>>,
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        let registry = CommandRegistry {
+            commands: StdRwLock::new(BTreeMap::new()),
+        };
+
+        builtin::register(&registry);
+        registry
+    }
+}
+
+impl CommandRegistry {
+    /// Adds `command`, replacing any command with the same name or alias.
+    pub fn register(&self, command: Command) {
+        let command = Arc::new(command);
+        let mut commands = self.commands.write().unwrap();
+
+        for label in std::iter::once(&command.name).chain(&command.aliases) {
+            commands.insert(label.to_lowercase(), Arc::clone(&command));
+        }
+    }
+
+    pub fn unregister(&self, name: &str) {
+        self.commands
+            .write()
+            .unwrap()
+            .retain(|_, command| !command.name.eq_ignore_ascii_case(name));
+    }
+
+    pub fn get(&self, label: &str) -> Option<Arc<Command>> {
+        self.commands.read().unwrap().get(&label.to_lowercase()).cloned()
+    }
+
+    /// Every command once, by name.
+    pub fn commands(&self) -> Vec<Arc<Command>> {
+        let commands = self.commands.read().unwrap();
+        commands
+            .iter()
+            .filter(|(label, command)| **label == command.name.to_lowercase())
+            .map(|(_, command)| Arc::clone(command))
+            .collect()
+    }
+
+    /// The command `line` runs, if `sender` may run it.
+    pub async fn find(&self, sender: &CommandSender, line: &str) -> Option<Arc<Command>> {
+        let label = line.split_whitespace().next()?;
+        let command = self.get(label)?;
+
+        command.allows(sender).await.then_some(command)
+    }
+
+    /// Runs `line`, e.g. `kick Notch Bye`, as `sender`.
+    pub async fn execute(&self, instance: &SharedProxyInstance, sender: CommandSender, line: &str) -> CommandResult {
+        let line = line.trim().trim_start_matches('/');
+        let label = line.split_whitespace().next().unwrap_or_default().to_owned();
+
+        let Some(command) = self.find(&sender, line).await else {
+            return Err(CommandError::Unknown(label));
+        };
+
+        let context = CommandContext {
+            sender,
+            args: line.split_whitespace().skip(1).map(str::to_owned).collect(),
+        };
+
+        (command.handler)(Arc::clone(instance), context).await
+    }
+
+    /// Runs `line` and describes the outcome as lines of text, errors
+    /// included, in the legacy `§` format.
+    pub async fn execute_to_text(&self, instance: &SharedProxyInstance, sender: CommandSender, line: &str) -> String {
+        match self.execute(instance, sender, line).await {
+            Ok(output) => output,
+            Err(CommandError::Unknown(label)) => format!("§cUnknown command: {}", label),
+            Err(CommandError::Usage) => {
+                let label = line.trim().trim_start_matches('/').split_whitespace().next().unwrap_or_default();
+                match self.get(label) {
+                    Some(command) => format!("§cUsage: /{} {}", command.name, command.usage),
+                    None => format!("§cUnknown command: {}", label),
+                }
+            }
+            Err(CommandError::Failed(message)) => format!("§c{}", message),
+        }
+    }
+
+    /// Completions for the last word of `line` that `sender` may use.
+    pub async fn complete(&self, instance: &SharedProxyInstance, sender: &CommandSender, line: &str) -> Vec<String> {
+        let line = line.trim_start_matches('/');
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        if line.is_empty() || line.ends_with(' ') {
+            words.push("");
+        }
+
+        let Some((partial, previous)) = words.split_last() else {
+            return Vec::new();
+        };
+        let partial = partial.to_lowercase();
+
+        let mut candidates = Vec::new();
+
+        if previous.is_empty() {
+            for command in self.commands() {
+                if command.allows(sender).await {
+                    candidates.push(command.name.clone());
+                }
+            }
+        } else if let Some(command) = self.find(sender, previous[0]).await {
+            let kinds = command.arguments.get(previous.len() - 1).copied().unwrap_or_default();
+
+            for kind in kinds {
+                match kind {
+                    ArgumentKind::Player => candidates.extend(player_names(instance).await),
+                    ArgumentKind::Server => {
+                        let instance = instance.read().await;
+                        candidates.extend(instance.servers.keys().cloned());
+                        candidates.extend(instance.config.groups.iter().flat_map(|groups| groups.keys().cloned()));
+                    }
+                    ArgumentKind::Literal(words) => candidates.extend(words.iter().map(|word| (*word).to_owned())),
+                }
+            }
+        }
+
+        candidates.retain(|candidate| candidate.to_lowercase().starts_with(&partial));
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+async fn player_names(instance: &SharedProxyInstance) -> Vec<String> {
+    let players: Vec<PlayerConnection> = instance.read().await.players.values().cloned().collect();
+    let mut names = Vec::with_capacity(players.len());
+
+    for player in players {
+        if let Some(info) = player.player_info.lock().await.as_ref() {
+            names.push(info.username.clone());
+        }
+    }
+
+    names
+}
+
+/// Text in the legacy `§` format as chat text.
+pub fn legacy_text(text: &str) -> FormattedText {
+    FormattedText::Text(TextComponent::new(text.to_owned()))
+}
+
+/// Removes `§` formatting codes, for output that is not shown in game.
+pub fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
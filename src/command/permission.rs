@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::{event::PermissionChecked, player::PlayerConnection, ProxyInstance};
+
+/// Whether `granted`, as written in the configuration, covers `permission`.
+/// `*` grants everything and `a.b.*` everything below `a.b`.
+pub fn permission_matches(granted: &str, permission: &str) -> bool {
+    if granted == "*" || granted == permission {
+        return true;
+    }
+
+    granted
+        .strip_suffix(".*")
+        .is_some_and(|prefix| permission.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('.')))
+}
+
+impl ProxyInstance {
+    /// Whether `[permissions]` grants `permission` to the player called
+    /// `name` or with `uuid`.
+    pub fn grants_permission(&self, name: &str, uuid: &str, permission: &str) -> bool {
+        let Some(permissions) = &self.config.permissions else {
+            return false;
+        };
+
+        permissions
+            .iter()
+            .filter(|(player, _)| player.eq_ignore_ascii_case(name) || player.eq_ignore_ascii_case(uuid))
+            .flat_map(|(_, granted)| granted)
+            .any(|granted| permission_matches(granted, permission))
+    }
+}
+
+impl PlayerConnection {
+    /// Whether the player has `permission`, as configured under
+    /// `[permissions]` and then decided by `PermissionChecked` listeners.
+    pub async fn has_permission(&self, permission: &str) -> bool {
+        // Copied out first, as holding the player's lock while waiting for
+        // the instance's can deadlock with code that does the opposite.
+        let info = self.player_info.lock().await.clone();
+        let granted = match info {
+            Some(info) => self.proxy_instance.read().await.grants_permission(
+                &info.username,
+                &info.uuid.to_string(),
+                permission,
+            ),
+            None => false,
+        };
+
+        let event = Arc::new(PermissionChecked {
+            connection: Arc::new(Mutex::new(self.clone())),
+            permission: permission.to_owned(),
+            granted: Arc::new(Mutex::new(granted)),
+        });
+
+        self.event_bus().dispatch(&event).await;
+
+        let granted = *event.granted.lock().await;
+        granted
+    }
+}
@@ -0,0 +1,109 @@
+//! The operator console, read from stdin when it is a terminal. Commands run
+//! with every permission, and log lines are printed above the prompt.
+
+use std::{
+    io::{self, IsTerminal},
+    thread,
+};
+
+use rustyline::{
+    completion::Completer, error::ReadlineError, history::DefaultHistory, Config, Context, Editor,
+    ExternalPrinter, Helper, Highlighter, Hinter, Validator,
+};
+use tokio::runtime::Handle;
+use tracing::error;
+
+use crate::{
+    command::{legacy_text, strip_formatting, CommandSender},
+    logging, SharedProxyInstance, SHUTDOWN_MESSAGE,
+};
+
+const PROMPT: &str = "> ";
+const MAX_HISTORY: usize = 1000;
+
+/// Completes command names and arguments from the command registry.
+#[derive(Helper, Highlighter, Hinter, Validator)]
+struct ConsoleHelper {
+    instance: SharedProxyInstance,
+    runtime: Handle,
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+
+        let candidates = self.runtime.block_on(async {
+            let commands = self.instance.read().await.commands.clone();
+            commands.complete(&self.instance, &CommandSender::Console, line).await
+        });
+
+        Ok((start, candidates))
+    }
+}
+
+/// Reads commands from stdin on a thread of its own. Does nothing unless
+/// stdin is a terminal, so the proxy can still run detached or piped.
+pub fn spawn(instance: &SharedProxyInstance) {
+    if !io::stdin().is_terminal() {
+        return;
+    }
+
+    let config = Config::builder()
+        .max_history_size(MAX_HISTORY)
+        .and_then(|builder| builder.history_ignore_dups(true))
+        .map(|builder| builder.auto_add_history(true).build());
+
+    let editor = config.and_then(Editor::<ConsoleHelper, DefaultHistory>::with_config);
+    let mut editor = match editor {
+        Ok(editor) => editor,
+        Err(e) => {
+            error!("Failed to start the console: {}", e);
+            return;
+        }
+    };
+
+    let runtime = Handle::current();
+    editor.set_helper(Some(ConsoleHelper {
+        instance: instance.clone(),
+        runtime: runtime.clone(),
+    }));
+
+    match editor.create_external_printer() {
+        Ok(mut printer) => logging::redirect(Some(Box::new(move |line| {
+            let _ = printer.print(line);
+        }))),
+        Err(e) => error!("Failed to print logs above the console: {}", e),
+    }
+
+    let instance = instance.clone();
+    thread::spawn(move || {
+        loop {
+            match editor.readline(PROMPT) {
+                Ok(line) if line.trim().is_empty() => {}
+                Ok(line) => {
+                    let output = runtime.block_on(async {
+                        let commands = instance.read().await.commands.clone();
+                        commands.execute_to_text(&instance, CommandSender::Console, &line).await
+                    });
+
+                    for line in output.lines().filter(|line| !line.is_empty()) {
+                        println!("{}", strip_formatting(line));
+                    }
+                }
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                Err(e) => {
+                    error!("Failed to read from the console: {}", e);
+                    break;
+                }
+            }
+        }
+
+        logging::redirect(None);
+        runtime.block_on(async {
+            instance.read().await.shutdown(legacy_text(SHUTDOWN_MESSAGE));
+        });
+    });
+}
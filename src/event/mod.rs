@@ -1,10 +1,12 @@
-use crate::{packet::RawPacket, player::{PlayerConnection, PlayerProxyConnection}, server::ProxiedServer, ProxyInstance, SharedProxyInstance};
+use crate::{metrics::Histogram, packet::{handshake::HandshakePacket, registry::PacketKind, status::ServerStatus, RawPacket}, player::{stats::StatsSnapshot, PlayerConnection, PlayerProxyConnection}, server::ProxiedServer, ProxyInstance, SharedProxyInstance};
+use azalea_chat::FormattedText;
 use std::{
     any::{Any, TypeId},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock},
+    time::{Duration, Instant},
 };
 use tokio::sync::{Mutex, RwLock};
 
@@ -38,21 +40,98 @@ pub struct EventBus {
         >,
     >,
 
+    packet_interest: StdRwLock<PacketInterest>,
+    /// How long dispatching took, by event type name.
+    dispatch_latency: StdMutex<BTreeMap<&'static str, Histogram>>,
+
     instance: SharedProxyInstance,
 }
 
+/// The packets `ServerSentPacket` listeners want to see. Packets nobody
+/// listens for are forwarded in the Play state without being decoded.
+#[derive(Default)]
+struct PacketInterest {
+    all: bool,
+    kinds: HashSet<PacketKind>,
+    /// Listeners that see every packet, but only while these say so.
+    while_wanted: Vec<Box<dyn Fn() -> bool + Send + Sync>>,
+}
+
 impl EventBus {
     pub fn new(instance: &SharedProxyInstance) -> Arc<Self> {
         Arc::new(Self {
             listeners: RwLock::new(HashMap::new()),
+            packet_interest: StdRwLock::new(PacketInterest::default()),
+            dispatch_latency: StdMutex::new(BTreeMap::new()),
             instance: instance.clone(),
         })
     }
 
     /// **Register an async event listener with a `lazy` flag**
+    ///
+    /// A `ServerSentPacket` listener registered here sees every packet, which
+    /// turns off the Play state fast path; prefer `listen_packet`.
     pub async fn listen<E,R, F, Fut>(&self, lazy: bool, callback: F)
     where
     E: Event<R>,
+    R: Clone + Send + Sync + 'static,
+        F: Fn(SharedProxyInstance, Arc<E>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<R>> + Send + 'static,
+    {
+        if TypeId::of::<E>() == TypeId::of::<ServerSentPacket>() {
+            self.packet_interest.write().unwrap().all = true;
+        }
+
+        self.register(lazy, callback).await;
+    }
+
+    /// **Register a `ServerSentPacket` listener for a single kind of packet**
+    pub async fn listen_packet<F, Fut>(&self, kind: PacketKind, lazy: bool, callback: F)
+    where
+        F: Fn(SharedProxyInstance, Arc<ServerSentPacket>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<EventResult>> + Send + 'static,
+    {
+        self.packet_interest.write().unwrap().kinds.insert(kind);
+
+        let callback = Arc::new(callback);
+        self.register::<ServerSentPacket, EventResult, _, _>(lazy, move |instance, event| {
+            let callback = Arc::clone(&callback);
+            async move {
+                if event.kind == Some(kind) {
+                    callback(instance, event).await
+                } else {
+                    None
+                }
+            }
+        })
+        .await;
+    }
+
+    /// **Register a `ServerSentPacket` listener for every packet while `wanted` returns true**
+    ///
+    /// `wanted` is asked before each packet in the Play state, so it has to
+    /// be cheap. While it returns false, the fast path stays on.
+    pub async fn listen_packets_while<W, F, Fut>(&self, wanted: W, lazy: bool, callback: F)
+    where
+        W: Fn() -> bool + Send + Sync + 'static,
+        F: Fn(SharedProxyInstance, Arc<ServerSentPacket>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<EventResult>> + Send + 'static,
+    {
+        self.packet_interest.write().unwrap().while_wanted.push(Box::new(wanted));
+        self.register::<ServerSentPacket, EventResult, _, _>(lazy, callback).await;
+    }
+
+    /// Whether any `ServerSentPacket` listener wants to see a packet of `kind`.
+    pub fn wants_packet(&self, kind: Option<PacketKind>) -> bool {
+        let interest = self.packet_interest.read().unwrap();
+        interest.all
+            || kind.is_some_and(|kind| interest.kinds.contains(&kind))
+            || interest.while_wanted.iter().any(|wanted| wanted())
+    }
+
+    async fn register<E,R, F, Fut>(&self, lazy: bool, callback: F)
+    where
+    E: Event<R>,
     R: Clone + Send + Sync + 'static,
         F: Fn(SharedProxyInstance, Arc<E>) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = Option<R>> + Send + 'static,
@@ -84,6 +163,7 @@ impl EventBus {
 
     /// **Dispatch an event, awaiting non-lazy listeners**
     pub async fn dispatch<E: Event<R>,R: Clone + Send + Sync + 'static,>(&self, event: &Arc<E>) -> Option<R> {
+        let started = Instant::now();
         let listeners = self.listeners.read().await;
         let event: Arc<RwLock<Box<dyn Any + Send + Sync>>> = Arc::new(RwLock::new(Box::new(event.clone())));
     
@@ -108,9 +188,22 @@ impl EventBus {
                 }
             }
         }
+
+        let name = std::any::type_name::<E>().rsplit("::").next().unwrap_or_default();
+        self.dispatch_latency
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .observe(started.elapsed());
     
         last_result
     }
+
+    /// How long dispatching took so far, by event type name.
+    pub fn dispatch_latencies(&self) -> BTreeMap<&'static str, Histogram> {
+        self.dispatch_latency.lock().unwrap().clone()
+    }
 }
 
 pub trait PlayerEvent: Event<EventResult> {
@@ -122,6 +215,14 @@ pub trait PlayerEvent: Event<EventResult> {
 pub struct ProxyFinishedInitialization;
 impl Event<EventResult> for ProxyFinishedInitialization {}
 
+/// Fired once the proxy stopped accepting connections while shutting down,
+/// before its players are disconnected with `reason`.
+#[derive(Clone)]
+pub struct ProxyShutdown {
+    pub reason: FormattedText,
+}
+impl Event<NoopEventResult> for ProxyShutdown {}
+
 #[derive(Clone)]
 pub struct PlayerJoinedProxy {
     pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
@@ -146,7 +247,87 @@ impl Event<NoopEventResult> for PlayerLeftProxy {}
 #[derive(Clone)]
 pub struct ServerSentPacket {
     pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
-    pub packet: RawPacket
+    pub packet: RawPacket,
+    /// What the packet registry identified the packet as, if anything.
+    pub kind: Option<PacketKind>,
+}
+
+impl Event<EventResult> for ServerSentPacket {}
+
+/// Fired when a health check cannot reach a server that was online.
+#[derive(Clone)]
+pub struct ServerWentDown {
+    pub key: String,
+    pub server: Arc<ProxiedServer>,
+}
+
+impl Event<NoopEventResult> for ServerWentDown {}
+
+/// Fired when a server that went down answers a health check again.
+#[derive(Clone)]
+pub struct ServerCameUp {
+    pub key: String,
+    pub server: Arc<ProxiedServer>,
+}
+
+impl Event<NoopEventResult> for ServerCameUp {}
+
+/// Fired when a player joins the queue of a full server or group, with the
+/// priority its permissions give. Listeners may overwrite `priority`;
+/// players with a higher one are let in first.
+#[derive(Clone)]
+pub struct QueuePriorityChecked {
+    pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
+    /// The server key or group name the player waits for.
+    pub target: String,
+    pub priority: Arc<tokio::sync::Mutex<i32>>,
+}
+
+impl Event<NoopEventResult> for QueuePriorityChecked {}
+
+/// Fired when a client requests the server list status. Listeners may edit
+/// `status` before it is sent; returning `Stop` closes the connection without
+/// answering.
+#[derive(Clone)]
+pub struct ProxyPinged {
+    pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
+    pub handshake: HandshakePacket,
+    pub status: Arc<tokio::sync::Mutex<ServerStatus>>,
+}
+
+impl Event<EventResult> for ProxyPinged {}
+
+/// Fired after the handshake with whether the client's protocol version is
+/// within the supported range. Listeners may overwrite `compatible` to let a
+/// client in or turn it away; accepting a version the packet registry does
+/// not know is at the listener's own risk.
+#[derive(Clone)]
+pub struct ProtocolVersionChecked {
+    pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
+    pub handshake: HandshakePacket,
+    pub compatible: Arc<tokio::sync::Mutex<bool>>,
+}
+
+impl Event<NoopEventResult> for ProtocolVersionChecked {}
+
+/// Fired whenever a player answers a keep-alive from its server, with the
+/// round trip it took and the connection's traffic counters at that time.
+#[derive(Clone)]
+pub struct PlayerLatencyMeasured {
+    pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
+    pub round_trip: Duration,
+    pub stats: StatsSnapshot,
+}
+
+impl Event<NoopEventResult> for PlayerLatencyMeasured {}
+
+/// Fired when a player's permission is checked, with whether the
+/// configuration grants it. Listeners may overwrite `granted`.
+#[derive(Clone)]
+pub struct PermissionChecked {
+    pub connection: Arc<tokio::sync::Mutex<PlayerConnection>>,
+    pub permission: String,
+    pub granted: Arc<tokio::sync::Mutex<bool>>,
 }
 
-impl Event<EventResult> for ServerSentPacket {}
\ No newline at end of file
+impl Event<NoopEventResult> for PermissionChecked {}
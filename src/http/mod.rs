@@ -0,0 +1,234 @@
+//! Just enough HTTP/1.1 for the metrics and admin endpoints: one request per
+//! connection, closed after the response.
+
+use std::{io::Error, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time,
+};
+
+/// How long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEAD_LENGTH: usize = 8192;
+const MAX_BODY_LENGTH: usize = 65536;
+
+pub(crate) struct Request {
+    pub method: String,
+    /// The path without its query string.
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The path split on `/`, without empty segments.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|segment| !segment.is_empty()).collect()
+    }
+}
+
+pub(crate) struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: &'static str, content_type: &'static str, body: String) -> Response {
+        Response {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found() -> Response {
+        Response::new("404 Not Found", "text/plain; charset=utf-8", "Not Found\n".to_owned())
+    }
+}
+
+/// Reads a request, or `None` if the client sent something this parser does
+/// not understand or took too long.
+pub(crate) async fn read_request(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<Request>, Error> {
+    let mut received = Vec::with_capacity(1024);
+
+    let read = time::timeout(READ_TIMEOUT, async {
+        let mut buffer = [0u8; 1024];
+
+        let head_length = loop {
+            if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                break Some(end + 4);
+            }
+
+            if received.len() >= MAX_HEAD_LENGTH {
+                break None;
+            }
+
+            match stream.read(&mut buffer).await? {
+                0 => break None,
+                n => received.extend_from_slice(&buffer[..n]),
+            }
+        };
+
+        let Some(head_length) = head_length else {
+            return Ok(None);
+        };
+
+        let Some(mut request) = parse_head(&received[..head_length]) else {
+            return Ok(None);
+        };
+
+        let content_length = match request.header("Content-Length") {
+            Some(length) => match length.trim().parse::<usize>() {
+                Ok(length) if length <= MAX_BODY_LENGTH => length,
+                _ => return Ok(None),
+            },
+            None => 0,
+        };
+
+        let mut body = received.split_off(head_length);
+        while body.len() < content_length {
+            match stream.read(&mut buffer).await? {
+                0 => return Ok(None),
+                n => body.extend_from_slice(&buffer[..n]),
+            }
+        }
+
+        body.truncate(content_length);
+        request.body = body;
+        Ok::<_, Error>(Some(request))
+    })
+    .await;
+
+    match read {
+        Ok(request) => request,
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_head(head: &[u8]) -> Option<Request> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_owned();
+    let path = request_line.next()?.split('?').next().unwrap_or_default().to_owned();
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+        .collect();
+
+    Some(Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    })
+}
+
+pub(crate) async fn write_response(stream: &mut (impl AsyncWrite + Unpin), response: Response) -> Result<(), Error> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(request: &[u8]) -> Option<Request> {
+        read_request(&mut &request[..]).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_are_parsed() {
+        let request = read(
+            b"POST /players/Notch/kick?now=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\nX-Empty:\r\n\r\nhello",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/players/Notch/kick");
+        assert_eq!(request.segments(), ["players", "Notch", "kick"]);
+        assert_eq!(request.header("Content-Length"), Some("5"));
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(request.header("X-Empty"), Some(""));
+        assert_eq!(request.header("Authorization"), None);
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn bodies_end_at_their_length() {
+        let request = read(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhello").await.unwrap();
+        assert_eq!(request.body, b"he");
+        assert!(request.segments().is_empty());
+
+        let request = read(b"GET / HTTP/1.1\r\n\r\nignored").await.unwrap();
+        assert!(request.body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_are_refused() {
+        for request in [
+            &b""[..],
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n",
+            b"GET\r\n\r\n",
+            b"\r\n\r\n",
+            b"GET /\xFF HTTP/1.1\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: many\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            // The client hung up before sending all of the body
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello",
+        ] {
+            assert!(read(request).await.is_none(), "{:?}", String::from_utf8_lossy(request));
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_are_bounded() {
+        let mut long_head = b"GET / HTTP/1.1\r\nX-Padding: ".to_vec();
+        long_head.resize(MAX_HEAD_LENGTH + 1024, b'a');
+        long_head.extend_from_slice(b"\r\n\r\n");
+        assert!(read(&long_head).await.is_none());
+
+        let body = vec![b'a'; MAX_BODY_LENGTH + 1];
+        let mut request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        request.extend_from_slice(&body);
+        assert!(read(&request).await.is_none());
+
+        let mut request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LENGTH).into_bytes();
+        request.extend_from_slice(&body[1..]);
+        assert_eq!(read(&request).await.unwrap().body.len(), MAX_BODY_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn responses_carry_their_length() {
+        let mut written = Vec::new();
+        write_response(&mut written, Response::new("201 Created", "application/json", "{\"a\":\"é\"}".to_owned()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nContent-Length: 10\r\nConnection: close\r\n\r\n{\"a\":\"é\"}"
+        );
+    }
+}
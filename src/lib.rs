@@ -1,42 +1,515 @@
+extern crate self as rustyproxy;
+
+pub mod api;
+pub mod command;
+pub mod console;
 pub mod event;
+mod http;
+pub mod logging;
+pub mod maintenance;
+pub mod metrics;
 pub mod packet;
 pub mod player;
+pub mod query;
+pub mod rcon;
+#[cfg(feature = "lua")]
+pub mod scripting;
 pub mod server;
+pub mod upgrade;
 
-use std::{collections::HashMap, error::Error, fs, future::Future, pin::Pin, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    future::Future,
+    io::ErrorKind,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use azalea_chat::{text_component::TextComponent, FormattedText};
-use event::{EventBus, EventResult, PlayerJoinedProxy, ProxyFinishedInitialization};
+use azalea_chat::{
+    text_component::TextComponent,
+    translatable_component::{StringOrComponent, TranslatableComponent},
+    FormattedText,
+};
+use api::ApiConfiguration;
+use command::CommandRegistry;
+use logging::LoggingConfiguration;
+use maintenance::{set_maintenance, MaintenanceConfiguration};
+use metrics::{MetricsConfiguration, ProxyMetrics};
+use event::{EventBus, EventResult, PlayerJoinedProxy, ProtocolVersionChecked, ProxyFinishedInitialization, ProxyPinged, ProxyShutdown};
 use packet::{
-    handshake::HandshakePacket, login::{self, LoginDisconnectPacket, LoginStartPacket, LoginSuccessPacket}, play::SystemChatMessagePacket, Packet
+    handshake::HandshakePacket, login::{LoginDisconnectPacket, LoginStartPacket, LoginSuccessPacket}, play::SystemChatMessagePacket,
+    registry,
+    status::{PingRequestPacket, PongResponsePacket, ServerStatus, StatusPlayers, StatusRequestPacket, StatusResponsePacket, StatusVersion},
+    Packet, PacketLimits,
+};
+use player::{
+    limbo::{self, LimboConfiguration},
+    ConnectionState, OutboundLimits, PlayerConnection, TrafficForwardingResult,
 };
-use player::{PlayerConnection, PlayerInfo, TrafficForwardingResult};
+use query::QueryConfiguration;
+use rcon::RconConfiguration;
+use upgrade::{Handoff, UpgradeConfiguration, UpgradeListener};
 use serde::Deserialize;
-use server::ProxiedServer;
+use uuid::Uuid;
+use server::{
+    group::ServerGroup,
+    health::{self, HealthCheckConfiguration},
+    queue::{self, ServerQueues},
+    ProxiedServer,
+};
 use tokio::{
-    net::TcpListener,
-    sync::{Mutex, RwLock},
-    task,
+    net::{TcpListener, UdpSocket},
+    sync::{watch, Mutex, RwLock},
+    task::{self, JoinHandle, JoinSet},
+    time,
 };
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+
+/// How long players get to disconnect once the proxy shuts down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What players are disconnected with when the proxy is stopped by a signal
+/// or from the console.
+pub const SHUTDOWN_MESSAGE: &str = "§cThe proxy is shutting down.";
+
+/// An inclusive range of protocol version numbers, e.g. `{ min = 766, max = 769 }`.
+#[derive(Deserialize, Clone, Copy)]
+pub struct ProtocolRange {
+    pub min: u32,
+    pub max: u32,
+}
 
 #[derive(Deserialize)]
 pub struct ProxyConfiguration {
     pub proxy_port: i16,
     pub address: Option<String>,
     pub servers: Option<HashMap<String, ProxiedServer>>,
+    /// Keys of the servers or groups players join, in order of preference.
+    /// Without it any server is tried.
+    #[serde(rename = "try")]
+    pub try_servers: Option<Vec<String>>,
+    /// Servers that players are spread over, by group name. A server key
+    /// takes precedence over a group of the same name.
+    pub groups: Option<HashMap<String, ServerGroup>>,
+    pub motd: Option<String>,
+    pub max_players: Option<u32>,
+    /// Client versions allowed to join, defaulting to everything the packet
+    /// registry knows.
+    pub supported_protocols: Option<ProtocolRange>,
+    /// Directory of `.lua` scripts loaded when built with the `lua` feature.
+    pub scripts: Option<String>,
+    pub packet_limits: Option<PacketLimits>,
+    /// What happens when a player or server reads slower than the proxy
+    /// receives traffic for it.
+    pub outbound_limits: Option<OutboundLimits>,
+    /// Serves Prometheus metrics over HTTP when set.
+    pub metrics: Option<MetricsConfiguration>,
+    pub logging: Option<LoggingConfiguration>,
+    /// Serves the admin API over HTTP when set.
+    pub api: Option<ApiConfiguration>,
+    /// Accepts remote consoles when set.
+    pub rcon: Option<RconConfiguration>,
+    /// Answers GameSpy4 queries over UDP when set.
+    pub query: Option<QueryConfiguration>,
+    /// Lets a newly started proxy take over the listener when set.
+    pub upgrade: Option<UpgradeConfiguration>,
+    /// Pings every server in the background when set, so players are only
+    /// sent to servers that answer.
+    pub health_checks: Option<HealthCheckConfiguration>,
+    /// Holds players in an empty world while no server can take them, when
+    /// set, instead of disconnecting them.
+    pub limbo: Option<LimboConfiguration>,
+    /// How the network looks while closed for maintenance, and who may
+    /// join anyway.
+    pub maintenance: Option<MaintenanceConfiguration>,
+    /// Permissions granted to players, by name or UUID, e.g.
+    /// `Notch = ["rustyproxy.command.*"]`.
+    pub permissions: Option<HashMap<String, Vec<String>>>,
+    /// The file this configuration was read from, if any.
+    #[serde(skip)]
+    pub path: Option<String>,
 }
 
 impl ProxyConfiguration {
     pub fn from_file(path: &str) -> Result<Self, std::io::Error> {
         let config_content = fs::read_to_string(path)?;
-        let config: ProxyConfiguration = toml::from_str(&config_content).unwrap();
+        let mut config: ProxyConfiguration = toml::from_str(&config_content)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        config.path = Some(path.to_owned());
         Ok(config)
     }
 }
 
 pub struct ProxyInstance {
     pub servers: HashMap<String, Arc<ProxiedServer>>,
+    /// Handles to the players that logged in, by UUID.
+    pub players: HashMap<Uuid, PlayerConnection>,
     pub config: ProxyConfiguration,
+    pub metrics: Arc<ProxyMetrics>,
+    /// Servers that still hold players but take no new ones.
+    pub draining: HashSet<String>,
+    pub commands: Arc<CommandRegistry>,
+    /// Names of the loaded plugins, as reported to queries.
+    pub plugins: Vec<String>,
+    /// Players waiting for a full server or group.
+    pub queues: ServerQueues,
+    /// Whether the network is closed for maintenance, starting as
+    /// configured.
+    pub maintenance: bool,
+    /// The reason players are disconnected with, once shutting down.
+    stopping: watch::Sender<Option<FormattedText>>,
+}
+
+impl ProxyInstance {
+    /// Stops accepting connections and disconnects every player with
+    /// `reason`, after which `start` returns. Only the first call counts.
+    pub fn shutdown(&self, reason: FormattedText) {
+        self.stopping.send_if_modified(|stopping| {
+            if stopping.is_some() {
+                return false;
+            }
+
+            *stopping = Some(reason);
+            true
+        });
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.borrow().is_some()
+    }
+
+    /// The configured protocol range, narrowed to what the registry knows.
+    pub fn supported_protocols(&self) -> ProtocolRange {
+        let first = registry::SUPPORTED_PROTOCOLS[0];
+        let last = registry::LATEST_PROTOCOL;
+
+        match self.config.supported_protocols {
+            Some(range) => ProtocolRange {
+                min: range.min.clamp(first, last),
+                max: range.max.clamp(first, last),
+            },
+            None => ProtocolRange {
+                min: first,
+                max: last,
+            },
+        }
+    }
+
+    pub fn packet_limits(&self) -> PacketLimits {
+        self.config.packet_limits.unwrap_or_default()
+    }
+
+    pub fn outbound_limits(&self) -> OutboundLimits {
+        self.config.outbound_limits.unwrap_or_default()
+    }
+
+    /// Whether the server registered under `key` is online and not
+    /// draining.
+    pub fn is_available(&self, key: &str) -> bool {
+        !self.draining.contains(key) && self.servers.get(key).is_some_and(|server| server.is_online())
+    }
+
+    /// Whether the server registered under `key` is available and below its
+    /// `max_players`, given how many `players` each server has.
+    pub fn has_room(&self, key: &str, players: &HashMap<String, usize>) -> bool {
+        self.is_available(key)
+            && self.servers[key]
+                .max_players
+                .is_none_or(|max| players.get(key).copied().unwrap_or(0) < max as usize)
+    }
+
+    /// Whether `target`, a server key or group, only lacks room: it has an
+    /// available server, but none below its `max_players`.
+    pub fn is_full(&self, target: &str, players: &HashMap<String, usize>) -> bool {
+        let keys: Vec<&str> = match (self.servers.contains_key(target), self.group(target)) {
+            (false, Some(group)) => group.servers.iter().map(String::as_str).collect(),
+            _ => vec![target],
+        };
+
+        keys.iter().any(|key| self.is_available(key)) && !keys.iter().any(|key| self.has_room(key, players))
+    }
+
+    /// The server the player `uuid` joins: the first of `try` that has
+    /// room, or any server with room without a `try` list, given how many
+    /// `players` each server has.
+    pub fn initial_server(&self, uuid: Uuid, players: &HashMap<String, usize>) -> Option<(String, Arc<ProxiedServer>)> {
+        let Some(targets) = &self.config.try_servers else {
+            let mut keys: Vec<_> = self.servers.keys().filter(|key| self.has_room(key, players)).collect();
+            keys.sort();
+            let key = keys.first()?;
+            return Some(((*key).clone(), Arc::clone(&self.servers[*key])));
+        };
+
+        targets.iter().find_map(|target| {
+            self.pick_server(target, uuid, players)
+                .filter(|(key, _)| self.has_room(key, players))
+        })
+    }
+
+    /// The server `target` names for the player `uuid`: the server under
+    /// that key, or else a server with room of the group by that name, given
+    /// how many `players` each server has.
+    pub fn pick_server(
+        &self,
+        target: &str,
+        uuid: Uuid,
+        players: &HashMap<String, usize>,
+    ) -> Option<(String, Arc<ProxiedServer>)> {
+        if let Some(server) = self.servers.get(target) {
+            return Some((target.to_owned(), Arc::clone(server)));
+        }
+
+        let group = self.group(target)?;
+        let candidates: Vec<_> = group
+            .servers
+            .iter()
+            .filter(|key| self.has_room(key, players))
+            .map(|key| (key.clone(), Arc::clone(&self.servers[key])))
+            .collect();
+
+        group.pick(&candidates, uuid, players).cloned()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&ServerGroup> {
+        self.config.groups.as_ref()?.get(name)
+    }
+
+    /// The key `server` is registered under, if it still is.
+    pub fn server_key(&self, server: &Arc<ProxiedServer>) -> Option<&str> {
+        self.servers
+            .iter()
+            .find(|(_, known)| Arc::ptr_eq(known, server))
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// Registers `server` under `key`, replacing and undraining any server
+    /// registered there before. Players stay on the server they are on.
+    pub fn add_server(&mut self, key: String, server: ProxiedServer) {
+        self.draining.remove(&key);
+        self.servers.insert(key, Arc::new(server));
+    }
+
+    /// Stops routing players to the server under `key`. Players on it stay
+    /// connected until they leave or are moved.
+    pub fn remove_server(&mut self, key: &str) -> Option<Arc<ProxiedServer>> {
+        self.draining.remove(key);
+        self.servers.remove(key)
+    }
+
+    /// Reads the configuration file again and applies it. Servers keep their
+    /// identity when unchanged; servers added at runtime are dropped. The
+    /// listeners, logging and API keep their settings until a restart.
+    ///
+    /// Returns whether to turn maintenance on or off, when the file changed
+    /// its mind about it. The free [`reload`] does so as well.
+    pub fn reload(&mut self) -> Result<Option<bool>, std::io::Error> {
+        let Some(path) = self.config.path.clone() else {
+            return Err(std::io::Error::new(ErrorKind::Unsupported, "the configuration was not read from a file"));
+        };
+
+        let config = ProxyConfiguration::from_file(&path)?;
+
+        let servers: HashMap<_, _> = config
+            .servers
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, server)| {
+                let server = match self.servers.get(&key) {
+                    Some(known) if **known == server => Arc::clone(known),
+                    _ => Arc::new(server),
+                };
+                (key, server)
+            })
+            .collect();
+
+        // Maintenance toggled at runtime survives a reload unless the file
+        // changed its mind.
+        let enabled = |config: &ProxyConfiguration| config.maintenance.as_ref().is_some_and(|maintenance| maintenance.enabled);
+        let maintenance = (enabled(&config) != enabled(&self.config)).then(|| enabled(&config));
+
+        self.draining.retain(|key| servers.contains_key(key));
+        self.servers = servers;
+        self.config = config;
+
+        info!("Reloaded the configuration from {}", path);
+        Ok(maintenance)
+    }
+
+    /// Forgets the player connected from `addr` once its connection ended.
+    pub fn remove_player(&mut self, addr: SocketAddr) {
+        let uuid = self
+            .players
+            .iter()
+            .find(|(_, player)| player.addr == addr)
+            .map(|(uuid, _)| *uuid);
+
+        if let Some(player) = uuid.and_then(|uuid| self.players.remove(&uuid)) {
+            self.metrics.connection_closed(player.stats());
+        }
+    }
+
+    pub fn supports_protocol(&self, protocol: u32) -> bool {
+        let range = self.supported_protocols();
+        registry::is_supported(protocol) && (range.min..=range.max).contains(&protocol)
+    }
+
+    /// The supported game versions as shown to players, e.g. `1.20.5-1.21.4`.
+    pub fn supported_versions(&self) -> String {
+        let range = self.supported_protocols();
+        let oldest = registry::version_name(range.min)
+            .and_then(|name| name.split('-').next())
+            .unwrap_or_default();
+        let newest = registry::version_name(range.max)
+            .and_then(|name| name.rsplit('-').next())
+            .unwrap_or_default();
+
+        if oldest == newest {
+            oldest.to_owned()
+        } else {
+            format!("{}-{}", oldest, newest)
+        }
+    }
+
+    /// The reason given to clients whose version is not supported.
+    pub fn incompatible_reason(&self) -> FormattedText {
+        FormattedText::Translatable(TranslatableComponent::new(
+            "multiplayer.disconnect.incompatible".to_owned(),
+            vec![StringOrComponent::String(self.supported_versions())],
+        ))
+    }
+
+    /// Builds the status shown in the server list from the configuration.
+    /// Compatible clients see their own version; others get the supported
+    /// range under a protocol number that marks the entry incompatible.
+    /// During maintenance every client sees the maintenance label that way.
+    pub fn status(&self, protocol: u32, compatible: bool) -> ServerStatus {
+        let maintenance = self.maintenance.then(|| self.maintenance_config());
+
+        let version = if let Some(maintenance) = &maintenance {
+            StatusVersion {
+                name: maintenance.version.clone(),
+                protocol: 0,
+            }
+        } else if compatible {
+            StatusVersion {
+                name: format!(
+                    "rustyproxy {}",
+                    registry::version_name(protocol).unwrap_or_default()
+                ),
+                protocol,
+            }
+        } else {
+            StatusVersion {
+                name: format!("rustyproxy {}", self.supported_versions()),
+                protocol: self.supported_protocols().max,
+            }
+        };
+
+        ServerStatus {
+            version,
+            players: StatusPlayers {
+                max: self.config.max_players.unwrap_or(20),
+                online: self.players.len() as u32,
+            },
+            description: FormattedText::Text(TextComponent::new(match maintenance {
+                Some(maintenance) => maintenance.motd,
+                None => self
+                    .config
+                    .motd
+                    .clone()
+                    .unwrap_or_else(|| "A rustyproxy server".to_owned()),
+            })),
+        }
+    }
+}
+
+/// Reloads the configuration, and turns maintenance on or off as the file
+/// asks, kicking players just as turning it on at runtime does. The kicks
+/// happen once the instance lock is released.
+pub async fn reload(instance: &SharedProxyInstance) -> Result<(), std::io::Error> {
+    let maintenance = instance.write().await.reload()?;
+    if let Some(enabled) = maintenance {
+        set_maintenance(instance, enabled).await;
+    }
+    Ok(())
+}
+
+/// How many players are on each server, by key.
+///
+/// Like everything that waits on a player's locks, this only holds the
+/// instance lock to copy the players out: a player may be waiting for the
+/// instance lock while holding its own.
+pub async fn players_by_server(instance: &SharedProxyInstance) -> HashMap<String, usize> {
+    let (players, servers) = {
+        let instance = instance.read().await;
+        let players: Vec<PlayerConnection> = instance.players.values().cloned().collect();
+        (players, instance.servers.clone())
+    };
+
+    let mut counts = HashMap::new();
+    for player in players {
+        if let Some(server) = player.current_server().await {
+            if let Some((key, _)) = servers.iter().find(|(_, known)| Arc::ptr_eq(known, &server)) {
+                *counts.entry(key.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    counts
+}
+
+/// Finds a player by UUID or, ignoring case, by name.
+pub async fn find_player(instance: &SharedProxyInstance, query: &str) -> Option<PlayerConnection> {
+    let players: Vec<PlayerConnection> = {
+        let instance = instance.read().await;
+        if let Ok(uuid) = Uuid::parse_str(query) {
+            return instance.players.get(&uuid).cloned();
+        }
+
+        instance.players.values().cloned().collect()
+    };
+
+    for player in players {
+        let matches = player
+            .player_info
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|info| info.username.eq_ignore_ascii_case(query));
+
+        if matches {
+            return Some(player);
+        }
+    }
+
+    None
+}
+
+/// Decides whether the client's protocol version is accepted, letting
+/// listeners of `ProtocolVersionChecked` override the configured range.
+async fn check_protocol(
+    instance: &SharedProxyInstance,
+    event_bus: &Arc<EventBus>,
+    connection: &Arc<Mutex<PlayerConnection>>,
+    handshake: &HandshakePacket,
+) -> bool {
+    let compatible = instance.read().await.supports_protocol(handshake.protocol);
+
+    let event = Arc::new(ProtocolVersionChecked {
+        connection: Arc::clone(connection),
+        handshake: handshake.clone(),
+        compatible: Arc::new(Mutex::new(compatible)),
+    });
+
+    event_bus.dispatch(&event).await;
+
+    let compatible = *event.compatible.lock().await;
+    compatible
 }
 
 pub type SharedProxyInstance = Arc<tokio::sync::RwLock<ProxyInstance>>;
@@ -56,64 +529,224 @@ impl ProxyInstance {
                 .to_string()
         };
 
-        let socket = TcpListener::bind(format!(
-            "{}:{}",
-            address,
-            instance.read().await.config.proxy_port
-        ))
-        .await?;
+        let upgrade_config = instance.read().await.config.upgrade.clone();
+        let inherited = match &upgrade_config {
+            Some(upgrade_config) => upgrade::inherit(&upgrade_config.socket).await?,
+            None => None,
+        };
+
+        let socket = match inherited {
+            Some(socket) => {
+                info!("Took over the listener on {} from the running proxy", socket.local_addr()?);
+                socket
+            }
+            None => {
+                TcpListener::bind(format!(
+                    "{}:{}",
+                    address,
+                    instance.read().await.config.proxy_port
+                ))
+                .await?
+            }
+        };
+
+        // Closed when handing the listener over, so the new process can bind
+        // them in turn.
+        let mut services = Vec::new();
+        start_services(&instance, &event_bus, &address, &mut services).await?;
+
+        let health_config = instance.read().await.config.health_checks;
+        if let Some(health_config) = health_config {
+            info!("Checking the health of servers every {}s", health_config.interval);
+            task::spawn(health::monitor(Arc::clone(&instance), Arc::clone(&event_bus), health_config));
+        }
+
+        task::spawn(queue::run(Arc::clone(&instance)));
+
+        let upgrades = match &upgrade_config {
+            Some(upgrade_config) => {
+                let upgrades = UpgradeListener::bind(&upgrade_config.socket)?;
+                info!("Offering the listener to new processes on {}", upgrade_config.socket);
+                Some(upgrades)
+            }
+            None => None,
+        };
 
         event_bus
             .dispatch(&Arc::new(ProxyFinishedInitialization))
             .await;
 
-        loop {
-            match socket.accept().await {
+        let signal_instance = Arc::clone(&instance);
+        task::spawn(async move {
+            termination_signal().await;
+            signal_instance
+                .read()
+                .await
+                .shutdown(FormattedText::Text(TextComponent::new(SHUTDOWN_MESSAGE.to_owned())));
+        });
+
+        let mut stopping = instance.read().await.stopping.subscribe();
+        let mut connections = JoinSet::new();
+
+        let handed_over = loop {
+            let accepted = tokio::select! {
+                accepted = socket.accept() => accepted,
+                Some(_) = connections.join_next() => continue,
+                _ = stopped(&mut stopping) => break false,
+                handoff = async {
+                    match &upgrades {
+                        Some(upgrades) => upgrades.accept().await,
+                        None => std::future::pending().await,
+                    }
+                } => match handoff {
+                    Ok(handoff) => {
+                        if hand_over(handoff, &socket, &mut services, &instance, &event_bus, &address).await {
+                            break true;
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Failed to accept a new process: {:?}", e);
+                        continue;
+                    }
+                },
+            };
+
+            match accepted {
                 Ok((stream, addr)) => {
+                    let accepted_at = Instant::now();
+                    let metrics = Arc::clone(&instance.read().await.metrics);
+                    let supervisor_instance = Arc::clone(&instance);
                     let instance = Arc::clone(&instance);
                     let event_bus = Arc::clone(&event_bus);
+                    let span = info_span!(
+                        "connection",
+                        addr = %addr,
+                        player = field::Empty,
+                        uuid = field::Empty,
+                        server = field::Empty,
+                    );
 
-                    task::spawn(async move {
-                        let connection = Arc::new(Mutex::new(PlayerConnection::new(
-                            stream, addr, &instance, &event_bus,
-                        )));
+                    let connection_task = task::spawn(async move {
+                        let mut player = PlayerConnection::new(stream, addr, &instance, &event_bus);
+                        {
+                            let proxy = instance.read().await;
+                            player.limits = proxy.packet_limits();
+                            player.outbound_limits = proxy.outbound_limits();
+                        }
+
+                        let connection = Arc::new(Mutex::new(player));
+
+                        let mut cnx = connection.lock().await;
+                        let handshake = match HandshakePacket::read_from(&mut cnx).await {
+                            Ok(handshake) => handshake,
+                            Err(e) => {
+                                cnx.close_after(&e).await;
+                                return;
+                            }
+                        };
+                        cnx.protocol = handshake.protocol;
+                        cnx.state = match handshake.next_state {
+                            1 => ConnectionState::Status,
+                            _ => ConnectionState::Login,
+                        };
+                        drop(cnx);
+
+                        let compatible =
+                            check_protocol(&instance, &event_bus, &connection, &handshake).await;
 
                         let mut cnx = connection.lock().await;
-                        let handshake = HandshakePacket::read_from(&mut cnx).await;
 
-                        if let Err(_) = handshake {
+                        if handshake.next_state == 1 {
+                            if let Err(e) = StatusRequestPacket::read_from(&mut cnx).await {
+                                cnx.close_after(&e).await;
+                                return;
+                            }
+
+                            drop(cnx);
+
+                            let status = instance.read().await.status(handshake.protocol, compatible);
+                            let event = Arc::new(ProxyPinged {
+                                connection: Arc::clone(&connection),
+                                handshake: *handshake,
+                                status: Arc::new(Mutex::new(status)),
+                            });
+
+                            let result = event_bus.dispatch(&event).await;
+
+                            let mut cnx = connection.lock().await;
+                            if result == Some(EventResult::Stop) {
+                                metrics.connection_rejected("cancelled");
+                                let _ = cnx.close().await;
+                                return;
+                            }
+
+                            metrics.connection_accepted("status");
+
+                            let response = StatusResponsePacket::from_status(&*event.status.lock().await);
+                            if cnx.send_packet(&response).is_err() {
+                                return;
+                            }
+
+                            match PingRequestPacket::read_from(&mut cnx).await {
+                                Ok(ping) => {
+                                    let _ = cnx.send_packet(&PongResponsePacket { payload: ping.payload });
+                                    let _ = cnx.close().await;
+                                }
+                                Err(e) => cnx.close_after(&e).await,
+                            }
+                            return;
+                        }
+
+                        if handshake.next_state == 2 && !compatible {
+                            metrics.connection_rejected("incompatible_version");
+                            let reason = instance.read().await.incompatible_reason();
+                            let _ = cnx.send_packet(&LoginDisconnectPacket { reason });
                             let _ = cnx.close().await;
                             return;
                         }
 
-                        let handshake = handshake.unwrap();
                         let proxy = instance.read().await;
 
                         if proxy.servers.is_empty() && handshake.next_state == 2 {
-                            let _ = cnx
-                                .send_packet(&LoginDisconnectPacket {
-                                    reason: FormattedText::Text(TextComponent::new(
-                                        "§cThere are currently no servers available.".to_owned(),
-                                    )),
-                                })
-                                .await;
+                            metrics.connection_rejected("no_servers");
+                            let _ = cnx.send_packet(&LoginDisconnectPacket {
+                                reason: FormattedText::Text(TextComponent::new(
+                                    "§cThere are currently no servers available.".to_owned(),
+                                )),
+                            });
                             return;
                         }
 
                         drop(proxy);
 
                         if handshake.next_state == 2 {
-                            let login_packet = LoginStartPacket::read_from(&mut cnx).await;
-                            if let Err(_) = login_packet {
-                                let _ = cnx.close().await;
-                                return;
-                            }
+                            let login_packet = match LoginStartPacket::read_from(&mut cnx).await {
+                                Ok(login_packet) => login_packet,
+                                Err(e) => {
+                                    cnx.close_after(&e).await;
+                                    return;
+                                }
+                            };
 
-                            cnx.set_player_info(login_packet.unwrap().as_player_info())
-                                .await;
+                            cnx.set_player_info(login_packet.as_player_info()).await;
+                            Span::current()
+                                .record("player", login_packet.username.as_str())
+                                .record("uuid", field::display(login_packet.uuid));
+                            let player = cnx.clone();
 
                             drop(cnx);
 
+                            let maintenance = instance.read().await.maintenance;
+                            if maintenance && !player.bypasses_maintenance().await {
+                                metrics.connection_rejected("maintenance");
+                                let reason = instance.read().await.maintenance_config().reason();
+                                let mut cnx = connection.lock().await;
+                                let _ = cnx.send_packet(&LoginDisconnectPacket { reason });
+                                let _ = cnx.close().await;
+                                return;
+                            }
+
                             let event = Arc::new(PlayerJoinedProxy {
                                 connection: Arc::clone(&connection),
                             });
@@ -122,35 +755,96 @@ impl ProxyInstance {
 
                             if let Some(result) = result {
                                 if result == EventResult::Stop {
+                                    metrics.connection_rejected("cancelled");
                                     return;
                                 }
                             }
 
+                            // Checked along with the insertion, so a player
+                            // logging in during shutdown is never missed.
+                            let stopping = {
+                                let mut proxy = instance.write().await;
+                                let stopping = proxy.stopping.borrow().clone();
+                                if stopping.is_none() {
+                                    proxy.players.insert(login_packet.uuid, player);
+                                }
+                                stopping
+                            };
+
+                            if let Some(reason) = stopping {
+                                let mut cnx = connection.lock().await;
+                                let _ = cnx.send_packet(&LoginDisconnectPacket { reason });
+                                let _ = cnx.close().await;
+                                return;
+                            }
+
+                            info!("Logged in");
+
                             fn recursively_connect(
                                 connection: Arc<Mutex<PlayerConnection>>, 
                                 instance: SharedProxyInstance,
-                                retry: bool
+                                retry: bool,
+                                accepted_at: Instant,
                             ) -> Pin<Box<impl Future<Output =  ()>>> {
                                 Box::pin(async move {
                                     let mut cnx = connection.lock().await;
-                                    let server = {
+                                    let uuid = cnx.uuid().await.unwrap_or_default();
+                                    let players = players_by_server(&instance).await;
+                                    let (server, metrics, limbo) = {
                                         let instance = instance.read().await;
-                            
-                                        instance
-                                            .servers
-                                            .get(&"local_unauthenticated".to_owned())
-                                            .unwrap()
-                                            .clone()
+                                        (
+                                            instance.initial_server(uuid, &players),
+                                            Arc::clone(&instance.metrics),
+                                            instance.config.limbo.clone(),
+                                        )
+                                    };
+
+                                    let unavailable = match server {
+                                        None => Some(("no_servers", "§cThere are currently no servers available.")),
+                                        Some((key, server)) => match cnx.connect_to(&server).await {
+                                            Ok(_) => None,
+                                            Err(e) if e.kind() == std::io::ErrorKind::ConnectionAborted => {
+                                                metrics.connection_rejected("cancelled");
+                                                let _ = cnx.close().await;
+                                                return;
+                                            }
+                                            Err(e) => {
+                                                warn!("Failed to connect to {}: {}", server.name, e);
+                                                metrics.backend_connect_failed(&key);
+                                                Some(("backend_unavailable", "§cCould not connect to the server."))
+                                            }
+                                        },
                                     };
-                            
-                                    cnx.connect_to(&server).await.unwrap();
 
-                                    if retry {
-                                        cnx.compression_threshold = 256;
-                                        let _ = cnx.send_packet_to_server(&LoginSuccessPacket {}).await;
+                                    // Players no server takes wait in limbo for one, when
+                                    // it is enabled and knows their version.
+                                    if let Some((reason, message)) = unavailable {
+                                        let reason_text = FormattedText::Text(TextComponent::new(message.to_owned()));
 
-                                        let _ = cnx.read_packet().await;
+                                        let Some(limbo) = limbo.filter(|_| limbo::supports(cnx.protocol)) else {
+                                            metrics.connection_rejected(reason);
+                                            let _ = cnx.send_packet(&LoginDisconnectPacket { reason: reason_text });
+                                            let _ = cnx.close().await;
+                                            return;
+                                        };
 
+                                        metrics.connection_accepted("limbo");
+                                        if let Err(e) = cnx.hold_in_limbo(&limbo).await {
+                                            info!("Disconnected from limbo: {}", e);
+                                            let _ = cnx.kick(reason_text).await;
+                                            return;
+                                        }
+                                    } else {
+                                        if retry {
+                                            cnx.set_compression_threshold(256);
+                                            let _ = cnx.send_packet_to_server(&LoginSuccessPacket::default()).await;
+
+                                            let _ = cnx.read_packet().await;
+
+                                        }
+
+                                        metrics.connection_accepted("login");
+                                        metrics.login_completed(accepted_at.elapsed());
                                     }
 
                                     let result = cnx.handle_traffic().await;
@@ -161,7 +855,7 @@ impl ProxyInstance {
                                                 let _ = cnx.send_packet(&SystemChatMessagePacket {
                                                     text: azalea_chat::FormattedText::Text(TextComponent::new(format!("§cYou were kicked from the server!").to_owned())),
                                                     overlay: false,
-                                                }).await;
+                                                });
                                             }
 
                                             //let _ = cnx.server_closed().await;
@@ -173,23 +867,220 @@ impl ProxyInstance {
                                         TrafficForwardingResult::PlayerDisconnected
                                         | TrafficForwardingResult::PlayerErrored => {
                                             let _ = cnx.close().await;
-                                            println!("d");
+                                            info!("Disconnected");
                                         }
                                     }
                                 })
                             }
 
-                            recursively_connect(connection, instance,false).await
+                            recursively_connect(connection, instance,false, accepted_at).await
+                        }
+                    }.instrument(span));
+
+                    // Keeps the registry and metrics right however the
+                    // connection ended.
+                    connections.spawn(async move {
+                        let result = connection_task.await;
+                        let mut instance = supervisor_instance.write().await;
+
+                        if result.is_err_and(|e| e.is_panic()) {
+                            error!("Connection of {} panicked", addr);
+                            instance.metrics.panic_caught();
                         }
+
+                        instance.remove_player(addr);
                     });
                 }
-                Err(e) => eprintln!("Failed to accept connection: {:?}", e),
+                Err(e) => error!("Failed to accept connection: {:?}", e),
+            }
+        };
+
+        if handed_over {
+            drop(upgrades);
+            drop(socket);
+
+            let drain_timeout = upgrade_config.and_then(|upgrade_config| upgrade_config.drain_timeout());
+            let deadline = drain_timeout.map(|timeout| time::Instant::now() + timeout);
+            info!("Waiting for {} connection(s) to close", connections.len());
+
+            loop {
+                tokio::select! {
+                    joined = connections.join_next() => if joined.is_none() {
+                        info!("Stopped");
+                        return Ok(());
+                    },
+                    _ = stopped(&mut stopping) => break,
+                    _ = time::sleep_until(deadline.unwrap_or_else(time::Instant::now)), if deadline.is_some() => {
+                        instance
+                            .read()
+                            .await
+                            .shutdown(FormattedText::Text(TextComponent::new(SHUTDOWN_MESSAGE.to_owned())));
+                        break;
+                    }
+                }
+            }
+        } else {
+            drop(socket);
+        }
+
+        let reason = instance.read().await.stopping.borrow().clone();
+        let Some(reason) = reason else {
+            return Ok(());
+        };
+
+        info!("Shutting down");
+
+        event_bus
+            .dispatch(&Arc::new(ProxyShutdown { reason: reason.clone() }))
+            .await;
+
+        let players: Vec<PlayerConnection> = instance.read().await.players.values().cloned().collect();
+        for mut player in players {
+            let _ = player.kick(reason.clone()).await;
+        }
+
+        let closed = time::timeout(SHUTDOWN_TIMEOUT, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+
+        if closed.is_err() {
+            warn!("{} connection(s) did not close in time", connections.len());
+            connections.shutdown().await;
+        }
+
+        info!("Stopped");
+        Ok(())
+    }
+}
+
+/// Starts the services besides the proxy itself, pushing each onto
+/// `services` as it starts, so a handoff stops them even if a later one
+/// failed to.
+async fn start_services(
+    instance: &SharedProxyInstance,
+    event_bus: &Arc<EventBus>,
+    address: &str,
+    services: &mut Vec<JoinHandle<()>>,
+) -> Result<(), std::io::Error> {
+    let metrics_config = instance.read().await.config.metrics.clone();
+    if let Some(metrics_config) = metrics_config {
+        let listener = TcpListener::bind((
+            metrics_config.address.as_deref().unwrap_or("127.0.0.1"),
+            metrics_config.port,
+        ))
+        .await?;
+
+        info!("Serving metrics on {}", listener.local_addr()?);
+        services.push(task::spawn(metrics::serve(listener, Arc::clone(instance), Arc::clone(event_bus))));
+    }
+
+    let api_config = instance.read().await.config.api.clone();
+    if let Some(api_config) = api_config {
+        if api_config.token.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "the API token must not be empty",
+            ));
+        }
+
+        let listener = TcpListener::bind((
+            api_config.address.as_deref().unwrap_or("127.0.0.1"),
+            api_config.port,
+        ))
+        .await?;
+
+        info!("Serving the admin API on {}", listener.local_addr()?);
+        services.push(task::spawn(api::serve(listener, Arc::clone(instance), api_config.token)));
+    }
+
+    let rcon_config = instance.read().await.config.rcon.clone();
+    if let Some(rcon_config) = rcon_config {
+        let listener = TcpListener::bind((
+            rcon_config.address.as_deref().unwrap_or("127.0.0.1"),
+            rcon_config.port,
+        ))
+        .await?;
+
+        info!("Accepting remote consoles on {}", listener.local_addr()?);
+        services.push(task::spawn(rcon::serve(listener, Arc::clone(instance), rcon_config.password)));
+    }
+
+    let query_config = instance.read().await.config.query.clone();
+    if let Some(query_config) = query_config {
+        let socket = UdpSocket::bind((
+            query_config.address.as_deref().unwrap_or(address),
+            query_config.port,
+        ))
+        .await?;
+
+        info!("Answering queries on {}", socket.local_addr()?);
+        services.push(task::spawn(query::serve(socket, Arc::clone(instance))));
+    }
+
+    Ok(())
+}
+
+/// Gives the listener to the new process behind `handoff`. The services are
+/// stopped first so it can bind them, and started again if the listener could
+/// not be handed over, in which case this process keeps accepting.
+async fn hand_over(
+    handoff: Handoff,
+    socket: &TcpListener,
+    services: &mut Vec<JoinHandle<()>>,
+    instance: &SharedProxyInstance,
+    event_bus: &Arc<EventBus>,
+    address: &str,
+) -> bool {
+    for service in services.iter() {
+        service.abort();
+    }
+    for service in services.drain(..) {
+        let _ = service.await;
+    }
+
+    match handoff.hand_over(socket).await {
+        Ok(()) => {
+            info!("Handed the listener over to a new process");
+            true
+        }
+        Err(e) => {
+            error!("Failed to hand the listener over: {}", e);
+            if let Err(e) = start_services(instance, event_bus, address, services).await {
+                error!("Failed to restart services: {}", e);
             }
+            false
         }
     }
 }
 
+/// Waits until the proxy is shutting down. Unlike waiting on the channel
+/// directly, no lock on it is held afterwards, so the future stays `Send`.
+async fn stopped(stopping: &mut watch::Receiver<Option<FormattedText>>) {
+    let _ = stopping.wait_for(Option::is_some).await;
+}
+
+/// Waits for SIGINT, or SIGTERM on Unix.
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+
+    let _ = tokio::signal::ctrl_c().await;
+}
+
 pub fn new_instance(config: ProxyConfiguration) -> Result<SharedProxyInstance, Box<dyn Error>> {
+    let maintenance = config.maintenance.as_ref().is_some_and(|maintenance| maintenance.enabled);
+
     Ok(Arc::new(RwLock::new(ProxyInstance {
         servers: config
             .servers
@@ -200,6 +1091,14 @@ pub fn new_instance(config: ProxyConfiguration) -> Result<SharedProxyInstance, B
                     .collect::<HashMap<_, _>>()
             })
             .unwrap_or_else(HashMap::new),
+        players: HashMap::new(),
         config,
+        metrics: Arc::new(ProxyMetrics::default()),
+        draining: HashSet::new(),
+        commands: Arc::new(CommandRegistry::default()),
+        plugins: Vec::new(),
+        queues: ServerQueues::default(),
+        maintenance,
+        stopping: watch::channel(None).0,
     })))
 }
@@ -0,0 +1,97 @@
+use std::{
+    env,
+    error::Error,
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+/// The `[logging]` section of the configuration.
+#[derive(Deserialize, Clone, Default)]
+pub struct LoggingConfiguration {
+    /// Which events to log, in the syntax of `RUST_LOG`, e.g.
+    /// `info,rustyproxy::player=debug`. `RUST_LOG` takes precedence when set.
+    pub filter: Option<String>,
+    pub format: Option<LogFormat>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the current span.
+    Json,
+}
+
+/// Receives every log line instead of stdout while set, e.g. to print it
+/// above the console's prompt.
+type Sink = Box<dyn FnMut(String) + Send>;
+
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+/// Sends log lines to `sink` from now on, or back to stdout with `None`.
+pub fn redirect(sink: Option<Sink>) {
+    *SINK.lock().unwrap() = sink;
+}
+
+/// Collects one formatted event and hands it on whole once dropped, so lines
+/// never interleave.
+#[derive(Default)]
+struct LogWriter {
+    buffer: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        let mut sink = SINK.lock().unwrap();
+
+        match sink.as_mut() {
+            Some(sink) => {
+                let line = String::from_utf8_lossy(&self.buffer);
+                sink(line.trim_end_matches('\n').to_owned());
+            }
+            None => {
+                let _ = io::stdout().lock().write_all(&self.buffer);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber. Connections log inside a `connection`
+/// span carrying the player's address, name, UUID and server.
+pub fn init(config: Option<&LoggingConfiguration>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let config = config.cloned().unwrap_or_default();
+
+    let filter = match env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(_) => EnvFilter::try_from_default_env()?,
+        Err(_) => EnvFilter::try_new(config.filter.as_deref().unwrap_or("info"))?,
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(LogWriter::default);
+
+    match config.format.unwrap_or_default() {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    }
+}
@@ -2,15 +2,16 @@ use std::{collections::HashMap, sync::Arc};
 
 use azalea_chat::text_component::TextComponent;
 use rustyproxy::{
-    event::{EventBus, EventResult, PlayerJoinedProxy, PlayerJoinedServer, ProxyFinishedInitialization, ServerSentPacket}, packet::{self, configuration::{channels::BrandChannel, PlayerConfigurationPluginMessagePacket}, login::LoginDisconnectPacket}, player::{ConnectionState, PlayerInfo}, server::ProxiedServer, ProxyConfiguration, ProxyInstance
+    event::{EventBus, EventResult, PlayerJoinedProxy, PlayerJoinedServer, ProxyFinishedInitialization}, packet::{configuration::{channels::BrandChannel, PlayerConfigurationPluginMessagePacket}, login::LoginDisconnectPacket, registry::PacketKind}, player::PlayerInfo, server::ProxiedServer, ProxyConfiguration, ProxyInstance
 };
+use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
-    let instance = rustyproxy::new_instance(
-        ProxyConfiguration::from_file("example/config.toml").unwrap()
-    )
-    .unwrap();
+    let config = ProxyConfiguration::from_file("example/config.toml").unwrap();
+    rustyproxy::logging::init(config.logging.as_ref()).map_err(std::io::Error::other)?;
+
+    let instance = rustyproxy::new_instance(config).unwrap();
 
     let event_bus = EventBus::new(&instance);
 
@@ -25,7 +26,7 @@ async fn main() -> Result<(), std::io::Error> {
                 .collect();
 
             let serialized_data: String = serde_json::to_string(&cloned_servers).unwrap();
-            println!("{}", serialized_data);
+            info!("{}", serialized_data);
 
             None
         })
@@ -39,7 +40,7 @@ async fn main() -> Result<(), std::io::Error> {
                 "local".to_string(),
                 Arc::new(ProxiedServer::new("Localhost".to_string(),"0.0.0.0".to_owned(), 25565)),
             );
-            println!("finished");
+            info!("finished");
 
             None
         })
@@ -64,35 +65,50 @@ async fn main() -> Result<(), std::io::Error> {
         })
         .await;
 
-    event_bus.listen::<ServerSentPacket, _,_,_>(false, |_, event| async move {
-        let mut connection = event.connection.lock().await;
-        let server_guard = connection.server.lock().await;
-        let server = server_guard.as_ref().unwrap();
-
+    event_bus.listen_packet(PacketKind::PlayerConfigurationPluginMessage, false, |_, event| async move {
+        let connection = event.connection.lock().await;
 
-        
-        let (_, id, data) = &event.packet;
-        if *id != 0x01 || server.state != Some(ConnectionState::Configuration) {
+        // Fails for plugin messages on any other channel.
+        let Ok(brand_packet) = event.packet.decode_as::<PlayerConfigurationPluginMessagePacket<BrandChannel>>() else {
             return None
-        }
+        };
 
-        drop(server_guard);
-
-        let mut position = 0 as usize;
-        let channel_name = packet::data::read_string(&data, &mut position).unwrap();
-
-        if channel_name != "minecraft:brand" {
-            return None
-        }
-
-        let brand = packet::data::read_string(&data, &mut position).unwrap();
-        let channel_data = BrandChannel { brand: format!("{} (rustyproxy)", brand) };
+        let channel_data = BrandChannel { brand: format!("{} (rustyproxy)", brand_packet.data.brand) };
 
         let constructed_packet = PlayerConfigurationPluginMessagePacket { data: channel_data };
 
-        let _ = connection.send_packet(&constructed_packet).await;
+        let _ = connection.send_packet(&constructed_packet);
         Some(EventResult::Stop)
     }).await;
 
+    #[cfg(feature = "lua")]
+    {
+        let scripts = instance.read().await.config.scripts.clone();
+        if let Some(directory) = scripts {
+            let engine = rustyproxy::scripting::ScriptEngine::load(directory, &instance)
+                .await
+                .map_err(std::io::Error::other)?;
+            engine.install(&event_bus).await;
+
+            #[cfg(unix)]
+            tokio::spawn(async move {
+                use tokio::signal::unix::{signal, SignalKind};
+
+                let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+                    return;
+                };
+
+                while hangup.recv().await.is_some() {
+                    match engine.reload().await {
+                        Ok(count) => info!("Reloaded {} script(s)", count),
+                        Err(e) => tracing::error!("Failed to reload scripts: {}", e),
+                    }
+                }
+            });
+        }
+    }
+
+    rustyproxy::console::spawn(&instance);
+
     ProxyInstance::start(instance, event_bus).await
 }
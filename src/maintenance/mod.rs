@@ -0,0 +1,189 @@
+//! Closes the network for maintenance while the proxy keeps running. The
+//! server list shows a maintenance MOTD, and only players on the bypass list
+//! or with `rustyproxy.maintenance.bypass` may log in.
+
+use azalea_chat::FormattedText;
+use serde::Deserialize;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{command::legacy_text, player::PlayerConnection, ProxyInstance, SharedProxyInstance};
+
+/// Lets a player join during maintenance.
+pub const BYPASS_PERMISSION: &str = "rustyproxy.maintenance.bypass";
+
+/// How maintenance looks to players, e.g. `[maintenance]` with
+/// `bypass = ["Notch"]`. Every field is optional.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MaintenanceConfiguration {
+    /// Whether the proxy starts in maintenance. Commands and the API turn it
+    /// on and off at runtime.
+    pub enabled: bool,
+    /// Shown in the server list instead of the usual MOTD.
+    pub motd: String,
+    /// Shown in the server list where the version would be.
+    pub version: String,
+    /// What players who may not join are disconnected with.
+    pub message: String,
+    /// Names or UUIDs of the players who may join anyway.
+    pub bypass: Vec<String>,
+    /// Whether players who may not bypass maintenance are kicked when it is
+    /// turned on.
+    pub kick_online: bool,
+}
+
+impl Default for MaintenanceConfiguration {
+    fn default() -> Self {
+        MaintenanceConfiguration {
+            enabled: false,
+            motd: "§cThe network is down for maintenance.".to_owned(),
+            version: "Maintenance".to_owned(),
+            message: "§cThe network is down for maintenance.\n§7Please come back later.".to_owned(),
+            bypass: Vec::new(),
+            kick_online: false,
+        }
+    }
+}
+
+impl MaintenanceConfiguration {
+    /// Whether the player called `name` or with `uuid` is on the bypass list.
+    pub fn bypasses(&self, name: &str, uuid: Uuid) -> bool {
+        self.bypass.iter().any(|entry| {
+            entry.eq_ignore_ascii_case(name) || Uuid::parse_str(entry).is_ok_and(|entry| entry == uuid)
+        })
+    }
+
+    pub fn reason(&self) -> FormattedText {
+        legacy_text(&self.message)
+    }
+}
+
+impl ProxyInstance {
+    pub fn maintenance_config(&self) -> MaintenanceConfiguration {
+        self.config.maintenance.clone().unwrap_or_default()
+    }
+}
+
+impl PlayerConnection {
+    /// Whether the player may stay or join during maintenance, by the
+    /// bypass list or by permission.
+    pub async fn bypasses_maintenance(&self) -> bool {
+        // Copied out first, for the same reason as in `has_permission`.
+        let info = self.player_info.lock().await.clone();
+        let listed = match info {
+            Some(info) => self
+                .proxy_instance
+                .read()
+                .await
+                .maintenance_config()
+                .bypasses(&info.username, info.uuid),
+            None => false,
+        };
+
+        listed || self.has_permission(BYPASS_PERMISSION).await
+    }
+}
+
+/// Turns maintenance on or off, or returns `None` when it already was.
+/// Turning it on with `kick_online` disconnects the players who may not
+/// bypass it, and returns how many were.
+pub async fn set_maintenance(instance: &SharedProxyInstance, enabled: bool) -> Option<usize> {
+    let (config, players) = {
+        let mut instance = instance.write().await;
+        if instance.maintenance == enabled {
+            return None;
+        }
+
+        instance.maintenance = enabled;
+        let players: Vec<PlayerConnection> = instance.players.values().cloned().collect();
+        (instance.maintenance_config(), players)
+    };
+
+    info!("Turned maintenance {}", if enabled { "on" } else { "off" });
+
+    if !enabled || !config.kick_online {
+        return Some(0);
+    }
+
+    let mut kicked = 0;
+    for mut player in players {
+        if !player.bypasses_maintenance().await && player.kick(config.reason()).await.is_ok() {
+            kicked += 1;
+        }
+    }
+
+    if kicked > 0 {
+        info!("Kicked {} players for maintenance", kicked);
+    }
+
+    Some(kicked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProxyConfiguration;
+
+    const NOTCH: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+
+    fn config(bypass: &[&str]) -> MaintenanceConfiguration {
+        MaintenanceConfiguration {
+            bypass: bypass.iter().map(|entry| entry.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn names_bypass_in_any_case() {
+        let config = config(&["Notch"]);
+        assert!(config.bypasses("Notch", Uuid::new_v4()));
+        assert!(config.bypasses("notch", Uuid::new_v4()));
+        assert!(config.bypasses("NOTCH", Uuid::new_v4()));
+    }
+
+    #[test]
+    fn uuids_bypass_whatever_the_name() {
+        let uuid = Uuid::parse_str(NOTCH).unwrap();
+        assert!(config(&[NOTCH]).bypasses("Renamed", uuid));
+        assert!(config(&[&NOTCH.to_uppercase()]).bypasses("Renamed", uuid));
+        assert!(config(&[&uuid.simple().to_string()]).bypasses("Renamed", uuid));
+    }
+
+    #[test]
+    fn others_do_not_bypass() {
+        let config = config(&["Notch", NOTCH]);
+        assert!(!config.bypasses("jeb_", Uuid::new_v4()));
+        assert!(!config.bypasses("Notc", Uuid::new_v4()));
+        assert!(!MaintenanceConfiguration::default().bypasses("Notch", Uuid::parse_str(NOTCH).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn reloading_toggles_maintenance_when_the_file_does() {
+        let path = std::env::temp_dir().join(format!("rustyproxy-maintenance-{}.toml", Uuid::new_v4()));
+        let path = path.to_string_lossy().into_owned();
+        let write = |enabled: bool| {
+            let config = format!("proxy_port = 25565\n[maintenance]\nenabled = {}\nkick_online = true\n", enabled);
+            std::fs::write(&path, config).unwrap();
+        };
+
+        write(false);
+        let instance = crate::new_instance(ProxyConfiguration::from_file(&path).unwrap()).unwrap();
+        assert!(!instance.read().await.maintenance);
+
+        write(true);
+        crate::reload(&instance).await.unwrap();
+        assert!(instance.read().await.maintenance);
+
+        // Turned off at runtime, it stays off while the file says the same.
+        assert_eq!(set_maintenance(&instance, false).await, Some(0));
+        crate::reload(&instance).await.unwrap();
+        assert!(!instance.read().await.maintenance);
+
+        write(false);
+        crate::reload(&instance).await.unwrap();
+        assert!(!instance.read().await.maintenance);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
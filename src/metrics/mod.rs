@@ -0,0 +1,262 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::Error,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task,
+};
+use tracing::error;
+
+use crate::{
+    event::EventBus,
+    http::{self, Response},
+    packet::PacketDirection,
+    player::stats::ConnectionStats,
+    SharedProxyInstance,
+};
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Where to serve metrics, e.g. `[metrics]` with `port = 9225`.
+#[derive(Deserialize, Clone)]
+pub struct MetricsConfiguration {
+    /// Defaults to `127.0.0.1`, as metrics are not meant to be public.
+    pub address: Option<String>,
+    pub port: u16,
+}
+
+/// A latency histogram with fixed buckets.
+#[derive(Clone)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`.
+    counts: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: [0; LATENCY_BUCKETS.len() + 1],
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        Duration::from_secs_f64(self.sum)
+    }
+
+    /// Writes the samples of the histogram, `labels` being either empty or
+    /// e.g. `event="ServerSentPacket",`.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, labels, self.count());
+
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{}}}", labels),
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count());
+    }
+}
+
+/// Counters kept by the proxy for its metrics endpoint.
+#[derive(Default)]
+pub struct ProxyMetrics {
+    accepted: Mutex<BTreeMap<&'static str, u64>>,
+    rejected: Mutex<BTreeMap<&'static str, u64>>,
+    login_latency: Mutex<Histogram>,
+    backend_connect_failures: Mutex<BTreeMap<String, u64>>,
+    /// Bytes forwarded by connections that have closed since.
+    closed_playerbound_bytes: AtomicU64,
+    closed_proxybound_bytes: AtomicU64,
+    panics: AtomicU64,
+}
+
+impl ProxyMetrics {
+    /// Counts a connection that got what it came for, `kind` being `status`
+    /// or `login`.
+    pub fn connection_accepted(&self, kind: &'static str) {
+        *self.accepted.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    pub fn connection_rejected(&self, reason: &'static str) {
+        *self.rejected.lock().unwrap().entry(reason).or_default() += 1;
+    }
+
+    /// Records the time from accepting a player to forwarding its traffic.
+    pub fn login_completed(&self, latency: Duration) {
+        self.login_latency.lock().unwrap().observe(latency);
+    }
+
+    pub fn backend_connect_failed(&self, server: &str) {
+        *self
+            .backend_connect_failures
+            .lock()
+            .unwrap()
+            .entry(server.to_owned())
+            .or_default() += 1;
+    }
+
+    /// Keeps the traffic of a connection that is going away.
+    pub fn connection_closed(&self, stats: &ConnectionStats) {
+        self.closed_playerbound_bytes
+            .fetch_add(stats.bytes(PacketDirection::Playerbound), Ordering::Relaxed);
+        self.closed_proxybound_bytes
+            .fetch_add(stats.bytes(PacketDirection::Proxybound), Ordering::Relaxed);
+    }
+
+    pub fn panic_caught(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub async fn render(instance: &SharedProxyInstance, event_bus: &EventBus) -> String {
+    let (metrics, players, servers) = {
+        let instance = instance.read().await;
+        let players: Vec<_> = instance.players.values().cloned().collect();
+        let servers: Vec<_> = instance
+            .servers
+            .iter()
+            .map(|(key, server)| (key.clone(), Arc::clone(server)))
+            .collect();
+
+        (Arc::clone(&instance.metrics), players, servers)
+    };
+
+    let mut out = String::new();
+
+    header(&mut out, "rustyproxy_players_online", "gauge", "Players connected to the proxy.");
+    let _ = writeln!(out, "rustyproxy_players_online {}", players.len());
+
+    let mut per_server = vec![0u64; servers.len()];
+    let mut playerbound = metrics.closed_playerbound_bytes.load(Ordering::Relaxed);
+    let mut proxybound = metrics.closed_proxybound_bytes.load(Ordering::Relaxed);
+
+    for player in &players {
+        playerbound += player.stats().bytes(PacketDirection::Playerbound);
+        proxybound += player.stats().bytes(PacketDirection::Proxybound);
+
+        if let Some(server) = player.current_server().await {
+            if let Some(index) = servers.iter().position(|(_, known)| Arc::ptr_eq(known, &server)) {
+                per_server[index] += 1;
+            }
+        }
+    }
+
+    header(&mut out, "rustyproxy_server_players_online", "gauge", "Players connected to each server.");
+    for ((key, _), count) in servers.iter().zip(per_server) {
+        let _ = writeln!(out, "rustyproxy_server_players_online{{server=\"{}\"}} {}", escape(key), count);
+    }
+
+    header(&mut out, "rustyproxy_connections_accepted_total", "counter", "Connections served, by kind.");
+    for (kind, count) in metrics.accepted.lock().unwrap().iter() {
+        let _ = writeln!(out, "rustyproxy_connections_accepted_total{{kind=\"{}\"}} {}", kind, count);
+    }
+
+    header(&mut out, "rustyproxy_connections_rejected_total", "counter", "Connections turned away, by reason.");
+    for (reason, count) in metrics.rejected.lock().unwrap().iter() {
+        let _ = writeln!(out, "rustyproxy_connections_rejected_total{{reason=\"{}\"}} {}", reason, count);
+    }
+
+    header(&mut out, "rustyproxy_login_duration_seconds", "histogram", "Time from accepting a player to forwarding its traffic.");
+    metrics.login_latency.lock().unwrap().render(&mut out, "rustyproxy_login_duration_seconds", "");
+
+    header(&mut out, "rustyproxy_backend_connect_failures_total", "counter", "Failed connections to backend servers.");
+    for (server, count) in metrics.backend_connect_failures.lock().unwrap().iter() {
+        let _ = writeln!(out, "rustyproxy_backend_connect_failures_total{{server=\"{}\"}} {}", escape(server), count);
+    }
+
+    header(&mut out, "rustyproxy_forwarded_bytes_total", "counter", "Bytes forwarded between players and servers.");
+    let _ = writeln!(out, "rustyproxy_forwarded_bytes_total{{direction=\"playerbound\"}} {}", playerbound);
+    let _ = writeln!(out, "rustyproxy_forwarded_bytes_total{{direction=\"proxybound\"}} {}", proxybound);
+
+    header(&mut out, "rustyproxy_event_dispatch_duration_seconds", "histogram", "Time spent dispatching events, by event type.");
+    for (event, histogram) in event_bus.dispatch_latencies() {
+        let labels = format!("event=\"{}\",", event);
+        histogram.render(&mut out, "rustyproxy_event_dispatch_duration_seconds", &labels);
+    }
+
+    header(&mut out, "rustyproxy_panics_total", "counter", "Panics caught in connection tasks.");
+    let _ = writeln!(out, "rustyproxy_panics_total {}", metrics.panics.load(Ordering::Relaxed));
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Answers `GET /metrics` on `listener` until the proxy stops.
+pub async fn serve(listener: TcpListener, instance: SharedProxyInstance, event_bus: Arc<EventBus>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let instance = Arc::clone(&instance);
+                let event_bus = Arc::clone(&event_bus);
+
+                task::spawn(async move {
+                    let _ = respond(stream, &instance, &event_bus).await;
+                });
+            }
+            Err(e) => error!("Failed to accept metrics connection: {:?}", e),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, instance: &SharedProxyInstance, event_bus: &EventBus) -> Result<(), Error> {
+    let Some(request) = http::read_request(&mut stream).await? else {
+        return Ok(());
+    };
+
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => Response::new(
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            render(instance, event_bus).await,
+        ),
+        _ => Response::not_found(),
+    };
+
+    http::write_response(&mut stream, response).await
+}
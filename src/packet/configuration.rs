@@ -1,8 +1,13 @@
-use std::io::{Error, ErrorKind};
+use azalea_chat::FormattedText;
+use crab_nbt::NbtCompound;
 
-use crate::{player::PlayerConnection, server::plugin_channel::PluginChannel};
+use crate::server::plugin_channel::PluginChannel;
 
-use super::{data, handshake::HandshakePacket, Packet, PlayerboundPacket, ProxyboundPacket};
+use super::{
+    data::{ByteBuf, ByteBufMut, PacketField},
+    registry::PacketKind,
+    Packet, PacketError, PlayerboundPacket, ProxyboundPacket,
+};
 
 #[derive(Clone)]
 pub struct PlayerConfigurationPluginMessagePacket<T: PluginChannel> {
@@ -10,41 +15,137 @@ pub struct PlayerConfigurationPluginMessagePacket<T: PluginChannel> {
 }
 
 impl<T: PluginChannel> Packet for PlayerConfigurationPluginMessagePacket<T> {
-    fn id() -> u32 {
-        0x01
+    fn kind() -> PacketKind {
+        PacketKind::PlayerConfigurationPluginMessage
     }
-    async fn read_from(
-        connection: &mut PlayerConnection,
-    ) -> Result<Box<PlayerConfigurationPluginMessagePacket<T>>, Error> {
-        let (_, id, buffer) = connection.read_packet().await?;
-        if id != Self::id() {
-            return Err(Error::new(ErrorKind::Other, "id mismatch!"));
-        }
 
-        let mut position = 0 as usize;
-        let channel_name = data::read_string(&buffer, &mut position).unwrap();
+    fn read_body(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        let channel_name = buffer.read_identifier()?;
 
         if channel_name != T::id() {
-            return Err(Error::new(ErrorKind::Other, "channel id mismatch!"));
+            return Err(PacketError::UnexpectedChannel(channel_name));
         }
 
-        Ok(Box::new(PlayerConfigurationPluginMessagePacket {
-            data: *T::read_from(&mut buffer[position..].to_vec())
-                .await
-                .unwrap(),
-        }))
+        let data = T::read_from(&mut ByteBuf::new(buffer.read_remaining()))?;
+
+        Ok(PlayerConfigurationPluginMessagePacket { data })
     }
 
-    fn write_to(&self, buffer: &mut Vec<u8>) {
-        data::write_string(buffer, &T::id());
+    fn write_to(&self, buffer: &mut ByteBufMut) -> Result<(), PacketError> {
+        buffer.write_string(&T::id());
         self.data.write_to(buffer);
+        Ok(())
     }
 }
 
 impl<T: PluginChannel> PlayerboundPacket for PlayerConfigurationPluginMessagePacket<T> {}
 
+#[derive(Clone, Packet)]
+#[packet(kind = ConfigurationDisconnect)]
+pub struct ConfigurationDisconnectPacket {
+    #[nbt_text]
+    pub reason: FormattedText,
+}
+
+impl PlayerboundPacket for ConfigurationDisconnectPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = FinishConfiguration)]
+pub struct FinishConfigurationPacket {}
+
+impl PlayerboundPacket for FinishConfigurationPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = AcknowledgeFinishConfiguration)]
+pub struct AcknowledgeFinishConfigurationPacket {}
+
+impl ProxyboundPacket for AcknowledgeFinishConfigurationPacket {}
+
+/// A data pack both sides may have, e.g. `minecraft:core` of a version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownPack {
+    pub namespace: String,
+    pub id: String,
+    pub version: String,
+}
+
+impl PacketField for KnownPack {
+    fn write(&self, buffer: &mut ByteBufMut) {
+        buffer.write_string(&self.namespace);
+        buffer.write_string(&self.id);
+        buffer.write_string(&self.version);
+    }
+
+    fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        Ok(KnownPack {
+            namespace: buffer.read_string()?,
+            id: buffer.read_string()?,
+            version: buffer.read_string()?,
+        })
+    }
+}
+
+/// The packs the server would take registry data from. The client answers
+/// with those it has as well, whose data then need not be sent.
+#[derive(Clone, Packet)]
+#[packet(kind = ClientboundKnownPacks)]
+pub struct ClientboundKnownPacksPacket {
+    #[prefixed_array]
+    pub packs: Vec<KnownPack>,
+}
+
+impl PlayerboundPacket for ClientboundKnownPacksPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = ServerboundKnownPacks)]
+pub struct ServerboundKnownPacksPacket {
+    #[prefixed_array]
+    pub packs: Vec<KnownPack>,
+}
+
+impl ProxyboundPacket for ServerboundKnownPacksPacket {}
+
+/// An entry of a registry, whose data the client takes from a known pack
+/// when none is sent.
+#[derive(Clone, Debug)]
+pub struct RegistryEntry {
+    pub id: String,
+    pub data: Option<NbtCompound>,
+}
+
+impl PacketField for RegistryEntry {
+    fn write(&self, buffer: &mut ByteBufMut) {
+        buffer.write_string(&self.id);
+        buffer.write_optional(self.data.as_ref(), |buffer, data| buffer.write_nbt(Some(data)));
+    }
+
+    fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        Ok(RegistryEntry {
+            id: buffer.read_identifier()?,
+            data: buffer.read_optional(ByteBuf::read_nbt)?.flatten(),
+        })
+    }
+}
+
+/// The entries of one registry, e.g. `minecraft:dimension_type`.
+#[derive(Clone, Packet)]
+#[packet(kind = RegistryData)]
+pub struct RegistryDataPacket {
+    pub registry: String,
+    #[prefixed_array]
+    pub entries: Vec<RegistryEntry>,
+}
+
+impl PlayerboundPacket for RegistryDataPacket {}
+
 pub mod channels {
-    use crate::{packet::data, server::plugin_channel::PluginChannel};
+    use crate::{
+        packet::{
+            data::{ByteBuf, ByteBufMut},
+            PacketError,
+        },
+        server::plugin_channel::PluginChannel,
+    };
 
     #[derive(Clone)]
     pub struct BrandChannel {
@@ -56,16 +157,14 @@ pub mod channels {
             "minecraft:brand".to_owned()
         }
 
-        fn write_to(&self, buffer: &mut Vec<u8>) {
-            data::write_string(buffer, &self.brand);
+        fn write_to(&self, buffer: &mut ByteBufMut) {
+            buffer.write_string(&self.brand);
         }
 
-        async fn read_from(buffer: &mut Vec<u8>) -> Result<Box<Self>, std::io::Error> {
-            Ok(
-                Box::new(
-                    BrandChannel { brand: data::read_string(buffer, &mut 0).unwrap() }
-                )
-            )            
+        fn read_from(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+            Ok(BrandChannel {
+                brand: buffer.read_string()?,
+            })
         }
     }
 }
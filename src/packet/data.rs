@@ -0,0 +1,1127 @@
+use std::io::Cursor;
+
+use azalea_chat::FormattedText;
+use cesu8::{from_java_cesu8, to_java_cesu8};
+use crab_nbt::{serde::ser::to_bytes_unnamed, Nbt, NbtCompound, NbtTag};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::PacketError;
+
+/// Longest string the protocol allows, in characters.
+pub const MAX_STRING_LENGTH: usize = 32767;
+
+/// Longest JSON text component accepted by the vanilla client.
+pub const MAX_JSON_TEXT_LENGTH: usize = 262144;
+
+/// Deepest nesting of NBT lists and compounds accepted.
+pub const MAX_NBT_DEPTH: usize = 512;
+
+/// Longest NBT string, in bytes of Java's modified UTF-8.
+pub const MAX_NBT_STRING_LENGTH: usize = u16::MAX as usize;
+
+/// A block position, packed into a single long on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockPosition {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// A rotation in steps of 1/256 of a full turn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Angle(pub u8);
+
+impl Angle {
+    pub fn from_degrees(degrees: f32) -> Angle {
+        Angle((degrees.rem_euclid(360.0) / 360.0 * 256.0) as u8)
+    }
+
+    pub fn degrees(self) -> f32 {
+        self.0 as f32 * 360.0 / 256.0
+    }
+}
+
+/// A bit set sent as a length-prefixed array of longs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BitSet(pub Vec<i64>);
+
+impl BitSet {
+    pub fn get(&self, index: usize) -> bool {
+        self.0
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        if self.0.len() <= index / 64 {
+            self.0.resize(index / 64 + 1, 0);
+        }
+
+        if value {
+            self.0[index / 64] |= 1 << (index % 64);
+        } else {
+            self.0[index / 64] &= !(1 << (index % 64));
+        }
+    }
+}
+
+/// A non-empty item stack, as far as the proxy decodes it. From 1.20.5 on,
+/// components added to an item differ between versions and carry no length,
+/// so only stacks without any can be read or written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slot {
+    pub item_id: u32,
+    pub count: i32,
+    /// Item NBT, only sent by protocols before 1.20.5.
+    pub nbt: Option<NbtCompound>,
+    /// Default components removed from the item, 1.20.5 and newer.
+    pub removed_components: Vec<u32>,
+}
+
+/// Reads protocol data types from a packet body, failing instead of reading
+/// past its end.
+pub struct ByteBuf<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+macro_rules! read_number {
+    ($($name:ident: $ty:ty),*) => {
+        $(
+            pub fn $name(&mut self) -> Result<$ty, PacketError> {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+
+                let mut bytes = [0u8; SIZE];
+                bytes.copy_from_slice(self.read_bytes(SIZE)?);
+
+                Ok(<$ty>::from_be_bytes(bytes))
+            }
+        )*
+    };
+}
+
+impl<'a> ByteBuf<'a> {
+    pub fn new(buffer: &'a [u8]) -> ByteBuf<'a> {
+        ByteBuf {
+            buffer,
+            position: 0,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buffer.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], PacketError> {
+        if length > self.remaining() {
+            return Err(PacketError::Truncated {
+                needed: length,
+                remaining: self.remaining(),
+            });
+        }
+
+        let bytes = &self.buffer[self.position..self.position + length];
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    /// Everything left in the buffer, e.g. a plugin message payload.
+    pub fn read_remaining(&mut self) -> &'a [u8] {
+        let bytes = &self.buffer[self.position..];
+        self.position = self.buffer.len();
+        bytes
+    }
+
+    pub fn read_prefixed_bytes(&mut self, max_length: usize) -> Result<&'a [u8], PacketError> {
+        let length = self.read_length("byte array", max_length)?;
+        self.read_bytes(length)
+    }
+
+    read_number!(
+        read_u8: u8,
+        read_i8: i8,
+        read_u16: u16,
+        read_i16: i16,
+        read_i32: i32,
+        read_i64: i64,
+        read_f32: f32,
+        read_f64: f64
+    );
+
+    pub fn read_bool(&mut self) -> Result<bool, PacketError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u32, PacketError> {
+        let mut value: u32 = 0;
+
+        for shift in (0..35).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7F) as u32) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(PacketError::BadVarInt)
+    }
+
+    pub fn read_varlong(&mut self) -> Result<u64, PacketError> {
+        let mut value: u64 = 0;
+
+        for shift in (0..70).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(PacketError::BadVarInt)
+    }
+
+    /// Reads a VarInt length and checks it against `max_length` and the bytes
+    /// left, so a bogus length never causes a large allocation.
+    fn read_length(&mut self, what: &'static str, max_length: usize) -> Result<usize, PacketError> {
+        let length = self.read_varint()? as usize;
+
+        if length > max_length {
+            return Err(PacketError::Oversize {
+                what,
+                length,
+                max: max_length,
+            });
+        }
+
+        Ok(length)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, PacketError> {
+        self.read_string_bounded(MAX_STRING_LENGTH)
+    }
+
+    /// Reads a string of at most `max_length` characters.
+    pub fn read_string_bounded(&mut self, max_length: usize) -> Result<String, PacketError> {
+        // A character takes up to three bytes in the protocol's length limit.
+        let length = self.read_length("string", max_length * 3)?;
+        let bytes = self.read_bytes(length)?;
+
+        let string = std::str::from_utf8(bytes).map_err(|_| PacketError::InvalidUtf8)?;
+        let characters = string.encode_utf16().count();
+        if characters > max_length {
+            return Err(PacketError::Oversize {
+                what: "string",
+                length: characters,
+                max: max_length,
+            });
+        }
+
+        Ok(string.to_owned())
+    }
+
+    /// Reads a `namespace:path` identifier, defaulting the namespace to
+    /// `minecraft`.
+    pub fn read_identifier(&mut self) -> Result<String, PacketError> {
+        let identifier = self.read_string()?;
+
+        let (namespace, path) = identifier
+            .split_once(':')
+            .unwrap_or(("minecraft", identifier.as_str()));
+
+        let valid_namespace = namespace
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '.' | '-' | '_'));
+        let valid_path = path
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '.' | '-' | '_' | '/'));
+
+        if !valid_namespace || !valid_path {
+            return Err(PacketError::InvalidIdentifier(identifier));
+        }
+
+        Ok(format!("{}:{}", namespace, path))
+    }
+
+    pub fn read_uuid(&mut self) -> Result<Uuid, PacketError> {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(self.read_bytes(16)?);
+
+        Ok(Uuid::from_bytes(bytes))
+    }
+
+    pub fn read_position(&mut self) -> Result<BlockPosition, PacketError> {
+        let value = self.read_i64()?;
+
+        Ok(BlockPosition {
+            x: (value >> 38) as i32,
+            y: (value << 52 >> 52) as i32,
+            z: (value << 26 >> 38) as i32,
+        })
+    }
+
+    pub fn read_angle(&mut self) -> Result<Angle, PacketError> {
+        Ok(Angle(self.read_u8()?))
+    }
+
+    pub fn read_bitset(&mut self) -> Result<BitSet, PacketError> {
+        let words = self.read_length("bit set", self.remaining() / 8)?;
+        let mut bits = Vec::with_capacity(words);
+
+        for _ in 0..words {
+            bits.push(self.read_i64()?);
+        }
+
+        Ok(BitSet(bits))
+    }
+
+    /// Reads a bit set of a size known in advance, sent without a length.
+    pub fn read_fixed_bitset(&mut self, bits: usize) -> Result<Vec<u8>, PacketError> {
+        Ok(self.read_bytes(bits.div_ceil(8))?.to_vec())
+    }
+
+    /// Reads a value preceded by a boolean telling whether it is present.
+    pub fn read_optional<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, PacketError>,
+    ) -> Result<Option<T>, PacketError> {
+        if self.read_bool()? {
+            Ok(Some(read(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads up to `max_length` values preceded by their VarInt count.
+    pub fn read_prefixed_array<T>(
+        &mut self,
+        max_length: usize,
+        mut read: impl FnMut(&mut Self) -> Result<T, PacketError>,
+    ) -> Result<Vec<T>, PacketError> {
+        // Every element takes at least a byte, which bounds the allocation.
+        let length = self.read_length("array", max_length.min(self.remaining()))?;
+        let mut items = Vec::with_capacity(length);
+
+        for _ in 0..length {
+            items.push(read(self)?);
+        }
+
+        Ok(items)
+    }
+
+    /// Reads a network NBT tag without decoding it, checking its structure
+    /// along the way.
+    pub fn read_nbt_bytes(&mut self) -> Result<&'a [u8], PacketError> {
+        let start = self.position;
+        let tag = self.read_u8()?;
+        self.skip_nbt_payload(tag, 0)?;
+
+        Ok(&self.buffer[start..self.position])
+    }
+
+    /// Reads a network NBT compound; `None` if an empty tag was sent.
+    pub fn read_nbt(&mut self) -> Result<Option<NbtCompound>, PacketError> {
+        let bytes = self.read_nbt_bytes()?;
+
+        match bytes[0] {
+            NBT_END => Ok(None),
+            NBT_COMPOUND => Nbt::read_unnamed(&mut Cursor::new(bytes))
+                .map(|nbt| Some(nbt.root_tag))
+                .map_err(|e| PacketError::BadNbt(e.to_string())),
+            tag => Err(PacketError::BadNbt(format!(
+                "expected a compound, got tag {}",
+                tag
+            ))),
+        }
+    }
+
+    /// Reads a text component sent as NBT (1.20.3 and newer).
+    pub fn read_nbt_text(&mut self) -> Result<FormattedText, PacketError> {
+        let bytes = self.read_nbt_bytes()?;
+
+        if bytes[0] == NBT_STRING {
+            let mut string = ByteBuf::new(&bytes[1..]);
+            let length = string.read_u16()? as usize;
+            let text = from_java_cesu8(string.read_bytes(length)?).map_err(|_| PacketError::InvalidUtf8)?;
+
+            return Ok(FormattedText::from(text.as_ref()));
+        }
+
+        // Components deserialize from any value, which the NBT deserializer
+        // cannot do, so they are decoded from the equivalent JSON instead.
+        let nbt = Nbt::read_unnamed(&mut Cursor::new(bytes)).map_err(|e| PacketError::BadNbt(e.to_string()))?;
+
+        FormattedText::deserialize(&nbt_to_json(&NbtTag::Compound(nbt.root_tag)))
+            .map_err(|e| PacketError::BadNbt(e.to_string()))
+    }
+
+    /// Reads a text component sent as a JSON string.
+    pub fn read_json_text(&mut self) -> Result<FormattedText, PacketError> {
+        let json = self.read_string_bounded(MAX_JSON_TEXT_LENGTH)?;
+        let value: Value =
+            serde_json::from_str(&json).map_err(|e| PacketError::BadJson(e.to_string()))?;
+
+        FormattedText::deserialize(&value).map_err(|e| PacketError::BadJson(e.to_string()))
+    }
+
+    /// Reads an item stack in the format used by `protocol`; `None` for an
+    /// empty slot. Fails with `Unsupported` for a stack with added
+    /// components, see `Slot`.
+    pub fn read_slot(&mut self, protocol: u32) -> Result<Option<Slot>, PacketError> {
+        if protocol < 766 {
+            if !self.read_bool()? {
+                return Ok(None);
+            }
+
+            return Ok(Some(Slot {
+                item_id: self.read_varint()?,
+                count: self.read_i8()? as i32,
+                nbt: self.read_nbt()?,
+                removed_components: Vec::new(),
+            }));
+        }
+
+        let count = self.read_varint()? as i32;
+        if count <= 0 {
+            return Ok(None);
+        }
+
+        let item_id = self.read_varint()?;
+        let added = self.read_varint()?;
+        let removed = self.read_varint()? as usize;
+
+        // Component data has no length prefix, so it can only be skipped by
+        // knowing every component type.
+        if added > 0 {
+            return Err(PacketError::Unsupported("item stack components"));
+        }
+
+        let removed_components = self.read_prefixed_count(removed, |buffer| buffer.read_varint())?;
+
+        Ok(Some(Slot {
+            item_id,
+            count,
+            nbt: None,
+            removed_components,
+        }))
+    }
+
+    fn read_prefixed_count<T>(
+        &mut self,
+        count: usize,
+        mut read: impl FnMut(&mut Self) -> Result<T, PacketError>,
+    ) -> Result<Vec<T>, PacketError> {
+        if count > self.remaining() {
+            return Err(PacketError::Truncated {
+                needed: count,
+                remaining: self.remaining(),
+            });
+        }
+
+        (0..count).map(|_| read(self)).collect()
+    }
+
+    fn skip_nbt_payload(&mut self, tag: u8, depth: usize) -> Result<(), PacketError> {
+        if depth > MAX_NBT_DEPTH {
+            return Err(PacketError::BadNbt("nested too deeply".to_owned()));
+        }
+
+        match tag {
+            NBT_END => (),
+            1 => {
+                self.read_bytes(1)?;
+            }
+            2 => {
+                self.read_bytes(2)?;
+            }
+            3 | 5 => {
+                self.read_bytes(4)?;
+            }
+            4 | 6 => {
+                self.read_bytes(8)?;
+            }
+            7 => {
+                let length = self.read_nbt_length()?;
+                self.read_bytes(length)?;
+            }
+            NBT_STRING => {
+                let length = self.read_u16()? as usize;
+                self.read_bytes(length)?;
+            }
+            9 => {
+                let element = self.read_u8()?;
+                let length = self.read_nbt_length()?;
+
+                if element == NBT_END && length > 0 {
+                    return Err(PacketError::BadNbt("list of end tags".to_owned()));
+                }
+                if length > self.remaining() {
+                    return Err(PacketError::Truncated {
+                        needed: length,
+                        remaining: self.remaining(),
+                    });
+                }
+
+                for _ in 0..length {
+                    self.skip_nbt_payload(element, depth + 1)?;
+                }
+            }
+            NBT_COMPOUND => loop {
+                let child = self.read_u8()?;
+                if child == NBT_END {
+                    break;
+                }
+
+                let name_length = self.read_u16()? as usize;
+                self.read_bytes(name_length)?;
+                self.skip_nbt_payload(child, depth + 1)?;
+            },
+            11 => {
+                let length = self.read_nbt_length()?;
+                self.read_bytes(length.saturating_mul(4))?;
+            }
+            12 => {
+                let length = self.read_nbt_length()?;
+                self.read_bytes(length.saturating_mul(8))?;
+            }
+            tag => return Err(PacketError::BadNbt(format!("unknown tag {}", tag))),
+        }
+
+        Ok(())
+    }
+
+    fn read_nbt_length(&mut self) -> Result<usize, PacketError> {
+        let length = self.read_i32()?;
+        if length < 0 {
+            return Err(PacketError::BadNbt("negative length".to_owned()));
+        }
+
+        Ok(length as usize)
+    }
+}
+
+const NBT_END: u8 = 0;
+const NBT_STRING: u8 = 8;
+const NBT_COMPOUND: u8 = 10;
+
+/// `string` in Java's modified UTF-8, as NBT stores strings.
+fn nbt_string(string: &str) -> Result<std::borrow::Cow<'_, [u8]>, PacketError> {
+    let bytes = to_java_cesu8(string);
+    if bytes.len() > MAX_NBT_STRING_LENGTH {
+        return Err(PacketError::Oversize {
+            what: "NBT string",
+            length: bytes.len(),
+            max: MAX_NBT_STRING_LENGTH,
+        });
+    }
+
+    Ok(bytes)
+}
+
+/// Checks that every string and key in `value` fits in an NBT string.
+fn check_nbt_strings(value: &Value) -> Result<(), PacketError> {
+    match value {
+        Value::String(string) => nbt_string(string).map(|_| ()),
+        Value::Array(values) => values.iter().try_for_each(check_nbt_strings),
+        Value::Object(map) => map.iter().try_for_each(|(key, value)| {
+            nbt_string(key)?;
+            check_nbt_strings(value)
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The JSON form of a tag, with bytes of 0 and 1 as the booleans they stand
+/// for in text components.
+fn nbt_to_json(tag: &NbtTag) -> Value {
+    match tag {
+        NbtTag::End => Value::Null,
+        NbtTag::Byte(value @ (0 | 1)) => Value::Bool(*value == 1),
+        NbtTag::Byte(value) => Value::from(*value),
+        NbtTag::Short(value) => Value::from(*value),
+        NbtTag::Int(value) => Value::from(*value),
+        NbtTag::Long(value) => Value::from(*value),
+        NbtTag::Float(value) => Value::from(*value),
+        NbtTag::Double(value) => Value::from(*value),
+        NbtTag::ByteArray(bytes) => bytes.iter().map(|byte| Value::from(*byte as i8)).collect(),
+        NbtTag::String(value) => Value::from(value.as_str()),
+        NbtTag::List(tags) => tags.iter().map(nbt_to_json).collect(),
+        NbtTag::Compound(compound) => Value::Object(
+            compound
+                .child_tags
+                .iter()
+                .map(|(name, tag)| (name.clone(), nbt_to_json(tag)))
+                .collect(),
+        ),
+        NbtTag::IntArray(values) => values.iter().copied().collect(),
+        NbtTag::LongArray(values) => values.iter().copied().collect(),
+    }
+}
+
+/// Writes protocol data types into a growing packet body.
+#[derive(Clone, Debug, Default)]
+pub struct ByteBufMut {
+    buffer: Vec<u8>,
+}
+
+macro_rules! write_number {
+    ($($name:ident: $ty:ty),*) => {
+        $(
+            pub fn $name(&mut self, value: $ty) {
+                self.buffer.extend_from_slice(&value.to_be_bytes());
+            }
+        )*
+    };
+}
+
+impl ByteBufMut {
+    pub fn new() -> ByteBufMut {
+        ByteBufMut::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> ByteBufMut {
+        ByteBufMut {
+            buffer: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn write_prefixed_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u32);
+        self.write_bytes(bytes);
+    }
+
+    write_number!(
+        write_u8: u8,
+        write_i8: i8,
+        write_u16: u16,
+        write_i16: i16,
+        write_i32: i32,
+        write_i64: i64,
+        write_f32: f32,
+        write_f64: f64
+    );
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_varint(&mut self, mut value: u32) {
+        while value >= 0x80 {
+            self.buffer.push(((value & 0x7F) | 0x80) as u8); // Add CONTINUE_BIT (0x80) if more bytes follow
+            value >>= 7;
+        }
+        self.buffer.push((value & 0x7F) as u8); // Final byte without CONTINUE_BIT
+    }
+
+    pub fn write_varlong(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buffer.push(((value & 0x7F) | 0x80) as u8);
+            value >>= 7;
+        }
+        self.buffer.push((value & 0x7F) as u8);
+    }
+
+    pub fn write_string(&mut self, value: &str) {
+        self.write_prefixed_bytes(value.as_bytes());
+    }
+
+    pub fn write_uuid(&mut self, uuid: &Uuid) {
+        self.write_bytes(uuid.as_bytes());
+    }
+
+    pub fn write_position(&mut self, position: BlockPosition) {
+        self.write_i64(
+            ((position.x as i64 & 0x3FFFFFF) << 38)
+                | ((position.z as i64 & 0x3FFFFFF) << 12)
+                | (position.y as i64 & 0xFFF),
+        );
+    }
+
+    pub fn write_angle(&mut self, angle: Angle) {
+        self.write_u8(angle.0);
+    }
+
+    pub fn write_bitset(&mut self, bits: &BitSet) {
+        self.write_varint(bits.0.len() as u32);
+        for word in &bits.0 {
+            self.write_i64(*word);
+        }
+    }
+
+    pub fn write_optional<T>(&mut self, value: Option<&T>, write: impl FnOnce(&mut Self, &T)) {
+        self.write_bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    pub fn write_prefixed_array<T>(&mut self, values: &[T], mut write: impl FnMut(&mut Self, &T)) {
+        self.write_varint(values.len() as u32);
+        for value in values {
+            write(self, value);
+        }
+    }
+
+    /// Writes a network NBT compound, or an empty tag for `None`.
+    pub fn write_nbt(&mut self, compound: Option<&NbtCompound>) {
+        match compound {
+            Some(compound) => {
+                self.write_bytes(&Nbt::new(String::new(), compound.clone()).write_unnamed())
+            }
+            None => self.write_u8(NBT_END),
+        }
+    }
+
+    /// Writes a text component as NBT, failing if one of its strings is
+    /// too long for an NBT string, which crab_nbt would cut off silently.
+    pub fn write_nbt_text(&mut self, text: &FormattedText) -> Result<(), PacketError> {
+        let json = serde_json::to_value(text).map_err(|e| PacketError::BadJson(e.to_string()))?;
+        check_nbt_strings(&json)?;
+
+        match to_bytes_unnamed(text) {
+            Ok(bytes) => self.write_bytes(&bytes),
+            // Every component serializes; fall back to its plain text.
+            Err(_) => {
+                let text = text.to_string();
+                let text = nbt_string(&text)?;
+                self.write_u8(NBT_STRING);
+                self.write_u16(text.len() as u16);
+                self.write_bytes(&text);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_json_text(&mut self, text: &FormattedText) {
+        self.write_string(&serde_json::to_string(text).unwrap_or_default());
+    }
+
+    /// Writes an item stack in the format used by `protocol`, without any
+    /// added components, see `Slot`.
+    pub fn write_slot(&mut self, slot: Option<&Slot>, protocol: u32) {
+        if protocol < 766 {
+            self.write_optional(slot, |buffer, slot| {
+                buffer.write_varint(slot.item_id);
+                buffer.write_i8(slot.count as i8);
+                buffer.write_nbt(slot.nbt.as_ref());
+            });
+            return;
+        }
+
+        match slot {
+            None => self.write_varint(0),
+            Some(slot) => {
+                self.write_varint(slot.count as u32);
+                self.write_varint(slot.item_id);
+                self.write_varint(0);
+                self.write_varint(slot.removed_components.len() as u32);
+                for component in &slot.removed_components {
+                    self.write_varint(*component);
+                }
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for ByteBufMut {
+    fn from(buffer: Vec<u8>) -> Self {
+        ByteBufMut { buffer }
+    }
+}
+
+pub fn varint_size(mut value: u32) -> usize {
+    let mut size = 0;
+    loop {
+        size += 1;
+        if value & !0x7F == 0 {
+            break;
+        }
+        value >>= 7;
+    }
+    size
+}
+
+/// A value with a single wire representation, read and written by
+/// `#[derive(Packet)]` for fields without an encoding attribute.
+pub trait PacketField: Sized {
+    fn write(&self, buffer: &mut ByteBufMut);
+    fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError>;
+}
+
+macro_rules! packet_field {
+    ($($ty:ty: $read:ident, $write:ident);* $(;)?) => {
+        $(
+            impl PacketField for $ty {
+                fn write(&self, buffer: &mut ByteBufMut) {
+                    buffer.$write(*self);
+                }
+
+                fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+                    buffer.$read()
+                }
+            }
+        )*
+    };
+}
+
+packet_field!(
+    u8: read_u8, write_u8;
+    i8: read_i8, write_i8;
+    u16: read_u16, write_u16;
+    i16: read_i16, write_i16;
+    i32: read_i32, write_i32;
+    i64: read_i64, write_i64;
+    f32: read_f32, write_f32;
+    f64: read_f64, write_f64;
+    bool: read_bool, write_bool;
+    BlockPosition: read_position, write_position;
+    Angle: read_angle, write_angle;
+);
+
+impl PacketField for String {
+    fn write(&self, buffer: &mut ByteBufMut) {
+        buffer.write_string(self);
+    }
+
+    fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        buffer.read_string()
+    }
+}
+
+impl PacketField for Uuid {
+    fn write(&self, buffer: &mut ByteBufMut) {
+        buffer.write_uuid(self);
+    }
+
+    fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        buffer.read_uuid()
+    }
+}
+
+impl PacketField for BitSet {
+    fn write(&self, buffer: &mut ByteBufMut) {
+        buffer.write_bitset(self);
+    }
+
+    fn read(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        buffer.read_bitset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(write: impl FnOnce(&mut ByteBufMut)) -> Vec<u8> {
+        let mut buffer = ByteBufMut::new();
+        write(&mut buffer);
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 255, 25565, 2097151, i32::MAX as u32, u32::MAX] {
+            let bytes = written(|buffer| buffer.write_varint(value));
+            assert_eq!(bytes.len(), varint_size(value));
+
+            let mut buffer = ByteBuf::new(&bytes);
+            assert_eq!(buffer.read_varint().unwrap(), value);
+            assert!(buffer.is_empty());
+        }
+
+        for value in [0, 300, u32::MAX as u64 + 1, i64::MAX as u64, u64::MAX] {
+            let bytes = written(|buffer| buffer.write_varlong(value));
+            assert_eq!(ByteBuf::new(&bytes).read_varlong().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn overlong_varints_are_rejected() {
+        let bytes = [0x80; 6];
+        assert!(matches!(ByteBuf::new(&bytes).read_varint(), Err(PacketError::BadVarInt)));
+
+        let bytes = [0x80; 11];
+        assert!(matches!(ByteBuf::new(&bytes).read_varlong(), Err(PacketError::BadVarInt)));
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        assert!(matches!(ByteBuf::new(&[0x80, 0x80]).read_varint(), Err(PacketError::Truncated { .. })));
+        assert!(matches!(
+            ByteBuf::new(&[1, 2, 3]).read_i32(),
+            Err(PacketError::Truncated { needed: 4, remaining: 3 })
+        ));
+        assert!(matches!(ByteBuf::new(&[0; 15]).read_uuid(), Err(PacketError::Truncated { .. })));
+        assert!(matches!(ByteBuf::new(&[5, b'a', b'b']).read_string(), Err(PacketError::Truncated { .. })));
+
+        // A compound that never ends
+        assert!(matches!(
+            ByteBuf::new(&[NBT_COMPOUND, 1, 0, 1, b'a', 7]).read_nbt_bytes(),
+            Err(PacketError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn lengths_beyond_the_buffer_are_rejected() {
+        let bytes = written(|buffer| buffer.write_varint(1_000_000));
+
+        assert!(matches!(ByteBuf::new(&bytes).read_prefixed_bytes(usize::MAX), Err(PacketError::Truncated { .. })));
+        assert!(matches!(
+            ByteBuf::new(&bytes).read_prefixed_array(usize::MAX, |buffer| buffer.read_u8()),
+            Err(PacketError::Oversize { length: 1_000_000, .. })
+        ));
+        assert!(matches!(ByteBuf::new(&bytes).read_bitset(), Err(PacketError::Oversize { .. })));
+
+        // An NBT list claiming more elements than there are bytes
+        let mut list = vec![9, 1];
+        list.extend_from_slice(&i32::MAX.to_be_bytes());
+        assert!(matches!(ByteBuf::new(&list).read_nbt_bytes(), Err(PacketError::Truncated { .. })));
+
+        let mut list = vec![9, 1];
+        list.extend_from_slice(&(-1i32).to_be_bytes());
+        assert!(matches!(ByteBuf::new(&list).read_nbt_bytes(), Err(PacketError::BadNbt(_))));
+    }
+
+    #[test]
+    fn strings_are_bounded() {
+        let bytes = written(|buffer| buffer.write_string("abcdef"));
+        assert!(matches!(
+            ByteBuf::new(&bytes).read_string_bounded(5),
+            Err(PacketError::Oversize { length: 6, max: 5, .. })
+        ));
+        assert_eq!(ByteBuf::new(&bytes).read_string_bounded(6).unwrap(), "abcdef");
+
+        assert!(matches!(ByteBuf::new(&[2, 0xC3, 0x28]).read_string(), Err(PacketError::InvalidUtf8)));
+    }
+
+    /// A list of lists `depth` levels deep.
+    fn nested_lists(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![9];
+        for _ in 1..depth {
+            bytes.push(9);
+            bytes.extend_from_slice(&1i32.to_be_bytes());
+        }
+        bytes.push(NBT_END);
+        bytes.extend_from_slice(&0i32.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn deep_nbt_is_rejected() {
+        let bytes = nested_lists(MAX_NBT_DEPTH + 1);
+        let mut buffer = ByteBuf::new(&bytes);
+        assert_eq!(buffer.read_nbt_bytes().unwrap().len(), bytes.len());
+
+        let bytes = nested_lists(MAX_NBT_DEPTH + 2);
+        assert!(matches!(ByteBuf::new(&bytes).read_nbt_bytes(), Err(PacketError::BadNbt(_))));
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        for position in [
+            BlockPosition { x: 0, y: 0, z: 0 },
+            BlockPosition { x: 18357644, y: 831, z: -20882616 },
+            BlockPosition { x: -33554432, y: -2048, z: 33554431 },
+            BlockPosition { x: 33554431, y: 2047, z: -33554432 },
+        ] {
+            let bytes = written(|buffer| buffer.write_position(position));
+            assert_eq!(ByteBuf::new(&bytes).read_position().unwrap(), position);
+        }
+
+        // The example from the protocol documentation
+        let bytes = 0x4607_632C_15B4_833Fu64.to_be_bytes();
+        assert_eq!(
+            ByteBuf::new(&bytes).read_position().unwrap(),
+            BlockPosition { x: 18357644, y: 831, z: -20882616 }
+        );
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let uuid = Uuid::from_u128(0x069a79f4_44e9_4726_a5be_fca90e38aaf5);
+        let bits = BitSet(vec![5, -1]);
+
+        let bytes = written(|buffer| {
+            buffer.write_u8(200);
+            buffer.write_i16(-2);
+            buffer.write_i64(i64::MIN);
+            buffer.write_f64(0.25);
+            buffer.write_bool(true);
+            buffer.write_string("héllo");
+            buffer.write_uuid(&uuid);
+            buffer.write_angle(Angle::from_degrees(90.0));
+            buffer.write_bitset(&bits);
+            buffer.write_optional(Some(&7), |buffer, value| buffer.write_i32(*value));
+            buffer.write_optional(None::<&i32>, |buffer, value| buffer.write_i32(*value));
+            buffer.write_prefixed_array(&[1u32, 300], |buffer, value| buffer.write_varint(*value));
+            buffer.write_prefixed_bytes(&[9, 8]);
+        });
+
+        let mut buffer = ByteBuf::new(&bytes);
+        assert_eq!(buffer.read_u8().unwrap(), 200);
+        assert_eq!(buffer.read_i16().unwrap(), -2);
+        assert_eq!(buffer.read_i64().unwrap(), i64::MIN);
+        assert_eq!(buffer.read_f64().unwrap(), 0.25);
+        assert!(buffer.read_bool().unwrap());
+        assert_eq!(buffer.read_string().unwrap(), "héllo");
+        assert_eq!(buffer.read_uuid().unwrap(), uuid);
+        assert_eq!(buffer.read_angle().unwrap().degrees(), 90.0);
+        assert_eq!(buffer.read_bitset().unwrap(), bits);
+        assert_eq!(buffer.read_optional(|buffer| buffer.read_i32()).unwrap(), Some(7));
+        assert_eq!(buffer.read_optional(|buffer| buffer.read_i32()).unwrap(), None);
+        assert_eq!(buffer.read_prefixed_array(16, |buffer| buffer.read_varint()).unwrap(), [1, 300]);
+        assert_eq!(buffer.read_prefixed_bytes(16).unwrap(), [9, 8]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn identifiers_default_to_minecraft() {
+        let bytes = written(|buffer| buffer.write_string("stone"));
+        assert_eq!(ByteBuf::new(&bytes).read_identifier().unwrap(), "minecraft:stone");
+
+        let bytes = written(|buffer| buffer.write_string("Bad:Name"));
+        assert!(matches!(ByteBuf::new(&bytes).read_identifier(), Err(PacketError::InvalidIdentifier(_))));
+    }
+
+    #[test]
+    fn nbt_round_trips() {
+        let mut compound = NbtCompound::new();
+        compound.put("name".to_owned(), "rustyproxy".to_owned());
+        compound.put("depth".to_owned(), 3);
+
+        let bytes = written(|buffer| {
+            buffer.write_nbt(Some(&compound));
+            buffer.write_nbt(None);
+        });
+
+        let mut buffer = ByteBuf::new(&bytes);
+        assert_eq!(buffer.read_nbt().unwrap(), Some(compound));
+        assert_eq!(buffer.read_nbt().unwrap(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let json = serde_json::json!({
+            "text": "Hello",
+            "color": "green",
+            "bold": true,
+            "extra": [{ "text": " world", "italic": false }],
+        });
+        let text = FormattedText::deserialize(&json).unwrap();
+        let expected = serde_json::to_value(&text).unwrap();
+
+        let bytes = written(|buffer| buffer.write_nbt_text(&text).unwrap());
+        let decoded = ByteBuf::new(&bytes).read_nbt_text().unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+
+        let bytes = written(|buffer| buffer.write_json_text(&text));
+        let decoded = ByteBuf::new(&bytes).read_json_text().unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), expected);
+    }
+
+    #[test]
+    fn slots_round_trip() {
+        let mut nbt = NbtCompound::new();
+        nbt.put("Damage".to_owned(), 4);
+
+        for (protocol, slot) in [
+            (765, Slot { item_id: 1, count: 64, nbt: Some(nbt), removed_components: Vec::new() }),
+            (769, Slot { item_id: 812, count: 1, nbt: None, removed_components: vec![3, 5] }),
+        ] {
+            let bytes = written(|buffer| {
+                buffer.write_slot(Some(&slot), protocol);
+                buffer.write_slot(None, protocol);
+            });
+
+            let mut buffer = ByteBuf::new(&bytes);
+            assert_eq!(buffer.read_slot(protocol).unwrap(), Some(slot));
+            assert_eq!(buffer.read_slot(protocol).unwrap(), None);
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn nbt_text_uses_modified_utf8() {
+        // NUL and characters outside the BMP are encoded differently than
+        // in UTF-8, as two bytes and as a surrogate pair.
+        let string = "a\0\u{1F600}";
+        let encoded = [b'a', 0xC0, 0x80, 0xED, 0xA0, 0xBD, 0xED, 0xB8, 0x80];
+
+        let mut bytes = vec![NBT_STRING, 0, encoded.len() as u8];
+        bytes.extend_from_slice(&encoded);
+        assert_eq!(ByteBuf::new(&bytes).read_nbt_text().unwrap().to_string(), string);
+
+        let text = FormattedText::from(string);
+        let bytes = written(|buffer| buffer.write_nbt_text(&text).unwrap());
+        assert!(bytes.windows(encoded.len()).any(|window| window == encoded));
+        assert_eq!(ByteBuf::new(&bytes).read_nbt_text().unwrap().to_string(), string);
+
+        let bytes = [NBT_STRING, 0, 2, b'a', 0xFF];
+        assert!(matches!(ByteBuf::new(&bytes).read_nbt_text(), Err(PacketError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn nbt_text_is_bounded() {
+        let longest = FormattedText::from("a".repeat(MAX_NBT_STRING_LENGTH).as_str());
+        let bytes = written(|buffer| buffer.write_nbt_text(&longest).unwrap());
+        assert_eq!(ByteBuf::new(&bytes).read_nbt_text().unwrap().to_string().len(), MAX_NBT_STRING_LENGTH);
+
+        // 11000 characters of six bytes each.
+        for text in ["a".repeat(MAX_NBT_STRING_LENGTH + 1), "\u{1F600}".repeat(11000)] {
+            let mut buffer = ByteBufMut::new();
+            assert!(matches!(
+                buffer.write_nbt_text(&FormattedText::from(text.as_str())),
+                Err(PacketError::Oversize { what: "NBT string", max: MAX_NBT_STRING_LENGTH, .. })
+            ));
+            assert!(buffer.as_slice().is_empty());
+        }
+    }
+
+    #[test]
+    fn slots_with_added_components_are_unsupported() {
+        let mut bytes = ByteBufMut::new();
+        bytes.write_varint(1); // Count
+        bytes.write_varint(812); // Item
+        bytes.write_varint(1); // Added components
+        bytes.write_varint(0); // Removed components
+
+        assert!(matches!(
+            ByteBuf::new(bytes.as_slice()).read_slot(769),
+            Err(PacketError::Unsupported(_))
+        ));
+    }
+}
@@ -1,80 +1,30 @@
-use std::io::{Error, ErrorKind};
-
 use azalea_chat::FormattedText;
-use serde::Deserialize;
-use serde_json::{self, Value};
 use uuid::Uuid;
 
-use crate::player::{PlayerConnection, PlayerInfo};
+use crate::player::PlayerInfo;
 
-use super::{data, Packet, PlayerboundPacket, ProxyboundPacket};
+use super::{
+    data::{ByteBuf, ByteBufMut},
+    registry::PacketKind,
+    Packet, PacketError, PlayerboundPacket, ProxyboundPacket,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Packet)]
+#[packet(kind = LoginDisconnect)]
 pub struct LoginDisconnectPacket {
+    #[json_text]
     pub reason: FormattedText, // Perhaps there is a library for text components
 }
 
-impl Packet for LoginDisconnectPacket {
-    fn id() -> u32 {
-        0x00
-    }
-
-    fn write_to(&self, buffer: &mut Vec<u8>) {
-        data::write_string(buffer, &serde_json::to_string(&self.reason).unwrap());
-    }
-
-    async fn read_from(
-        connection: &mut PlayerConnection,
-    ) -> Result<Box<LoginDisconnectPacket>, Error> {
-        let (_, id, buffer) = connection.read_packet().await?;
-        if id != Self::id() {
-            return Err(Error::new(ErrorKind::Other, "id mismatch!"));
-        }
-
-        let mut position = 0 as usize;
-
-        let j: Value =
-            serde_json::from_str(&data::read_string(&buffer, &mut position).unwrap()).unwrap();
-
-        Ok(Box::new(LoginDisconnectPacket {
-            reason: FormattedText::deserialize(&j).unwrap(),
-        }))
-    }
-}
-
 impl PlayerboundPacket for LoginDisconnectPacket {}
 
-#[derive(Clone)]
+#[derive(Clone, Packet)]
+#[packet(kind = LoginStart)]
 pub struct LoginStartPacket {
     pub username: String,
     pub uuid: Uuid,
 }
 
-impl Packet for LoginStartPacket {
-    fn id() -> u32 {
-        0x00
-    }
-
-    fn write_to(&self, buffer: &mut Vec<u8>) {
-        data::write_string(buffer, &self.username);
-        data::write_uuid(buffer, &self.uuid);
-    }
-
-    async fn read_from(connection: &mut PlayerConnection) -> Result<Box<LoginStartPacket>, Error> {
-        let (_, id, buffer) = connection.read_packet().await?;
-        if id != Self::id() {
-            return Err(Error::new(ErrorKind::Other, "id mismatch!"));
-        }
-
-        let mut position = 0 as usize;
-
-        Ok(Box::new(LoginStartPacket {
-            username: data::read_string(&buffer, &mut position).unwrap(),
-            uuid: data::read_uuid(&buffer, &mut position).unwrap(),
-        }))
-    }
-}
-
 impl PlayerboundPacket for LoginStartPacket {}
 impl ProxyboundPacket for LoginStartPacket {}
 
@@ -87,26 +37,67 @@ impl LoginStartPacket {
     }
 }
 
-#[derive(Clone)]
-pub struct LoginSuccessPacket {}
+/// Ends the login with the player's profile. Profile properties, such as
+/// skins, are neither sent nor kept.
+#[derive(Clone, Default)]
+pub struct LoginSuccessPacket {
+    pub uuid: Uuid,
+    pub username: String,
+    /// Whether the client disconnects on packets it cannot handle; only
+    /// sent to 1.20.5 to 1.21.1 clients.
+    pub strict_error_handling: Option<bool>,
+}
 
 impl Packet for LoginSuccessPacket {
-    fn id() -> u32 {
-        0x02
+    fn kind() -> PacketKind {
+        PacketKind::LoginSuccess
     }
 
-    fn write_to(&self, _: &mut Vec<u8>) {
-        ()
-    }
-
-    async fn read_from(connection: &mut PlayerConnection) -> Result<Box<Self>, Error> {
-        let (_, id, _) = connection.read_packet().await?;
-        if id != Self::id() {
-            return Err(Error::new(ErrorKind::Other, "id mismatch!"));
+    fn write_to(&self, buffer: &mut ByteBufMut) -> Result<(), PacketError> {
+        buffer.write_uuid(&self.uuid);
+        buffer.write_string(&self.username);
+        buffer.write_varint(0);
+        if let Some(strict_error_handling) = self.strict_error_handling {
+            buffer.write_bool(strict_error_handling);
         }
+        Ok(())
+    }
 
-        Ok(Box::new(LoginSuccessPacket {}))
+    fn read_body(buffer: &mut ByteBuf) -> Result<Self, PacketError> {
+        let uuid = buffer.read_uuid()?;
+        let username = buffer.read_string()?;
+        buffer.read_prefixed_array(usize::MAX, |buffer| {
+            buffer.read_string()?;
+            buffer.read_string()?;
+            buffer.read_optional(ByteBuf::read_string)
+        })?;
+
+        let strict_error_handling = match buffer.remaining() {
+            0 => None,
+            _ => Some(buffer.read_bool()?),
+        };
+
+        Ok(LoginSuccessPacket {
+            uuid,
+            username,
+            strict_error_handling,
+        })
     }
 }
 
 impl PlayerboundPacket for LoginSuccessPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = SetCompression)]
+pub struct SetCompressionPacket {
+    #[varint]
+    pub threshold: u32,
+}
+
+impl PlayerboundPacket for SetCompressionPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = LoginAcknowledged)]
+pub struct LoginAcknowledgedPacket {}
+
+impl ProxyboundPacket for LoginAcknowledgedPacket {}
@@ -1,355 +1,465 @@
 use std::io::{Error, ErrorKind, Read, Write};
 
-use azalea_chat::FormattedText;
+use data::{ByteBuf, ByteBufMut};
 use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
-use crate::player::PlayerConnection;
+use serde::Deserialize;
+
+use crate::player::{ConnectionState, PlayerConnection};
+use registry::PacketKind;
 
 pub mod handshake;
 pub mod login;
 pub mod play;
 pub mod configuration;
+pub mod data;
+pub mod registry;
+pub mod status;
+
+/// A packet as read off the wire, before being decoded into a typed struct.
+#[derive(Clone, Debug)]
+pub struct RawPacket {
+    pub length: u32,
+    pub id: u32,
+    pub data: Vec<u8>,
+}
 
-pub mod data {
-    use std::io::{Error, ErrorKind};
+impl RawPacket {
+    /// Whether this is a `P` for a connection speaking `protocol`.
+    pub fn is<P: Packet>(&self, protocol: u32) -> bool {
+        Some(self.id) == P::id(protocol)
+    }
 
-    use azalea_chat::FormattedText;
-    use serde::Deserialize;
-    use serde_json::Value;
-    use uuid::Uuid;
+    /// Decodes the packet body as `P`. The ID is not checked; use `is` first
+    /// when the packet could be of another kind.
+    pub fn decode_as<P: Packet>(&self) -> Result<P, PacketError> {
+        P::decode(&self.data)
+    }
+}
 
-    pub mod nbt {
-        use std::io::{Cursor, Error};
+#[derive(Debug)]
+pub enum PacketError {
+    /// The packet body could not be read.
+    Io(Error),
+    /// A different packet arrived than the one expected.
+    IdMismatch { expected: Option<u32>, actual: u32 },
+    /// The packet ended before a value could be read.
+    Truncated { needed: usize, remaining: usize },
+    /// A length prefix exceeded what the protocol allows.
+    Oversize { what: &'static str, length: usize, max: usize },
+    /// A VarInt or VarLong ran over its maximum size.
+    BadVarInt,
+    InvalidUtf8,
+    InvalidIdentifier(String),
+    BadNbt(String),
+    BadJson(String),
+    /// Valid data the proxy cannot decode yet.
+    Unsupported(&'static str),
+    /// The compressed body of a packet could not be inflated.
+    Decompression(Error),
+    /// A plugin message arrived on another channel than the one expected.
+    UnexpectedChannel(String),
+    /// The packet has no ID in the connection's protocol version.
+    UnknownPacket { kind: PacketKind, protocol: u32 },
+    /// A compressed packet inflated to another size than it declared.
+    UncompressedLengthMismatch { declared: usize, actual: usize },
+}
 
-        use azalea_chat::FormattedText;
-        use crab_nbt::{serde::{de::{from_bytes_unnamed, from_cursor_unnamed}, ser::to_bytes_unnamed}, Nbt};
+impl PacketError {
+    /// Whether the peer sent data that does not follow the protocol, as
+    /// opposed to the connection failing or closing.
+    pub fn is_malformed(&self) -> bool {
+        !matches!(self, PacketError::Io(_))
+    }
+}
 
-        pub fn write_text(buffer: &mut Vec<u8>, value: &FormattedText) {
-            let mut text_as_bytes = to_bytes_unnamed(value).unwrap();
-            buffer.extend_from_slice(&text_as_bytes);
+impl std::fmt::Display for PacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PacketError::Io(e) => write!(f, "{}", e),
+            PacketError::IdMismatch { expected, actual } => match expected {
+                Some(expected) => write!(f, "expected packet 0x{:02X}, got 0x{:02X}", expected, actual),
+                None => write!(f, "packet 0x{:02X} is not expected in this protocol version", actual),
+            },
+            PacketError::Truncated { needed, remaining } => {
+                write!(f, "packet truncated: needed {} bytes, {} left", needed, remaining)
+            }
+            PacketError::Oversize { what, length, max } => {
+                write!(f, "{} of length {} exceeds the maximum of {}", what, length, max)
+            }
+            PacketError::BadVarInt => write!(f, "VarInt is too big"),
+            PacketError::InvalidUtf8 => write!(f, "invalid UTF-8 in string"),
+            PacketError::InvalidIdentifier(identifier) => write!(f, "invalid identifier {:?}", identifier),
+            PacketError::BadNbt(e) => write!(f, "invalid NBT: {}", e),
+            PacketError::BadJson(e) => write!(f, "invalid JSON text: {}", e),
+            PacketError::Unsupported(what) => write!(f, "{} are not supported", what),
+            PacketError::Decompression(e) => write!(f, "failed to decompress packet: {}", e),
+            PacketError::UnexpectedChannel(channel) => write!(f, "unexpected plugin channel {:?}", channel),
+            PacketError::UnknownPacket { kind, protocol } => {
+                write!(f, "{:?} does not exist in protocol {}", kind, protocol)
+            }
+            PacketError::UncompressedLengthMismatch { declared, actual } => write!(
+                f,
+                "packet declared {} bytes uncompressed but inflated to {}",
+                declared, actual
+            ),
         }
+    }
+}
 
-        pub fn read_text(buffer: &[u8], position: &mut usize) -> Result<FormattedText, Error>{
-            let mut cursor = Cursor::new(buffer);
-            cursor.set_position(*position as u64);
-            
-            Ok(from_cursor_unnamed::<FormattedText>(&mut cursor).unwrap())
-        }
+impl std::error::Error for PacketError {}
+
+impl From<Error> for PacketError {
+    fn from(e: Error) -> Self {
+        PacketError::Io(e)
     }
+}
 
-    pub fn write_varint(buffer: &mut Vec<u8>, mut value: u32) {
-        while value >= 0x80 {
-            buffer.push(((value & 0x7F) | 0x80) as u8); // Add CONTINUE_BIT (0x80) if more bytes follow
-            value >>= 7;
+impl From<PacketError> for Error {
+    fn from(e: PacketError) -> Self {
+        match e {
+            PacketError::Io(e) => e,
+            e => Error::new(ErrorKind::InvalidData, e),
         }
-        buffer.push((value & 0x7F) as u8); // Final byte without CONTINUE_BIT
     }
+}
 
-    pub fn write_string(buffer: &mut Vec<u8>, val: &str) {
-        let length = val.len();
-        write_varint(buffer, length as u32);
+/// Size limits applied to incoming packets, configured under
+/// `[packet_limits]`. Every field is optional.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct PacketLimits {
+    /// Longest frame accepted from either side, in bytes.
+    pub max_frame_length: usize,
+    /// Largest size a compressed packet may declare once inflated.
+    pub max_uncompressed_length: usize,
+    /// Longest frame a player may send in the Handshake state.
+    pub handshake: usize,
+    /// Longest frame a player may send in the Status state.
+    pub status: usize,
+    /// Longest frame a player may send in the Login state. The default
+    /// leaves room for a Cookie Response with its largest, 5 KiB payload.
+    pub login: usize,
+}
 
-        buffer.extend_from_slice(val.as_bytes());
+impl Default for PacketLimits {
+    fn default() -> Self {
+        PacketLimits {
+            // The largest length a 3-byte VarInt can hold, as in vanilla
+            max_frame_length: 2097151,
+            max_uncompressed_length: 8388608,
+            handshake: 1024,
+            status: 64,
+            login: 8192,
+        }
     }
+}
 
-    pub fn write_ushort(buffer: &mut Vec<u8>, value: u16) {
-        buffer.extend_from_slice(&value.to_be_bytes());
+impl PacketLimits {
+    /// The limits for packets read in `state` from `direction`'s sender.
+    /// The per-state maximums only apply to what players send.
+    pub fn frame_limits(&self, state: &ConnectionState, direction: PacketDirection) -> FrameLimits {
+        let state_limit = match (direction, state) {
+            (PacketDirection::Proxybound, ConnectionState::Handshake) => self.handshake,
+            (PacketDirection::Proxybound, ConnectionState::Status) => self.status,
+            (PacketDirection::Proxybound, ConnectionState::Login) => self.login,
+            _ => self.max_frame_length,
+        };
+
+        FrameLimits {
+            max_frame_length: state_limit.min(self.max_frame_length),
+            max_uncompressed_length: self.max_uncompressed_length,
+        }
     }
+}
 
-    pub fn write_uuid(buffer: &mut Vec<u8>, uuid: &Uuid) {
-        let uuid_bytes = uuid.as_bytes();
-        buffer.extend_from_slice(uuid_bytes);
-    }
+/// The limits that apply to a single read, resolved from `PacketLimits`.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameLimits {
+    pub max_frame_length: usize,
+    pub max_uncompressed_length: usize,
+}
 
-    pub fn read_ushort(buffer: &Vec<u8>, position: &mut usize) -> Result<u16, Error> {
-        if *position + 2 > buffer.len() {
-            return Err(Error::new(ErrorKind::Other, "Not enough bytes"));
+pub async fn read_packet<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    compression_threshold: u32,
+    limits: FrameLimits,
+) -> Result<RawPacket, PacketError> {
+    // Read the length prefix one byte at a time, as it is a VarInt
+    let mut packet_length: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = stream.read_u8().await?;
+        packet_length |= ((byte & 0x7F) as u32) << shift;
+
+        if byte & 0x80 == 0 {
+            break;
+        } else if shift == 28 {
+            return Err(PacketError::BadVarInt);
         }
+    }
 
-        let result = u16::from_be_bytes([buffer[*position], buffer[*position + 1]]);
-
-        *position += 2;
+    // Ensure the packet size is valid
+    check_frame_length(packet_length as usize, &limits)?;
 
-        Ok(result)
-    }
+    // Read full packet into buffer
+    let mut buffer = vec![0u8; packet_length as usize];
+    stream.read_exact(&mut buffer).await?;
 
-    pub fn read_varint(buffer: &[u8], position: &mut usize) -> Result<u32, Error> {
-        let mut value: u32 = 0;
-        let mut shift: u32 = 0;
+    read_frame(packet_length, &buffer, compression_threshold, &limits)
+}
 
-        loop {
-            if *position >= buffer.len() {
-                return Err(Error::new(ErrorKind::Other, "Not enough bytes"));
-            }
+pub fn read_packet_from_bytes(
+    slice: &[u8],
+    compression_threshold: u32,
+    limits: FrameLimits,
+) -> Result<RawPacket, PacketError> {
+    let mut buffer = ByteBuf::new(slice);
 
-            let current_byte = buffer[*position];
-            *position += 1; // Move to the next byte
+    let packet_length = buffer.read_varint()?;
+    check_frame_length(packet_length as usize, &limits)?;
+    let frame = buffer.read_bytes(packet_length as usize)?;
 
-            value |= ((current_byte & 0x7F) as u32) << shift; // Extract 7 bits and add to value
-            if (current_byte & 0x80) == 0 {
-                break; // Stop if CONTINUE_BIT is not set
-            }
+    read_frame(packet_length, frame, compression_threshold, &limits)
+}
 
-            shift += 7;
+/// The length of the first packet in `buffer`, including its length prefix,
+/// or `None` if it has not been received in full yet.
+pub fn frame_length(buffer: &[u8], limits: FrameLimits) -> Result<Option<usize>, PacketError> {
+    let mut reader = ByteBuf::new(buffer);
 
-            if shift >= 32 {
-                return Err(Error::new(ErrorKind::Other, "Varint too big!"))?;
-            }
-        }
+    let packet_length = match reader.read_varint() {
+        Ok(length) => length as usize,
+        Err(PacketError::Truncated { .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    };
 
-        Ok(value)
-    }
+    check_frame_length(packet_length, &limits)?;
 
-    pub fn varint_size(mut value: u32) -> usize {
-        let mut size = 0;
-        loop {
-            size += 1;
-            if value & !0x7F == 0 {
-                break;
-            }
-            value >>= 7;
-        }
-        size
+    if reader.remaining() < packet_length {
+        return Ok(None);
     }
 
-    pub fn read_string(buffer: &[u8], position: &mut usize) -> Result<String, Error> {
-        let length = read_varint(buffer, position)? as usize;
+    Ok(Some(reader.position() + packet_length))
+}
 
-        if *position + length > buffer.len() {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "Not enoguh bytes",
-            ))?;
-        }
+/// Reads the ID of a whole frame without decoding its body. Only as much of
+/// a compressed packet is inflated as the ID takes.
+pub fn peek_packet_id(frame: &[u8], compression_threshold: u32) -> Result<u32, PacketError> {
+    let mut buffer = ByteBuf::new(frame);
+    buffer.read_varint()?;
 
-        let string_bytes = &buffer[*position..*position + length];
-        *position += length;
-        match String::from_utf8(string_bytes.to_vec()) {
-            Ok(string) => Ok(string),
-            Err(_) => Err(Error::new(ErrorKind::Other, "Invalid UTF-8 in string"))?,
-        }
+    if compression_threshold == 0 || buffer.read_varint()? == 0 {
+        return buffer.read_varint();
     }
 
-    pub fn read_uuid(buffer: &[u8], position: &mut usize) -> Result<Uuid, Error> {
-        if *position + 16 > buffer.len() {
-            return Err(Error::new(
-                ErrorKind::UnexpectedEof,
-                "Not enough bytes",
-            ));
-        }
-
-        let uuid_bytes: [u8; 16] = buffer[*position..*position + 16].try_into().unwrap();
-        *position += 16;
+    let mut decoder = ZlibDecoder::new(buffer.read_remaining()).take(5);
+    let mut id = Vec::with_capacity(5);
+    decoder
+        .read_to_end(&mut id)
+        .map_err(PacketError::Decompression)?;
 
-        Ok(Uuid::from_bytes(uuid_bytes))
-    }
+    ByteBuf::new(&id).read_varint()
+}
 
-    pub fn read_text(buffer: &[u8], position: &mut usize) -> Result<FormattedText, Error> {
-        let j: Value = serde_json::from_str(
-            &read_string(&buffer, position).unwrap()
-        )
-        .unwrap();
-    
-        Ok(FormattedText::deserialize(&j)?)
+/// The size a compressed frame declares it inflates to, or `None` if the
+/// frame is sent uncompressed.
+pub fn peek_uncompressed_length(frame: &[u8], compression_threshold: u32) -> Option<usize> {
+    if compression_threshold == 0 {
+        return None;
     }
 
-    pub fn write_text(buffer: &mut Vec<u8>, text: &FormattedText) {
-        write_string(buffer, &serde_json::to_string(text).unwrap())
-    }
+    let mut buffer = ByteBuf::new(frame);
+    buffer.read_varint().ok()?;
 
-    pub fn write_bool(buffer: &mut Vec<u8>, value: bool) {
-        buffer.push(if value { 1 } else { 0 });
-    }
-    
-    pub fn read_bool(buffer: &Vec<u8>, position: &mut usize) -> Result<bool, Error> {
-        if *position >= buffer.len() {
-            return Err(Error::new(ErrorKind::Other, "Not enough bytes"));
-        }
-    
-        let result = buffer[*position] != 0;
-        *position += 1;
-    
-        Ok(result)
+    match buffer.read_varint().ok()? {
+        0 => None,
+        length => Some(length as usize),
     }
 }
 
-pub type RawPacket = (u32, u32, Vec<u8>);
-pub async fn read_packet(
-    stream: &mut TcpStream,
-    compression_threshold: u32,
-) -> Result<RawPacket, Error> {
-    // Read first byte to determine packet size
-    let mut temp_buffer = vec![0u8; 5];
-    stream.read_exact(&mut temp_buffer[..1]).await?;
-
-    let mut position = 0;
-    let mut slice = &temp_buffer[..];
-    let packet_length = data::read_varint(&mut slice, &mut position)?;
+fn check_frame_length(length: usize, limits: &FrameLimits) -> Result<(), PacketError> {
+    if length == 0 {
+        return Err(PacketError::Truncated { needed: 1, remaining: 0 });
+    }
 
-    // Ensure the packet size is valid
-    if packet_length == 0 {
-        return Err(Error::new(ErrorKind::InvalidData, "Packet length cannot be zero"));
+    if length > limits.max_frame_length {
+        return Err(PacketError::Oversize {
+            what: "packet",
+            length,
+            max: limits.max_frame_length,
+        });
     }
 
-    // Read full packet into buffer
-    let mut buffer = vec![0u8; packet_length as usize];
-    stream.read_exact(&mut buffer).await?;
+    Ok(())
+}
 
-    let mut position = 0;
-    let mut slice = &buffer[..];
+/// Splits a frame (everything after the length prefix) into ID and body,
+/// decompressing it first if needed.
+fn read_frame(
+    length: u32,
+    frame: &[u8],
+    compression_threshold: u32,
+    limits: &FrameLimits,
+) -> Result<RawPacket, PacketError> {
+    let mut buffer = ByteBuf::new(frame);
 
     if compression_threshold == 0 {
         // No compression: Read packet ID and return
-        let packet_id = data::read_varint(&mut slice, &mut position)?;
-        let data = slice[position..].to_vec();
-        return Ok((packet_length, packet_id, data));
+        let id = buffer.read_varint()?;
+        let data = buffer.read_remaining().to_vec();
+        return Ok(RawPacket { length, id, data });
     }
 
     // Read Data Length
-    let data_length = data::read_varint(&mut slice, &mut position)?;
-
-    let (packet_id, data) = if data_length >= compression_threshold {
-        // Compressed packet: Decompress safely
-        let compressed_data = &slice[position..];
-        let mut decoder = ZlibDecoder::new(compressed_data);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
+    let data_length = buffer.read_varint()?;
 
-        let mut position = 0;
-        let mut slice = &decompressed_data[..];
-        let packet_id = data::read_varint(&mut slice, &mut position)?;
-        let data = slice[position..].to_vec();
-
-        (packet_id, data)
-    } else {
+    if data_length == 0 {
         // Uncompressed packet
-        let packet_id = data::read_varint(&mut slice, &mut position)?;
-        let data = slice[position..].to_vec();
-        (packet_id, data)
-    };
-
-    Ok((packet_length, packet_id, data))
-}
-
-pub fn read_packet_from_bytes(mut slice: &[u8], compression_threshold: u32) -> Result<RawPacket, Error> {
-    let mut position = 0;
-
-    let packet_length = data::read_varint(&mut slice, &mut position)?;
-    if slice.len() < packet_length as usize {
-        return Err(Error::new(ErrorKind::UnexpectedEof, "Packet too short"));
+        let id = buffer.read_varint()?;
+        let data = buffer.read_remaining().to_vec();
+        return Ok(RawPacket { length, id, data });
     }
 
-    if compression_threshold == 0 {
-        // No compression: Read packet ID and return
-        let packet_id = data::read_varint(&mut slice, &mut position)?;
-        let data = slice[position..].to_vec();
-        return Ok((packet_length, packet_id, data));
+    // Compressed packet: never inflate past the declared length, which must
+    // itself be within limits
+    let data_length = data_length as usize;
+    if data_length > limits.max_uncompressed_length {
+        return Err(PacketError::Oversize {
+            what: "uncompressed packet",
+            length: data_length,
+            max: limits.max_uncompressed_length,
+        });
     }
 
-    // Read Data Length
-    let data_length = data::read_varint(&mut slice, &mut position)?;
-
-    let (packet_id, data) = if data_length >= compression_threshold {
-        // Compressed packet: Decompress safely
-        let compressed_data = &slice[position..];
-        let mut decoder = ZlibDecoder::new(compressed_data);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
-
-        let mut position = 0;
-        let mut slice = &decompressed_data[..];
-        let packet_id = data::read_varint(&mut slice, &mut position)?;
-        let data = slice[position..].to_vec();
+    let mut decoder = ZlibDecoder::new(buffer.read_remaining()).take(data_length as u64 + 1);
+    let mut decompressed_data = Vec::new();
+    decoder
+        .read_to_end(&mut decompressed_data)
+        .map_err(PacketError::Decompression)?;
+
+    if decompressed_data.len() != data_length {
+        return Err(PacketError::UncompressedLengthMismatch {
+            declared: data_length,
+            actual: decompressed_data.len(),
+        });
+    }
 
-        (packet_id, data)
-    } else {
-        // Uncompressed packet
-        let packet_id = data::read_varint(&mut slice, &mut position)?;
-        let data = slice[position..].to_vec();
-        (packet_id, data)
-    };
+    let mut buffer = ByteBuf::new(&decompressed_data);
+    let id = buffer.read_varint()?;
+    let data = buffer.read_remaining().to_vec();
 
-    Ok((packet_length, packet_id, data))
+    Ok(RawPacket { length, id, data })
 }
 
-pub(crate) async fn send_packet<P: Packet>(
-    packet: &P,
-    cnx: &mut TcpStream,
-    compression_threshold: u32,
-) -> Result<(), Error> {
-    let mut buffer = Vec::new();
+/// Writes the ID and fields of `packet` as sent to a connection speaking
+/// `protocol`, without the frame around them.
+pub fn encode_packet<P: Packet>(packet: &P, protocol: u32) -> Result<(u32, Vec<u8>), PacketError> {
+    let mut buffer = ByteBufMut::new();
 
     // Write packet ID first
-    let packet_id = P::id();
-    data::write_varint(&mut buffer, packet_id);
+    let packet_id = P::id(protocol).ok_or(PacketError::UnknownPacket {
+        kind: P::kind(),
+        protocol,
+    })?;
+    buffer.write_varint(packet_id);
 
     // Write packet data
-    let mut data_buffer = Vec::new();
-    packet.write_to(&mut data_buffer);
+    packet.write_to(&mut buffer)?;
 
-    // Concatenate ID + data
-    buffer.extend(data_buffer);
+    Ok((packet_id, buffer.into_inner()))
+}
+
+/// Frames an encoded packet, compressing it if it reaches the threshold.
+pub(crate) fn frame_packet(buffer: &[u8], compression_threshold: u32) -> Result<Vec<u8>, Error> {
     let uncompressed_length = buffer.len() as u32;
 
-    let mut final_buffer = Vec::new();
+    let mut final_buffer = ByteBufMut::with_capacity(buffer.len() + 10);
 
     if compression_threshold == 0 {
         // No compression - use original format
-        data::write_varint(&mut final_buffer, uncompressed_length);
-        final_buffer.extend(buffer);
+        final_buffer.write_varint(uncompressed_length);
+        final_buffer.write_bytes(buffer);
     } else if uncompressed_length >= compression_threshold {
         // Compression is required
-        println!("Compressing packet with ID: {}", P::id());
-
         let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&buffer)?; // Compress (Packet ID + Data)
+        encoder.write_all(buffer)?; // Compress (Packet ID + Data)
         let compressed_data = encoder.finish()?;
 
         // Write total packet length (compressed data size + size of `Data Length`)
         let total_compressed_length =
             compressed_data.len() as u32 + data::varint_size(uncompressed_length) as u32;
-        data::write_varint(&mut final_buffer, total_compressed_length);
+        final_buffer.write_varint(total_compressed_length);
 
         // Write uncompressed data length
-        data::write_varint(&mut final_buffer, uncompressed_length);
+        final_buffer.write_varint(uncompressed_length);
 
         // Append compressed packet ID + Data
-        final_buffer.extend(compressed_data);
+        final_buffer.write_bytes(&compressed_data);
     } else {
         // Packet size is below threshold, send uncompressed but in the new format
-        println!("Sending uncompressed packet with ID: {}", P::id());
-        
         let uncompressed_length_with_indicator =
             uncompressed_length + data::varint_size(0) as u32;
 
         // Write total packet length
-        data::write_varint(&mut final_buffer, uncompressed_length_with_indicator);
+        final_buffer.write_varint(uncompressed_length_with_indicator);
 
         // Write Data Length = 0 (Uncompressed indicator)
-        data::write_varint(&mut final_buffer, 0);
+        final_buffer.write_varint(0);
 
         // Append packet ID + Data
-        final_buffer.extend(buffer);
+        final_buffer.write_bytes(buffer);
     }
 
-    // Send packet
-    cnx.write_all(&final_buffer).await?;
-    cnx.flush().await?;
+    Ok(final_buffer.into_inner())
+}
 
-    Ok(())
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PacketDirection {
+    Playerbound,
+    Proxybound,
 }
 
-pub trait Packet: Clone {
-    fn id() -> u32;
+pub use rustyproxy_derive::Packet;
 
-    fn write_to(&self, buffer: &mut Vec<u8>);
+pub trait Packet: Clone + Send {
+    fn kind() -> PacketKind;
+
+    /// The ID of this packet for a connection speaking `protocol`, or `None`
+    /// if it does not exist in that version.
+    fn id(protocol: u32) -> Option<u32> {
+        registry::packet_id(Self::kind(), protocol)
+    }
+
+    /// Writes the packet's fields, failing if one cannot be encoded, e.g. a
+    /// text too long for NBT.
+    fn write_to(&self, buffer: &mut ByteBufMut) -> Result<(), PacketError>;
+
+    /// Reads the packet's fields from its body.
+    fn read_body(buffer: &mut ByteBuf) -> Result<Self, PacketError>;
+
+    /// Decodes a packet body that has already been read, e.g. the `data` of
+    /// a `RawPacket`.
+    fn decode(buffer: &[u8]) -> Result<Self, PacketError> {
+        Self::read_body(&mut ByteBuf::new(buffer))
+    }
+
+    /// Reads the next packet from the player and decodes it as `Self`.
     fn read_from(
         connection: &mut PlayerConnection,
-    ) -> impl std::future::Future<Output = Result<Box<Self>, Error>> + Send;
+    ) -> impl std::future::Future<Output = Result<Box<Self>, PacketError>> + Send {
+        async move {
+            let packet = connection.read_packet().await?;
+            if !packet.is::<Self>(connection.protocol) {
+                return Err(PacketError::IdMismatch {
+                    expected: Self::id(connection.protocol),
+                    actual: packet.id,
+                });
+            }
+
+            Ok(Box::new(packet.decode_as()?))
+        }
+    }
 }
 
 pub trait ProxyboundPacket: Packet {
@@ -359,3 +469,185 @@ pub trait ProxyboundPacket: Packet {
 pub trait PlayerboundPacket: Packet {
     // Server -> (Proxy -/-> Player)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: FrameLimits = FrameLimits {
+        max_frame_length: 1024,
+        max_uncompressed_length: 4096,
+    };
+
+    /// A compressed frame with `body` deflated, declaring `declared` bytes
+    /// once inflated.
+    fn compressed_frame(body: &[u8], declared: u32) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut frame = ByteBufMut::new();
+        frame.write_varint(declared);
+        frame.write_bytes(&compressed);
+
+        let mut buffer = ByteBufMut::new();
+        buffer.write_prefixed_bytes(frame.as_slice());
+        buffer.into_inner()
+    }
+
+    fn encoded(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut buffer = ByteBufMut::new();
+        buffer.write_varint(id);
+        buffer.write_bytes(data);
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let small = encoded(0x0B, &[1, 2, 3]);
+        let large = encoded(0x27, &[7; 600]);
+
+        for threshold in [0, 256] {
+            for packet in [&small, &large] {
+                let frame = frame_packet(packet, threshold).unwrap();
+                assert_eq!(frame_length(&frame, LIMITS).unwrap(), Some(frame.len()));
+                assert_eq!(peek_packet_id(&frame, threshold).unwrap(), packet[0] as u32);
+
+                let raw = read_packet_from_bytes(&frame, threshold, LIMITS).unwrap();
+                assert_eq!(encoded(raw.id, &raw.data), *packet);
+            }
+        }
+
+        let frame = frame_packet(&large, 256).unwrap();
+        assert_eq!(peek_uncompressed_length(&frame, 256), Some(large.len()));
+        assert!(frame.len() < large.len());
+    }
+
+    #[test]
+    fn incomplete_frames_wait_for_more() {
+        let frame = frame_packet(&encoded(0x00, &[0; 300]), 0).unwrap();
+
+        assert_eq!(frame_length(&frame[..1], LIMITS).unwrap(), None);
+        assert_eq!(frame_length(&frame[..frame.len() - 1], LIMITS).unwrap(), None);
+        assert!(matches!(
+            read_packet_from_bytes(&frame[..frame.len() - 1], 0, LIMITS),
+            Err(PacketError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn frames_over_the_limit_are_rejected() {
+        let frame = frame_packet(&encoded(0x00, &[0; 1024]), 0).unwrap();
+
+        assert!(matches!(frame_length(&frame, LIMITS), Err(PacketError::Oversize { length: 1025, .. })));
+        assert!(matches!(
+            read_packet_from_bytes(&frame, 0, LIMITS),
+            Err(PacketError::Oversize { what: "packet", .. })
+        ));
+
+        // Only the prefix is needed to reject it
+        assert!(matches!(frame_length(&frame[..2], LIMITS), Err(PacketError::Oversize { .. })));
+        assert!(matches!(frame_length(&[0], LIMITS), Err(PacketError::Truncated { .. })));
+    }
+
+    #[tokio::test]
+    async fn streams_are_checked_before_reading_the_frame() {
+        let frame = frame_packet(&encoded(0x00, &[0; 1024]), 0).unwrap();
+        let result = read_packet(&mut &frame[..3], 0, LIMITS).await;
+        assert!(matches!(result, Err(PacketError::Oversize { .. })));
+
+        let frame = frame_packet(&encoded(0x01, &[5; 10]), 0).unwrap();
+        let raw = read_packet(&mut &frame[..], 0, LIMITS).await.unwrap();
+        assert_eq!((raw.id, raw.data), (0x01, vec![5; 10]));
+
+        let overlong = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        let result = read_packet(&mut &overlong[..], 0, LIMITS).await;
+        assert!(matches!(result, Err(PacketError::BadVarInt)));
+    }
+
+    #[test]
+    fn limits_depend_on_state_and_direction() {
+        let limits = PacketLimits::default();
+
+        let player = |state| limits.frame_limits(&state, PacketDirection::Proxybound).max_frame_length;
+        assert_eq!(player(ConnectionState::Handshake), limits.handshake);
+        assert_eq!(player(ConnectionState::Status), limits.status);
+        assert_eq!(player(ConnectionState::Login), limits.login);
+        assert_eq!(player(ConnectionState::Configuration), limits.max_frame_length);
+        assert_eq!(player(ConnectionState::Play), limits.max_frame_length);
+
+        let server = limits.frame_limits(&ConnectionState::Login, PacketDirection::Playerbound);
+        assert_eq!(server.max_frame_length, limits.max_frame_length);
+
+        // A per-state limit never exceeds the overall one
+        let limits = PacketLimits {
+            max_frame_length: 100,
+            ..PacketLimits::default()
+        };
+        let login = limits.frame_limits(&ConnectionState::Login, PacketDirection::Proxybound);
+        assert_eq!(login.max_frame_length, 100);
+    }
+
+    #[test]
+    fn login_limit_fits_the_largest_cookie() {
+        let mut body = ByteBufMut::new();
+        body.write_string("minecraft:some/long/cookie/key");
+        body.write_optional(Some(&[0u8; 5120]), |buffer, payload| buffer.write_prefixed_bytes(payload));
+
+        let frame = frame_packet(&encoded(0x04, body.as_slice()), 0).unwrap();
+        let limits = PacketLimits::default().frame_limits(&ConnectionState::Login, PacketDirection::Proxybound);
+        assert!(frame_length(&frame, limits).unwrap().is_some());
+    }
+
+    #[test]
+    fn compressed_packets_are_bounded() {
+        // Declared over the limit: refused before inflating
+        let frame = compressed_frame(&encoded(0x00, &[0; 100]), 5000);
+        assert!(matches!(
+            read_packet_from_bytes(&frame, 64, LIMITS),
+            Err(PacketError::Oversize { what: "uncompressed packet", .. })
+        ));
+
+        // Declared within the limit but inflating past it: inflation stops
+        // one byte after the declared length
+        let frame = compressed_frame(&encoded(0x00, &[0; 100_000]), 101);
+        assert!(frame.len() < LIMITS.max_frame_length);
+        assert!(matches!(
+            read_packet_from_bytes(&frame, 64, LIMITS),
+            Err(PacketError::UncompressedLengthMismatch { declared: 101, actual: 102 })
+        ));
+    }
+
+    #[test]
+    fn declared_lengths_must_match() {
+        let body = encoded(0x00, &[3; 100]);
+
+        let frame = compressed_frame(&body, 200);
+        assert!(matches!(
+            read_packet_from_bytes(&frame, 64, LIMITS),
+            Err(PacketError::UncompressedLengthMismatch { declared: 200, actual: 101 })
+        ));
+
+        let frame = compressed_frame(&body, 50);
+        assert!(matches!(
+            read_packet_from_bytes(&frame, 64, LIMITS),
+            Err(PacketError::UncompressedLengthMismatch { declared: 50, actual: 51 })
+        ));
+
+        let frame = compressed_frame(&body, 101);
+        assert_eq!(read_packet_from_bytes(&frame, 64, LIMITS).unwrap().data, [3; 100]);
+    }
+
+    #[test]
+    fn corrupt_compressed_data_is_rejected() {
+        let mut frame = ByteBufMut::new();
+        frame.write_varint(6);
+        frame.write_varint(100);
+        frame.write_bytes(&[1, 2, 3, 4, 5]);
+
+        assert!(matches!(
+            read_packet_from_bytes(frame.as_slice(), 64, LIMITS),
+            Err(PacketError::Decompression(_))
+        ));
+    }
+}
@@ -1,39 +1,207 @@
-use std::io::{Error, ErrorKind};
-
 use azalea_chat::FormattedText;
 
-use super::{data, Packet, PlayerboundPacket};
+use super::{
+    data::{ByteBuf, ByteBufMut},
+    registry::PacketKind,
+    Packet, PacketError, PlayerboundPacket, ProxyboundPacket,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Packet)]
+#[packet(kind = SystemChatMessage)]
 pub struct SystemChatMessagePacket {
+    #[nbt_text]
     pub text: FormattedText,
     pub overlay: bool
 }
 
-impl Packet for SystemChatMessagePacket {
-    fn id() -> u32 {
-        0x73
+impl PlayerboundPacket for SystemChatMessagePacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = PlayDisconnect)]
+pub struct PlayDisconnectPacket {
+    #[nbt_text]
+    pub reason: FormattedText,
+}
+
+impl PlayerboundPacket for PlayDisconnectPacket {}
+
+/// Sends the player back to the Configuration state, e.g. to switch servers.
+#[derive(Clone, Packet)]
+#[packet(kind = StartConfiguration)]
+pub struct StartConfigurationPacket {}
+
+impl PlayerboundPacket for StartConfigurationPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = ConfigurationAcknowledged)]
+pub struct ConfigurationAcknowledgedPacket {}
+
+impl ProxyboundPacket for ConfigurationAcknowledgedPacket {}
+
+/// A command typed by the player, without the leading slash. Only the
+/// command is read; 1.20.3 follows it with signatures the proxy ignores.
+#[derive(Clone, Packet)]
+#[packet(kind = ChatCommand)]
+pub struct ChatCommandPacket {
+    pub command: String,
+}
+
+impl ProxyboundPacket for ChatCommandPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = PlayKeepAlive)]
+pub struct PlayKeepAlivePacket {
+    pub id: i64,
+}
+
+impl PlayerboundPacket for PlayKeepAlivePacket {}
+
+/// Puts the player in a world. Only written, by the proxy itself, as its
+/// layout differs between versions.
+#[derive(Clone)]
+pub struct PlayLoginPacket {
+    /// The protocol version the packet is written for; 1.21.2 added the
+    /// sea level.
+    pub protocol: u32,
+    pub entity_id: i32,
+    pub dimensions: Vec<String>,
+    pub max_players: u32,
+    pub view_distance: u32,
+    /// Index of the dimension type in the registry sent to the player.
+    pub dimension_type: u32,
+    pub dimension: String,
+    pub game_mode: u8,
+    pub sea_level: u32,
+}
+
+impl Packet for PlayLoginPacket {
+    fn kind() -> PacketKind {
+        PacketKind::PlayLogin
     }
 
-    fn write_to(&self, buffer: &mut Vec<u8>) {
-        data::nbt::write_text(buffer, &self.text);
-        data::write_bool(buffer, self.overlay);
+    fn write_to(&self, buffer: &mut ByteBufMut) -> Result<(), PacketError> {
+        buffer.write_i32(self.entity_id);
+        buffer.write_bool(false); // Hardcore
+        buffer.write_prefixed_array(&self.dimensions, |buffer, dimension| buffer.write_string(dimension));
+        buffer.write_varint(self.max_players);
+        buffer.write_varint(self.view_distance);
+        buffer.write_varint(self.view_distance); // Simulation distance
+        buffer.write_bool(false); // Reduced debug info
+        buffer.write_bool(true); // Respawn screen
+        buffer.write_bool(false); // Limited crafting
+        buffer.write_varint(self.dimension_type);
+        buffer.write_string(&self.dimension);
+        buffer.write_i64(0); // Hashed seed
+        buffer.write_u8(self.game_mode);
+        buffer.write_i8(-1); // No previous game mode
+        buffer.write_bool(false); // Debug world
+        buffer.write_bool(false); // Flat world
+        buffer.write_bool(false); // No death location
+        buffer.write_varint(0); // Portal cooldown
+        if self.protocol >= 768 {
+            buffer.write_varint(self.sea_level);
+        }
+        buffer.write_bool(false); // Enforces secure chat
+        Ok(())
+    }
+
+    fn read_body(_: &mut ByteBuf) -> Result<Self, PacketError> {
+        Err(PacketError::Unsupported("login packets of the Play state"))
+    }
+}
+
+impl PlayerboundPacket for PlayLoginPacket {}
+
+/// `event` 13 tells the player to wait for the chunks around it.
+#[derive(Clone, Packet)]
+#[packet(kind = GameEvent)]
+pub struct GameEventPacket {
+    pub event: u8,
+    pub value: f32,
+}
+
+impl PlayerboundPacket for GameEventPacket {}
+
+/// Teleports the player to an absolute position. Only written, as its
+/// layout differs between versions.
+#[derive(Clone)]
+pub struct SynchronizePlayerPositionPacket {
+    /// The protocol version the packet is written for; 1.21.2 moved the
+    /// teleport ID first and added the velocity.
+    pub protocol: u32,
+    pub teleport_id: u32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Packet for SynchronizePlayerPositionPacket {
+    fn kind() -> PacketKind {
+        PacketKind::SynchronizePlayerPosition
     }
 
-    async fn read_from(
-        connection: &mut crate::player::PlayerConnection,
-    ) -> Result<Box<Self>, Error> {
-        let (_, id, buffer) = connection.read_packet().await?;
-        if id != Self::id() {
-            return Err(Error::new(ErrorKind::Other, "id mismatch!"));
+    fn write_to(&self, buffer: &mut ByteBufMut) -> Result<(), PacketError> {
+        if self.protocol >= 768 {
+            buffer.write_varint(self.teleport_id);
         }
 
-        let mut position = 0 as usize;
+        buffer.write_f64(self.x);
+        buffer.write_f64(self.y);
+        buffer.write_f64(self.z);
+
+        if self.protocol >= 768 {
+            buffer.write_f64(0.0);
+            buffer.write_f64(0.0);
+            buffer.write_f64(0.0);
+        }
+
+        buffer.write_f32(self.yaw);
+        buffer.write_f32(self.pitch);
+
+        // No relative fields
+        if self.protocol >= 768 {
+            buffer.write_i32(0);
+        } else {
+            buffer.write_u8(0);
+            buffer.write_varint(self.teleport_id);
+        }
+        Ok(())
+    }
 
-        Ok(Box::new(SystemChatMessagePacket {
-            text: data::nbt::read_text(&buffer, &mut position).unwrap(),
-            overlay: data::read_bool(&buffer, &mut position).unwrap(),
-        }))
+    fn read_body(_: &mut ByteBuf) -> Result<Self, PacketError> {
+        Err(PacketError::Unsupported("player position packets"))
     }
 }
-impl PlayerboundPacket for SystemChatMessagePacket {}
+
+impl PlayerboundPacket for SynchronizePlayerPositionPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = SetTitleText)]
+pub struct SetTitleTextPacket {
+    #[nbt_text]
+    pub text: FormattedText,
+}
+
+impl PlayerboundPacket for SetTitleTextPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = SetSubtitleText)]
+pub struct SetSubtitleTextPacket {
+    #[nbt_text]
+    pub text: FormattedText,
+}
+
+impl PlayerboundPacket for SetSubtitleTextPacket {}
+
+/// How long titles fade in, stay and fade out, in ticks.
+#[derive(Clone, Packet)]
+#[packet(kind = SetTitleAnimationTimes)]
+pub struct SetTitleAnimationTimesPacket {
+    pub fade_in: i32,
+    pub stay: i32,
+    pub fade_out: i32,
+}
+
+impl PlayerboundPacket for SetTitleAnimationTimesPacket {}
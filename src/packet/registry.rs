@@ -0,0 +1,251 @@
+use crate::player::ConnectionState;
+
+use super::PacketDirection;
+
+/// Protocol versions the registry has packet IDs for, oldest first.
+pub const SUPPORTED_PROTOCOLS: [u32; 5] = [765, 766, 767, 768, 769];
+
+/// The newest protocol version rustyproxy speaks.
+pub const LATEST_PROTOCOL: u32 = 769;
+
+/// Every packet the proxy knows how to recognise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PacketKind {
+    Handshake,
+
+    StatusRequest,
+    StatusResponse,
+    PingRequest,
+    PongResponse,
+
+    LoginDisconnect,
+    LoginStart,
+    LoginSuccess,
+    SetCompression,
+    LoginAcknowledged,
+
+    PlayerConfigurationPluginMessage,
+    ServerConfigurationPluginMessage,
+    ConfigurationDisconnect,
+    FinishConfiguration,
+    AcknowledgeFinishConfiguration,
+    ConfigurationKeepAlive,
+    ConfigurationKeepAliveResponse,
+    ClientboundKnownPacks,
+    ServerboundKnownPacks,
+    RegistryData,
+
+    PlayDisconnect,
+    PlayKeepAlive,
+    PlayKeepAliveResponse,
+    SystemChatMessage,
+    StartConfiguration,
+    ConfigurationAcknowledged,
+    ChatCommand,
+    PlayLogin,
+    GameEvent,
+    SynchronizePlayerPosition,
+    SetTitleText,
+    SetSubtitleText,
+    SetTitleAnimationTimes,
+}
+
+enum PacketIds {
+    /// The same ID in every protocol version.
+    Fixed(u32),
+    /// One ID per entry of `SUPPORTED_PROTOCOLS`.
+    PerVersion([u32; SUPPORTED_PROTOCOLS.len()]),
+    /// Like `PerVersion`, with `None` where the packet does not exist or has
+    /// a layout the proxy cannot read or write.
+    Partial([Option<u32>; SUPPORTED_PROTOCOLS.len()]),
+}
+
+impl PacketKind {
+    pub const ALL: [PacketKind; 33] = [
+        PacketKind::Handshake,
+        PacketKind::StatusRequest,
+        PacketKind::StatusResponse,
+        PacketKind::PingRequest,
+        PacketKind::PongResponse,
+        PacketKind::LoginDisconnect,
+        PacketKind::LoginStart,
+        PacketKind::LoginSuccess,
+        PacketKind::SetCompression,
+        PacketKind::LoginAcknowledged,
+        PacketKind::PlayerConfigurationPluginMessage,
+        PacketKind::ServerConfigurationPluginMessage,
+        PacketKind::ConfigurationDisconnect,
+        PacketKind::FinishConfiguration,
+        PacketKind::AcknowledgeFinishConfiguration,
+        PacketKind::ConfigurationKeepAlive,
+        PacketKind::ConfigurationKeepAliveResponse,
+        PacketKind::ClientboundKnownPacks,
+        PacketKind::ServerboundKnownPacks,
+        PacketKind::RegistryData,
+        PacketKind::PlayDisconnect,
+        PacketKind::PlayKeepAlive,
+        PacketKind::PlayKeepAliveResponse,
+        PacketKind::SystemChatMessage,
+        PacketKind::StartConfiguration,
+        PacketKind::ConfigurationAcknowledged,
+        PacketKind::ChatCommand,
+        PacketKind::PlayLogin,
+        PacketKind::GameEvent,
+        PacketKind::SynchronizePlayerPosition,
+        PacketKind::SetTitleText,
+        PacketKind::SetSubtitleText,
+        PacketKind::SetTitleAnimationTimes,
+    ];
+
+    pub fn state(self) -> ConnectionState {
+        match self {
+            PacketKind::Handshake => ConnectionState::Handshake,
+
+            PacketKind::StatusRequest
+            | PacketKind::StatusResponse
+            | PacketKind::PingRequest
+            | PacketKind::PongResponse => ConnectionState::Status,
+
+            PacketKind::LoginDisconnect
+            | PacketKind::LoginStart
+            | PacketKind::LoginSuccess
+            | PacketKind::SetCompression
+            | PacketKind::LoginAcknowledged => ConnectionState::Login,
+
+            PacketKind::PlayerConfigurationPluginMessage
+            | PacketKind::ServerConfigurationPluginMessage
+            | PacketKind::ConfigurationDisconnect
+            | PacketKind::FinishConfiguration
+            | PacketKind::AcknowledgeFinishConfiguration
+            | PacketKind::ConfigurationKeepAlive
+            | PacketKind::ConfigurationKeepAliveResponse
+            | PacketKind::ClientboundKnownPacks
+            | PacketKind::ServerboundKnownPacks
+            | PacketKind::RegistryData => ConnectionState::Configuration,
+
+            PacketKind::PlayDisconnect
+            | PacketKind::PlayKeepAlive
+            | PacketKind::PlayKeepAliveResponse
+            | PacketKind::SystemChatMessage
+            | PacketKind::StartConfiguration
+            | PacketKind::ConfigurationAcknowledged
+            | PacketKind::ChatCommand
+            | PacketKind::PlayLogin
+            | PacketKind::GameEvent
+            | PacketKind::SynchronizePlayerPosition
+            | PacketKind::SetTitleText
+            | PacketKind::SetSubtitleText
+            | PacketKind::SetTitleAnimationTimes => ConnectionState::Play,
+        }
+    }
+
+    pub fn direction(self) -> PacketDirection {
+        match self {
+            PacketKind::Handshake
+            | PacketKind::StatusRequest
+            | PacketKind::PingRequest
+            | PacketKind::LoginStart
+            | PacketKind::LoginAcknowledged
+            | PacketKind::ServerConfigurationPluginMessage
+            | PacketKind::AcknowledgeFinishConfiguration
+            | PacketKind::ConfigurationKeepAliveResponse
+            | PacketKind::ServerboundKnownPacks
+            | PacketKind::PlayKeepAliveResponse
+            | PacketKind::ConfigurationAcknowledged
+            | PacketKind::ChatCommand => PacketDirection::Proxybound,
+
+            _ => PacketDirection::Playerbound,
+        }
+    }
+
+    #[rustfmt::skip]
+    fn ids(self) -> PacketIds {
+        use PacketIds::*;
+
+        //                                                   765   766   767   768   769
+        match self {
+            PacketKind::Handshake => Fixed(0x00),
+
+            PacketKind::StatusRequest => Fixed(0x00),
+            PacketKind::StatusResponse => Fixed(0x00),
+            PacketKind::PingRequest => Fixed(0x01),
+            PacketKind::PongResponse => Fixed(0x01),
+
+            PacketKind::LoginDisconnect => Fixed(0x00),
+            PacketKind::LoginStart => Fixed(0x00),
+            PacketKind::LoginSuccess => Fixed(0x02),
+            PacketKind::SetCompression => Fixed(0x03),
+            PacketKind::LoginAcknowledged => Fixed(0x03),
+
+            PacketKind::PlayerConfigurationPluginMessage => PerVersion([0x00, 0x01, 0x01, 0x01, 0x01]),
+            PacketKind::ServerConfigurationPluginMessage => PerVersion([0x01, 0x02, 0x02, 0x02, 0x02]),
+            PacketKind::ConfigurationDisconnect =>          PerVersion([0x01, 0x02, 0x02, 0x02, 0x02]),
+            PacketKind::FinishConfiguration =>              PerVersion([0x02, 0x03, 0x03, 0x03, 0x03]),
+            PacketKind::AcknowledgeFinishConfiguration =>   PerVersion([0x02, 0x03, 0x03, 0x03, 0x03]),
+            PacketKind::ConfigurationKeepAlive =>           PerVersion([0x03, 0x04, 0x04, 0x04, 0x04]),
+            PacketKind::ConfigurationKeepAliveResponse =>   PerVersion([0x03, 0x04, 0x04, 0x04, 0x04]),
+            PacketKind::ClientboundKnownPacks =>            Partial([None, Some(0x0E), Some(0x0E), Some(0x0E), Some(0x0E)]),
+            PacketKind::ServerboundKnownPacks =>            Partial([None, Some(0x07), Some(0x07), Some(0x07), Some(0x07)]),
+            PacketKind::RegistryData =>                     Partial([None, Some(0x07), Some(0x07), Some(0x07), Some(0x07)]),
+
+            PacketKind::PlayDisconnect =>                   PerVersion([0x1B, 0x1D, 0x1D, 0x1D, 0x1D]),
+            PacketKind::PlayKeepAlive =>                    PerVersion([0x24, 0x26, 0x26, 0x27, 0x27]),
+            PacketKind::PlayKeepAliveResponse =>            PerVersion([0x15, 0x18, 0x18, 0x1A, 0x1A]),
+            PacketKind::SystemChatMessage =>                PerVersion([0x69, 0x6C, 0x6C, 0x73, 0x73]),
+            PacketKind::StartConfiguration =>               PerVersion([0x67, 0x69, 0x69, 0x70, 0x70]),
+            PacketKind::ConfigurationAcknowledged =>        PerVersion([0x0B, 0x0C, 0x0C, 0x0E, 0x0E]),
+            PacketKind::ChatCommand =>                      PerVersion([0x04, 0x04, 0x04, 0x05, 0x05]),
+            PacketKind::PlayLogin =>                        Partial([None, Some(0x2B), Some(0x2B), Some(0x2C), Some(0x2C)]),
+            PacketKind::GameEvent =>                        PerVersion([0x20, 0x22, 0x22, 0x23, 0x23]),
+            PacketKind::SynchronizePlayerPosition =>        PerVersion([0x3E, 0x40, 0x40, 0x42, 0x42]),
+            PacketKind::SetTitleText =>                     PerVersion([0x63, 0x65, 0x65, 0x6C, 0x6C]),
+            PacketKind::SetSubtitleText =>                  PerVersion([0x61, 0x63, 0x63, 0x6A, 0x6A]),
+            PacketKind::SetTitleAnimationTimes =>           PerVersion([0x64, 0x66, 0x66, 0x6D, 0x6D]),
+        }
+    }
+}
+
+pub fn is_supported(protocol: u32) -> bool {
+    SUPPORTED_PROTOCOLS.contains(&protocol)
+}
+
+/// The game versions a protocol number corresponds to.
+pub fn version_name(protocol: u32) -> Option<&'static str> {
+    match protocol {
+        765 => Some("1.20.3-1.20.4"),
+        766 => Some("1.20.5-1.20.6"),
+        767 => Some("1.21-1.21.1"),
+        768 => Some("1.21.2-1.21.3"),
+        769 => Some("1.21.4"),
+        _ => None,
+    }
+}
+
+/// Resolves the ID of `kind` for a connection speaking `protocol`.
+pub fn packet_id(kind: PacketKind, protocol: u32) -> Option<u32> {
+    match kind.ids() {
+        PacketIds::Fixed(id) => Some(id),
+        PacketIds::PerVersion(ids) => SUPPORTED_PROTOCOLS
+            .iter()
+            .position(|supported| *supported == protocol)
+            .map(|index| ids[index]),
+        PacketIds::Partial(ids) => SUPPORTED_PROTOCOLS
+            .iter()
+            .position(|supported| *supported == protocol)
+            .and_then(|index| ids[index]),
+    }
+}
+
+/// Identifies a packet seen on the wire, if the registry knows it.
+pub fn packet_kind(
+    state: &ConnectionState,
+    direction: PacketDirection,
+    id: u32,
+    protocol: u32,
+) -> Option<PacketKind> {
+    PacketKind::ALL.into_iter().find(|kind| {
+        kind.state() == *state
+            && kind.direction() == direction
+            && packet_id(*kind, protocol) == Some(id)
+    })
+}
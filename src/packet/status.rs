@@ -0,0 +1,62 @@
+use azalea_chat::FormattedText;
+use serde::Serialize;
+
+use super::{Packet, PlayerboundPacket, ProxyboundPacket};
+
+#[derive(Clone, Serialize)]
+pub struct StatusVersion {
+    pub name: String,
+    pub protocol: u32,
+}
+
+#[derive(Clone, Serialize)]
+pub struct StatusPlayers {
+    pub max: u32,
+    pub online: u32,
+}
+
+/// The JSON document sent back to clients in the server list.
+#[derive(Clone, Serialize)]
+pub struct ServerStatus {
+    pub version: StatusVersion,
+    pub players: StatusPlayers,
+    pub description: FormattedText,
+}
+
+#[derive(Clone, Packet)]
+#[packet(kind = StatusRequest)]
+pub struct StatusRequestPacket {}
+
+impl ProxyboundPacket for StatusRequestPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = StatusResponse)]
+pub struct StatusResponsePacket {
+    pub status: String,
+}
+
+impl PlayerboundPacket for StatusResponsePacket {}
+
+impl StatusResponsePacket {
+    pub fn from_status(status: &ServerStatus) -> StatusResponsePacket {
+        StatusResponsePacket {
+            status: serde_json::to_string(status).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Packet)]
+#[packet(kind = PingRequest)]
+pub struct PingRequestPacket {
+    pub payload: i64,
+}
+
+impl ProxyboundPacket for PingRequestPacket {}
+
+#[derive(Clone, Packet)]
+#[packet(kind = PongResponse)]
+pub struct PongResponsePacket {
+    pub payload: i64,
+}
+
+impl PlayerboundPacket for PongResponsePacket {}
@@ -0,0 +1,491 @@
+//! An empty world inside the proxy that holds players while no server can
+//! take them. The player spawns as a spectator in the void, where no chunks
+//! need to be sent, and is moved to the first server that becomes available
+//! or to the one it is sent to.
+
+use std::{
+    io::{Error, ErrorKind},
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, oneshot},
+    task,
+    time::{self, MissedTickBehavior},
+};
+use tracing::{info, trace, Instrument, Span};
+
+use crate::{
+    command::legacy_text,
+    packet::{
+        configuration::{
+            AcknowledgeFinishConfigurationPacket, ClientboundKnownPacksPacket, FinishConfigurationPacket, KnownPack,
+            RegistryDataPacket, RegistryEntry, ServerboundKnownPacksPacket,
+        },
+        login::{LoginAcknowledgedPacket, LoginSuccessPacket},
+        play::{
+            ChatCommandPacket, ConfigurationAcknowledgedPacket, GameEventPacket, PlayKeepAlivePacket, PlayLoginPacket,
+            SetSubtitleTextPacket, SetTitleAnimationTimesPacket, SetTitleTextPacket, StartConfigurationPacket,
+            SynchronizePlayerPositionPacket,
+        },
+        registry, Packet, PacketError, RawPacket,
+    },
+    players_by_server,
+};
+
+use super::{switch::Request, ConnectionState, PlayerConnection, PlayerProxyConnection};
+
+/// How often players in limbo are sent a keep-alive, well within the time
+/// clients wait for one.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tells the client to wait for the chunks around it, which it needs before
+/// it leaves the loading screen.
+const WAIT_FOR_CHUNKS: u8 = 13;
+
+const SPECTATOR: u8 = 3;
+
+/// The world players in limbo spawn in, which uses the overworld's type.
+const LIMBO_DIMENSION: &str = "minecraft:overworld";
+
+/// What players in limbo are told, e.g. `[limbo]` with
+/// `title = "§cServers are down"`. Every field is optional.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LimboConfiguration {
+    /// Shown in chat when the player arrives.
+    pub message: String,
+    /// Shown in the middle of the screen for as long as the player stays.
+    pub title: String,
+    pub subtitle: String,
+    /// Seconds between attempts to move the player to a server.
+    pub retry_interval: u64,
+}
+
+impl Default for LimboConfiguration {
+    fn default() -> Self {
+        LimboConfiguration {
+            message: "§eNo server is available right now. You will be moved to one as soon as it is back.".to_owned(),
+            title: "§cServers are down".to_owned(),
+            subtitle: "§7Please wait".to_owned(),
+            retry_interval: 5,
+        }
+    }
+}
+
+impl LimboConfiguration {
+    pub fn retry_interval(&self) -> Duration {
+        Duration::from_secs(self.retry_interval.max(1))
+    }
+}
+
+/// Whether players speaking `protocol` can be held in limbo. Clients before
+/// 1.20.5 need every registry in full, which the proxy does not have.
+pub fn supports(protocol: u32) -> bool {
+    RegistryDataPacket::id(protocol).is_some()
+}
+
+/// Handed from the task waiting for a server to the player's connection
+/// once the player was sent back to the Configuration state.
+struct Departure {
+    requests: UnboundedReceiver<Request>,
+    connection: PlayerProxyConnection,
+    /// Reports the outcome to whoever sent the player, if anyone did.
+    done: Option<oneshot::Sender<Result<(), Error>>>,
+}
+
+impl PlayerConnection {
+    /// Whether the player waits in limbo for a server.
+    pub fn in_limbo(&self) -> bool {
+        self.limbo.load(Ordering::Relaxed)
+    }
+
+    /// Completes the login of a player no server could take, holds it in
+    /// limbo and moves it to a server as soon as one is available. Returns
+    /// once the player is in the Configuration state with that server, or
+    /// with an error when it left.
+    pub(crate) async fn hold_in_limbo(&mut self, config: &LimboConfiguration) -> Result<(), Error> {
+        let Some(requests) = self.request_receiver.take() else {
+            return Err(Error::new(ErrorKind::Unsupported, "this handle cannot hold the player"));
+        };
+
+        let result = self.wait_in_limbo(config, requests).await;
+        self.limbo.store(false, Ordering::Relaxed);
+        result
+    }
+
+    async fn wait_in_limbo(&mut self, config: &LimboConfiguration, requests: UnboundedReceiver<Request>) -> Result<(), Error> {
+        self.enter_limbo().await?;
+        info!("Held in limbo");
+        let _ = self.send_message(legacy_text(&config.message)).await;
+
+        let (depart, mut departure) = oneshot::channel();
+        let waiter = task::spawn(
+            wait_for_server(self.clone(), config.clone(), requests, depart).instrument(Span::current()),
+        );
+
+        // Only this handle reads from the player, so no packet is ever cut
+        // off; the waiter has it leave once a server took it.
+        let departure = loop {
+            let packet = match self.read_packet().await {
+                Ok(packet) => packet,
+                Err(e) => {
+                    waiter.abort();
+                    return Err(e.into());
+                }
+            };
+
+            if packet.is::<ConfigurationAcknowledgedPacket>(self.protocol) {
+                match departure.try_recv() {
+                    Ok(departure) => break departure,
+                    Err(oneshot::error::TryRecvError::Empty) => continue,
+                    Err(oneshot::error::TryRecvError::Closed) => {
+                        return Err(Error::other("stopped waiting for a server"));
+                    }
+                }
+            }
+
+            if packet.is::<ChatCommandPacket>(self.protocol) {
+                if let Ok(command) = packet.decode_as::<ChatCommandPacket>() {
+                    self.execute_command(command.command).await;
+                }
+            }
+        };
+
+        self.request_receiver = Some(departure.requests);
+        self.finish_switch(departure.connection, Ok(())).await?;
+
+        if let Some(done) = departure.done {
+            let _ = done.send(Ok(()));
+        }
+
+        Ok(())
+    }
+
+    /// Logs the player in, configures it and spawns it in the void.
+    async fn enter_limbo(&mut self) -> Result<(), Error> {
+        let Some(info) = self.player_info.lock().await.clone() else {
+            return Err(Error::new(ErrorKind::NotConnected, "the player has not logged in"));
+        };
+
+        self.send_packet(&LoginSuccessPacket {
+            uuid: info.uuid,
+            username: info.username,
+            strict_error_handling: matches!(self.protocol, 766 | 767).then_some(false),
+        })?;
+        self.read_until::<LoginAcknowledgedPacket>().await?;
+        self.state = ConnectionState::Configuration;
+
+        let packs = core_packs(self.protocol);
+        self.send_packet(&ClientboundKnownPacksPacket { packs: packs.clone() })?;
+
+        let known = self.read_until::<ServerboundKnownPacksPacket>().await?;
+        let known = known.decode_as::<ServerboundKnownPacksPacket>()?;
+        if !known.packs.iter().any(|pack| packs.contains(pack)) {
+            return Err(Error::new(ErrorKind::Unsupported, "the player lacks the vanilla data pack"));
+        }
+
+        for registry in registries(self.protocol) {
+            self.send_packet(&registry)?;
+        }
+
+        self.send_packet(&FinishConfigurationPacket {})?;
+        self.read_until::<AcknowledgeFinishConfigurationPacket>().await?;
+        self.state = ConnectionState::Play;
+        self.limbo.store(true, Ordering::Relaxed);
+
+        self.send_packet(&PlayLoginPacket {
+            protocol: self.protocol,
+            entity_id: 1,
+            dimensions: vec![LIMBO_DIMENSION.to_owned()],
+            max_players: 1,
+            view_distance: 2,
+            dimension_type: 0,
+            dimension: LIMBO_DIMENSION.to_owned(),
+            game_mode: SPECTATOR,
+            sea_level: 63,
+        })?;
+        self.send_packet(&GameEventPacket {
+            event: WAIT_FOR_CHUNKS,
+            value: 0.0,
+        })?;
+        self.send_packet(&SynchronizePlayerPositionPacket {
+            protocol: self.protocol,
+            teleport_id: 1,
+            x: 0.5,
+            y: 128.0,
+            z: 0.5,
+            yaw: 0.0,
+            pitch: 0.0,
+        })?;
+
+        Ok(())
+    }
+
+    /// Reads from the player until a `P` arrives, skipping what it sends in
+    /// between, e.g. its settings.
+    async fn read_until<P: Packet>(&mut self) -> Result<RawPacket, PacketError> {
+        loop {
+            let packet = self.read_packet().await?;
+            if packet.is::<P>(self.protocol) {
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+/// Keeps the player in limbo alive and shows it the title, until a server
+/// takes it. Then hands the server connection over through `depart` and
+/// sends the player back to the Configuration state.
+async fn wait_for_server(
+    player: PlayerConnection,
+    config: LimboConfiguration,
+    mut requests: UnboundedReceiver<Request>,
+    depart: oneshot::Sender<Departure>,
+) {
+    let uuid = player.uuid().await.unwrap_or_default();
+    let mut keep_alive = time::interval(KEEP_ALIVE_INTERVAL);
+    let mut retry = time::interval(config.retry_interval());
+    retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut fade_in = 10;
+
+    let (connection, done) = loop {
+        tokio::select! {
+            _ = keep_alive.tick() => {
+                let id = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+                let _ = player.send_packet(&PlayKeepAlivePacket { id });
+            }
+            _ = retry.tick() => {
+                // Shown again before it fades, so it stays up while the
+                // player waits.
+                let stay = (config.retry_interval().as_secs() * 20 + 40) as i32;
+                let _ = player.send_packet(&SetTitleAnimationTimesPacket { fade_in, stay, fade_out: 20 });
+                let _ = player.send_packet(&SetSubtitleTextPacket { text: legacy_text(&config.subtitle) });
+                let _ = player.send_packet(&SetTitleTextPacket { text: legacy_text(&config.title) });
+                fade_in = 0;
+
+                let players = players_by_server(&player.proxy_instance).await;
+                let server = player.proxy_instance.read().await.initial_server(uuid, &players);
+                let Some((key, server)) = server else {
+                    continue;
+                };
+
+                match player.prepare_switch(&server).await {
+                    Ok(connection) => {
+                        info!("Moving out of limbo to {}", key);
+                        break (connection, None);
+                    }
+                    Err(e) => trace!("Could not move out of limbo to {}: {}", key, e),
+                }
+            }
+            Some(request) = requests.recv() => match request {
+                Request::Switch { server, done } => match player.prepare_switch(&server).await {
+                    Ok(connection) => break (connection, Some(done)),
+                    Err(e) => {
+                        let _ = done.send(Err(e));
+                    }
+                },
+            },
+        }
+    };
+
+    let departure = Departure {
+        requests,
+        connection,
+        done,
+    };
+
+    if depart.send(departure).is_ok() {
+        let _ = player.send_packet(&StartConfigurationPacket {});
+    }
+}
+
+/// The vanilla data pack of the versions speaking `protocol`, offered so the
+/// registries can be sent without their data.
+fn core_packs(protocol: u32) -> Vec<KnownPack> {
+    // Each protocol version spans two game versions at most, e.g.
+    // "1.21-1.21.1".
+    registry::version_name(protocol)
+        .into_iter()
+        .flat_map(|versions| versions.split('-'))
+        .map(|version| KnownPack {
+            namespace: "minecraft".to_owned(),
+            id: "core".to_owned(),
+            version: version.to_owned(),
+        })
+        .collect()
+}
+
+/// The registry entries a client needs to join a world. The damage types are
+/// those every client looks up as it creates one, and wolf and painting
+/// variants must not be empty.
+fn registries(protocol: u32) -> Vec<RegistryDataPacket> {
+    let mut damage_types = vec![
+        "in_fire",
+        "lightning_bolt",
+        "on_fire",
+        "lava",
+        "hot_floor",
+        "in_wall",
+        "cramming",
+        "drown",
+        "starve",
+        "cactus",
+        "fall",
+        "fly_into_wall",
+        "out_of_world",
+        "generic",
+        "magic",
+        "wither",
+        "dragon_breath",
+        "dry_out",
+        "sweet_berry_bush",
+        "freeze",
+        "stalagmite",
+        "outside_border",
+        "generic_kill",
+    ];
+    if protocol >= 768 {
+        damage_types.extend(["campfire", "ender_pearl"]);
+    }
+
+    let mut registries = vec![
+        ("dimension_type", vec!["overworld"]),
+        ("worldgen/biome", vec!["plains"]),
+        ("damage_type", damage_types),
+        ("wolf_variant", vec!["pale"]),
+    ];
+    if protocol >= 767 {
+        registries.push(("painting_variant", vec!["kebab"]));
+    }
+
+    registries
+        .into_iter()
+        .map(|(registry, entries)| RegistryDataPacket {
+            registry: format!("minecraft:{}", registry),
+            entries: entries
+                .into_iter()
+                .map(|entry| RegistryEntry {
+                    id: format!("minecraft:{}", entry),
+                    data: None,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::data::{ByteBuf, ByteBufMut};
+    use uuid::Uuid;
+
+    fn encoded(packet: &impl Packet) -> Vec<u8> {
+        let mut buffer = ByteBufMut::new();
+        packet.write_to(&mut buffer).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn only_versions_with_known_packs_are_supported() {
+        assert!(!supports(765));
+        assert!(!supports(0));
+        for protocol in 766..=registry::LATEST_PROTOCOL {
+            assert!(supports(protocol), "{}", protocol);
+            assert!(!core_packs(protocol).is_empty());
+        }
+
+        let versions: Vec<String> = core_packs(767).into_iter().map(|pack| pack.version).collect();
+        assert_eq!(versions, ["1.21", "1.21.1"]);
+    }
+
+    #[test]
+    fn registries_round_trip() {
+        for protocol in 766..=registry::LATEST_PROTOCOL {
+            let registries = registries(protocol);
+
+            for registry in &registries {
+                let decoded = RegistryDataPacket::decode(&encoded(registry)).unwrap();
+                assert_eq!(decoded.registry, registry.registry);
+                assert!(!decoded.entries.is_empty());
+
+                let ids: Vec<&str> = decoded.entries.iter().map(|entry| entry.id.as_str()).collect();
+                let expected: Vec<&str> = registry.entries.iter().map(|entry| entry.id.as_str()).collect();
+                assert_eq!(ids, expected);
+                assert!(ids.iter().all(|id| id.starts_with("minecraft:")));
+                assert!(decoded.entries.iter().all(|entry| entry.data.is_none()));
+            }
+
+            let names: Vec<&str> = registries.iter().map(|registry| registry.registry.as_str()).collect();
+            assert_eq!(names.contains(&"minecraft:painting_variant"), protocol >= 767);
+
+            let damage_types = registries.iter().find(|registry| registry.registry == "minecraft:damage_type").unwrap();
+            let campfire = damage_types.entries.iter().any(|entry| entry.id == "minecraft:campfire");
+            assert_eq!(campfire, protocol >= 768);
+        }
+    }
+
+    #[test]
+    fn login_success_round_trips() {
+        for strict_error_handling in [None, Some(false)] {
+            let packet = LoginSuccessPacket {
+                uuid: Uuid::new_v4(),
+                username: "rustyproxy".to_owned(),
+                strict_error_handling,
+            };
+
+            let decoded = LoginSuccessPacket::decode(&encoded(&packet)).unwrap();
+            assert_eq!(decoded.uuid, packet.uuid);
+            assert_eq!(decoded.username, packet.username);
+            assert_eq!(decoded.strict_error_handling, strict_error_handling);
+        }
+    }
+
+    #[test]
+    fn play_login_is_laid_out_per_version() {
+        for protocol in [767, 768] {
+            let packet = PlayLoginPacket {
+                protocol,
+                entity_id: 1,
+                dimensions: vec![LIMBO_DIMENSION.to_owned()],
+                max_players: 1,
+                view_distance: 2,
+                dimension_type: 0,
+                dimension: LIMBO_DIMENSION.to_owned(),
+                game_mode: SPECTATOR,
+                sea_level: 63,
+            };
+            let bytes = encoded(&packet);
+            let mut buffer = ByteBuf::new(&bytes);
+
+            assert_eq!(buffer.read_i32().unwrap(), 1);
+            assert!(!buffer.read_bool().unwrap());
+            let dimensions = buffer.read_prefixed_array(usize::MAX, ByteBuf::read_string).unwrap();
+            assert_eq!(dimensions, [LIMBO_DIMENSION]);
+            assert_eq!(buffer.read_varint().unwrap(), 1);
+            assert_eq!(buffer.read_varint().unwrap(), 2);
+            assert_eq!(buffer.read_varint().unwrap(), 2);
+            assert!(!buffer.read_bool().unwrap());
+            assert!(buffer.read_bool().unwrap());
+            assert!(!buffer.read_bool().unwrap());
+            assert_eq!(buffer.read_varint().unwrap(), 0);
+            assert_eq!(buffer.read_string().unwrap(), LIMBO_DIMENSION);
+            assert_eq!(buffer.read_i64().unwrap(), 0);
+            assert_eq!(buffer.read_u8().unwrap(), SPECTATOR);
+            assert_eq!(buffer.read_i8().unwrap(), -1);
+            for _ in 0..3 {
+                assert!(!buffer.read_bool().unwrap());
+            }
+            assert_eq!(buffer.read_varint().unwrap(), 0);
+
+            // 1.21.2 added the sea level before the last field.
+            if protocol >= 768 {
+                assert_eq!(buffer.read_varint().unwrap(), 63);
+            }
+            assert!(!buffer.read_bool().unwrap());
+            assert!(buffer.is_empty());
+        }
+    }
+}
@@ -1,38 +1,105 @@
+pub mod limbo;
+mod outbound;
+pub mod stats;
+mod switch;
+
 use std::{
     io::{Error, ErrorKind},
     net::SocketAddr,
-    sync::Arc,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use azalea_chat::text_component::TextComponent;
+use bytes::BytesMut;
+use outbound::{Outbound, OutboundMessage};
+use serde::Deserialize;
+use stats::ConnectionStats;
+use switch::Request;
+use tracing::{error, info, trace, warn, Instrument, Span};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    sync::{Mutex, RwLock},
+    io::AsyncReadExt,
+    net::{tcp::OwnedReadHalf, TcpStream},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot, Mutex, RwLock,
+    },
+    task,
+    time::{sleep_until, Instant},
 };
 use uuid::Uuid;
 
 use crate::{
-    event::{EventBus, EventResult, PlayerJoinedServer, ServerSentPacket},
+    command::{legacy_text, CommandSender},
+    event::{EventBus, EventResult, PlayerJoinedServer, PlayerLatencyMeasured, ServerSentPacket},
     packet::{
-        self, data, handshake::HandshakePacket, login::LoginStartPacket,
-        play::SystemChatMessagePacket, PlayerboundPacket, RawPacket,
+        self,
+        data::{ByteBuf, ByteBufMut},
+        handshake::HandshakePacket,
+        login::LoginStartPacket,
+        play::ChatCommandPacket,
+        registry::{self, PacketKind},
+        FrameLimits, PacketDirection, PacketError, PacketLimits, PlayerboundPacket, RawPacket,
     },
     server::ProxiedServer,
     ProxyInstance, SharedProxyInstance,
 };
 
-#[derive(Clone)]
+/// A handle to a player's connection. Clones share the outbound queue and can
+/// send packets from anywhere; only the original reads from the player.
 pub struct PlayerConnection {
     pub proxy_instance: Arc<RwLock<ProxyInstance>>,
     pub addr: SocketAddr,
 
-    cnx: Arc<Mutex<TcpStream>>,
+    reader: Option<OwnedReadHalf>,
+    outbound: Outbound,
     pub server: Arc<Mutex<Option<PlayerProxyConnection>>>,
 
     pub player_info: Arc<Mutex<Option<PlayerInfo>>>,
-    pub compression_threshold: u32,
+    compression_threshold: Arc<AtomicU32>,
+    /// Protocol version the player announced in its handshake.
+    pub protocol: u32,
+    /// The state the player is in, which decides the size limits of the
+    /// packets read from it.
+    pub state: ConnectionState,
+    pub limits: PacketLimits,
+    pub outbound_limits: OutboundLimits,
+    stats: Arc<ConnectionStats>,
     event_bus: Arc<EventBus>,
+
+    /// Asks the task forwarding the player's traffic to act on it.
+    requests: UnboundedSender<Request>,
+    /// Only the original handle forwards traffic and receives requests.
+    request_receiver: Option<UnboundedReceiver<Request>>,
+    /// Whether the player is held in limbo, where it plays without a server.
+    limbo: Arc<AtomicBool>,
+}
+
+impl Clone for PlayerConnection {
+    fn clone(&self) -> Self {
+        PlayerConnection {
+            proxy_instance: Arc::clone(&self.proxy_instance),
+            addr: self.addr,
+            reader: None,
+            outbound: self.outbound.clone(),
+            server: Arc::clone(&self.server),
+            player_info: Arc::clone(&self.player_info),
+            compression_threshold: Arc::clone(&self.compression_threshold),
+            protocol: self.protocol,
+            state: self.state.clone(),
+            limits: self.limits,
+            outbound_limits: self.outbound_limits,
+            stats: Arc::clone(&self.stats),
+            event_bus: Arc::clone(&self.event_bus),
+            requests: self.requests.clone(),
+            request_receiver: None,
+            limbo: Arc::clone(&self.limbo),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -42,10 +109,43 @@ pub struct PlayerInfo {
 }
 
 pub struct PlayerProxyConnection {
-    cnx: TcpStream,
-    player: Arc<Mutex<PlayerConnection>>,
+    outbound: Outbound,
+    /// Taken by the task forwarding the server's packets to the player.
+    reader: Option<OwnedReadHalf>,
     server: Arc<ProxiedServer>,
     pub state: Option<ConnectionState>,
+    /// The threshold the server compresses with, which can differ from the
+    /// player's after a server switch.
+    compression_threshold: u32,
+}
+
+/// How much may be queued for a socket that writes slower than the other
+/// side of the proxy reads.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct OutboundLimits {
+    /// Queued bytes above which the connection is considered too slow.
+    pub high_water_mark: usize,
+    pub on_overflow: OverflowAction,
+}
+
+impl Default for OutboundLimits {
+    fn default() -> Self {
+        OutboundLimits {
+            high_water_mark: 4 * 1024 * 1024,
+            on_overflow: OverflowAction::Pause,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowAction {
+    /// Stop reading from the other side until the queue drained to half the
+    /// high-water mark.
+    Pause,
+    /// Disconnect the player.
+    Disconnect,
 }
 
 #[derive(PartialEq, Eq)]
@@ -64,9 +164,10 @@ pub enum TrafficForwardingResult {
     ServerKickedPlayer,
 }
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum ConnectionState {
     Handshake,
+    Status,
     Login,
     Configuration,
     Play,
@@ -79,14 +180,26 @@ impl PlayerConnection {
         proxy_instance: &SharedProxyInstance,
         event_bus: &Arc<EventBus>,
     ) -> PlayerConnection {
+        let (reader, writer) = cnx.into_split();
+        let (requests, request_receiver) = mpsc::unbounded_channel();
+
         PlayerConnection {
-            compression_threshold: 0,
-            cnx: Arc::new(Mutex::new(cnx)),
+            compression_threshold: Arc::new(AtomicU32::new(0)),
+            protocol: registry::LATEST_PROTOCOL,
+            state: ConnectionState::Handshake,
+            limits: PacketLimits::default(),
+            outbound_limits: OutboundLimits::default(),
+            stats: Arc::new(ConnectionStats::default()),
+            reader: Some(reader),
+            outbound: Outbound::spawn(writer),
             addr,
             server: Arc::new(Mutex::const_new(None)),
             player_info: Arc::new(Mutex::const_new(None)),
             proxy_instance: Arc::clone(proxy_instance),
             event_bus: event_bus.clone(),
+            requests,
+            request_receiver: Some(request_receiver),
+            limbo: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -95,8 +208,29 @@ impl PlayerConnection {
         *info_guard = Some(info)
     }
 
+    pub(crate) fn event_bus(&self) -> &Arc<EventBus> {
+        &self.event_bus
+    }
+
+    /// Traffic counters and latency of the connection.
+    pub fn stats(&self) -> &Arc<ConnectionStats> {
+        &self.stats
+    }
+
+    /// The compression threshold set by the server, or 0 if none.
+    pub fn compression_threshold(&self) -> u32 {
+        self.compression_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Sets the compression threshold for everything read from and sent to
+    /// the player from now on.
+    pub fn set_compression_threshold(&self, threshold: u32) {
+        self.compression_threshold.store(threshold, Ordering::Relaxed);
+        let _ = self.outbound.send(OutboundMessage::Compression(threshold));
+    }
+
     pub async fn close(&mut self) -> Result<(), Error> {
-        self.cnx.lock().await.shutdown().await?;
+        self.outbound.close();
 
         let mut proxy_cnx = self.server.lock().await;
 
@@ -108,6 +242,17 @@ impl PlayerConnection {
         Ok(())
     }
 
+    /// Closes the connection after failing to read from the player, logging
+    /// the error when the player sent something that breaks the protocol.
+    pub async fn close_after(&mut self, error: &PacketError) {
+        if error.is_malformed() {
+            warn!("Disconnecting after a malformed packet: {}", error);
+            self.proxy_instance.read().await.metrics.connection_rejected("malformed");
+        }
+
+        let _ = self.close().await;
+    }
+
     pub(crate) async fn server_closed(&mut self) -> Result<(), Error> {
         let old_server: Arc<ProxiedServer>;
         {
@@ -135,10 +280,8 @@ impl PlayerConnection {
             }
         }
 
-        let cloned_player = Arc::new(Mutex::new(self.clone()));
-
         let event = Arc::new(PlayerJoinedServer {
-            connection: cloned_player.clone(),
+            connection: Arc::new(Mutex::new(self.clone())),
             server: server.clone(),
         });
 
@@ -150,48 +293,64 @@ impl PlayerConnection {
             }
         }
 
-        let mut connection = PlayerProxyConnection {
-            cnx: server.establish_connection().await?,
-            player: cloned_player.clone(),
+        let connection = self.open_connection(server).await?;
+        self.compression_threshold.store(0, Ordering::Relaxed);
+
+        {
+            let mut server = self.server.lock().await;
+            *server = Some(connection);
+        }
+
+        Span::current().record("server", server.name.as_str());
+        Ok(ConnectionResult::Success)
+    }
+
+    /// Connects to `server` and starts logging in as the player.
+    async fn open_connection(&self, server: &Arc<ProxiedServer>) -> Result<PlayerProxyConnection, Error> {
+        let (reader, writer) = server.establish_connection().await?.into_split();
+        let connection = PlayerProxyConnection {
+            outbound: Outbound::spawn(writer),
+            reader: Some(reader),
             server: Arc::clone(server),
             state: Some(ConnectionState::Login),
+            compression_threshold: 0,
         };
 
-        self.compression_threshold = 0;
-
-        packet::send_packet(
+        // Send a handshake as soon as we establish a connection
+        connection.outbound.send_packet(
             &HandshakePacket {
-                protocol: 769,
+                protocol: self.protocol,
                 server_address: server.address.clone(),
                 port: server.port,
                 next_state: 2,
             },
-            &mut connection.cnx,
-            self.compression_threshold,
-        )
-        .await?; // Send a handshake as soon as we establish a connection
+            self.protocol,
+        )?;
 
         {
             let info = self.player_info.lock().await;
             let info = info.as_ref().unwrap();
 
-            packet::send_packet(
+            connection.outbound.send_packet(
                 &LoginStartPacket {
                     username: info.username.to_owned(),
                     uuid: info.uuid,
                 },
-                &mut connection.cnx,
-                self.compression_threshold,
-            )
-            .await?;
+                self.protocol,
+            )?;
         }
 
-        {
-            let mut server = self.server.lock().await;
-            *server = Some(connection);
-        }
+        Ok(connection)
+    }
 
-        Ok(ConnectionResult::Success)
+    pub async fn uuid(&self) -> Option<Uuid> {
+        self.player_info.lock().await.as_ref().map(|info| info.uuid)
+    }
+
+    /// The server the player is connected to, if any.
+    pub async fn current_server(&self) -> Option<Arc<ProxiedServer>> {
+        let server = self.server.lock().await;
+        server.as_ref().map(|server| Arc::clone(&server.server))
     }
 
     pub async fn is_connected(&self) -> bool {
@@ -203,133 +362,596 @@ impl PlayerConnection {
     }
 
     pub(crate) async fn handle_traffic(&mut self) -> TrafficForwardingResult {
-        let mut server_guard = self.server.lock().await;
-        match server_guard.as_mut() {
-            None => return TrafficForwardingResult::PlayerDisconnected,
-            Some(_) => (),
+        let Some(mut requests) = self.request_receiver.take() else {
+            return TrafficForwardingResult::PlayerErrored;
         };
 
-        drop(server_guard);
-
-        let mut buffer = vec![0u8; 4096*12];
-
-        let mut buffer_recv = vec![0u8; 4096*12];
+        let result = self.forward_traffic(&mut requests).await;
+        self.request_receiver = Some(requests);
+        result
+    }
 
+    /// Forwards traffic both ways until either side disconnects, switching
+    /// servers in between when asked to.
+    async fn forward_traffic(&mut self, requests: &mut UnboundedReceiver<Request>) -> TrafficForwardingResult {
         loop {
-            tokio::select! {
-                result = async {
-                    let stream = &mut self.cnx;
-
-                    let mut stream = stream.lock().await;
-                    stream.read(&mut buffer).await
-                } => {
-                    match result {
-                        Ok(0) => return TrafficForwardingResult::PlayerDisconnected, // Client disconnected
-                        Ok(n) => {
-
-                            let mut server_guard = self.server.lock().await;
-                            let proxy_connection =  &mut server_guard.as_mut().unwrap().cnx;
-                            if let Err(e) = proxy_connection.write_all(&buffer[..n]).await {
-                                if e.kind() == std::io::ErrorKind::BrokenPipe {
-                                    return TrafficForwardingResult::PlayerErrored;
-                                }
+            let (server_outbound, server_reader) = {
+                let mut server_guard = self.server.lock().await;
+                match server_guard.as_mut() {
+                    None => return TrafficForwardingResult::PlayerDisconnected,
+                    Some(server) => (server.outbound.clone(), server.reader.take()),
+                }
+            };
+
+            let (Some(server_reader), Some(player_reader)) = (server_reader, self.reader.take()) else {
+                return TrafficForwardingResult::ServerErrored;
+            };
+
+            // Each direction is forwarded by its own task. The player's side is
+            // stopped rather than aborted so its read half can be used again.
+            let (stop, stopped) = oneshot::channel();
+            let mut player_task = task::spawn({
+                let player = self.clone();
+                async move { player.forward_player(player_reader, server_outbound, stopped).await }
+                    .instrument(Span::current())
+            });
+            let mut server_task = task::spawn({
+                let mut player = self.clone();
+                async move {
+                    let result = player.forward_server(server_reader).await;
+                    (player.state, result)
+                }
+                .instrument(Span::current())
+            });
+
+            // Traffic keeps flowing to the current server while the player
+            // logs in to the one it switches to.
+            let (connection, done) = loop {
+                tokio::select! {
+                    result = &mut player_task => {
+                        server_task.abort();
+                        return match result {
+                            Ok((reader, result)) => {
+                                self.reader = Some(reader);
+                                result
                             }
+                            Err(_) => TrafficForwardingResult::PlayerErrored,
+                        };
+                    }
+                    result = &mut server_task => {
+                        let _ = stop.send(());
+                        if let Ok((reader, _)) = player_task.await {
+                            self.reader = Some(reader);
                         }
-                        Err(e) => {
-                            eprintln!("Error reading from player: {:?}", e);
-                            return TrafficForwardingResult::PlayerErrored;
-                        }
+
+                        return match result {
+                            Ok((state, result)) => {
+                                self.state = state;
+                                result
+                            }
+                            Err(_) => TrafficForwardingResult::ServerErrored,
+                        };
                     }
+                    Some(request) = requests.recv() => match request {
+                        Request::Switch { server, done } => match self.prepare_switch(&server).await {
+                            Ok(connection) => break (connection, done),
+                            Err(e) => {
+                                let _ = done.send(Err(e));
+                            }
+                        },
+                    },
                 }
+            };
 
-                result = async {
-                    let mut server_guard = self.server.lock().await;
-                    let proxy_connection =  &mut server_guard.as_mut().unwrap().cnx;
-                    proxy_connection.read(&mut buffer_recv).await
-                } => {
-                    match result {
-                        Ok(0) => return TrafficForwardingResult::ServerDisconnectedPlayer(), // Proxy server disconnected
-                        Ok(n) => {
-                            let mut server_guard = self.server.lock().await;
-                            let server = &mut server_guard.as_mut().unwrap();
-
-                            let state = {
-                                server.state.clone().unwrap_or_else(|| ConnectionState::Handshake)
-                            };
+            server_task.abort();
+            let _ = stop.send(());
 
-                            let (length, id, data) = packet::read_packet_from_bytes(&buffer_recv, self.compression_threshold).unwrap();
-                            if id == 0x03 && state == ConnectionState::Login { // Login Compression
-                                self.compression_threshold = data::read_varint(&data, &mut 0).unwrap();
-                            } // Change this so the proxy is the one to set the compression threshold.
+            match player_task.await {
+                Ok((reader, TrafficForwardingResult::ServerDisconnectedPlayer())) => self.reader = Some(reader),
+                Ok((reader, result)) => {
+                    self.reader = Some(reader);
+                    let _ = done.send(Err(Error::new(ErrorKind::NotConnected, "the player disconnected")));
+                    return result;
+                }
+                Err(_) => return TrafficForwardingResult::PlayerErrored,
+            }
 
-                            if id == 0x02 && state == ConnectionState::Login {
-                                server.state = Some(ConnectionState::Configuration);
+            if let Err(e) = self.complete_switch(connection).await {
+                warn!("Failed to switch servers: {}", e);
+                let _ = done.send(Err(e));
+                return TrafficForwardingResult::PlayerErrored;
+            }
+
+            let _ = done.send(Ok(()));
+        }
+    }
+
+    /// Forwards everything the server sends to the player, inspecting whole
+    /// packets along the way.
+    async fn forward_server(&mut self, mut reader: OwnedReadHalf) -> TrafficForwardingResult {
+        // Bytes received from the server that do not form a whole packet yet
+        let mut pending = BytesMut::with_capacity(4096*12);
+        let mut compression_threshold = {
+            let server = self.server.lock().await;
+            server.as_ref().map_or(0, |server| server.compression_threshold)
+        };
+
+        loop {
+            pending.reserve(4096*12);
+            match reader.read_buf(&mut pending).await {
+                Ok(0) => return TrafficForwardingResult::ServerDisconnectedPlayer(), // Proxy server disconnected
+                Ok(_) => {
+                    // Only whole packets are inspected and forwarded; a partial one
+                    // stays in `pending` until the rest arrives.
+                    let mut forwarded = Vec::new();
+                    let mut compression_changed = None;
+                    let mut consumed = 0;
+                    let mut kicked = false;
+                    let limits = self.server_limits().await;
+
+                    while let Some(frame_length) = match packet::frame_length(&pending[consumed..], limits) {
+                        Ok(frame_length) => frame_length,
+                        Err(e) => {
+                            error!("Malformed packet from proxied server: {}", e);
+                            return TrafficForwardingResult::ServerErrored;
+                        }
+                    } {
+                        let range = consumed..consumed + frame_length;
+                        let frame = &pending[range.clone()];
+                        consumed += frame_length;
+
+                        let id = match packet::peek_packet_id(frame, compression_threshold) {
+                            Ok(id) => id,
+                            Err(e) => {
+                                error!("Malformed packet from proxied server: {}", e);
+                                return TrafficForwardingResult::ServerErrored;
+                            }
+                        };
+
+                        self.stats.record(
+                            PacketDirection::Playerbound,
+                            &self.state,
+                            id,
+                            frame_length,
+                            packet::peek_uncompressed_length(frame, compression_threshold),
+                        );
+
+                        // Frames can only be passed on as they are while both sides
+                        // compress alike; otherwise they are framed again.
+                        let transcode = compression_threshold != self.compression_threshold();
+
+                        let kind = registry::packet_kind(&self.state, PacketDirection::Playerbound, id, self.protocol);
+                        if matches!(kind, Some(PacketKind::PlayKeepAlive | PacketKind::ConfigurationKeepAlive)) {
+                            if let Some(id) = read_keep_alive(frame, compression_threshold, limits) {
+                                self.stats.keep_alive_sent(id);
                             }
+                        }
 
-                            if id == 0x02 && state == ConnectionState::Configuration {
-                                server.state = Some(ConnectionState::Play)
+                        // In Play, packets nobody listens for are passed on after
+                        // reading just their ID.
+                        if self.state == ConnectionState::Play
+                            && !transcode
+                            && kind != Some(PacketKind::PlayDisconnect)
+                            && !self.event_bus.wants_packet(kind)
+                        {
+                            forward(&mut forwarded, range);
+                            continue;
+                        }
+
+                        let packet = match packet::read_packet_from_bytes(frame, compression_threshold, limits) {
+                            Ok(packet) => packet,
+                            Err(e) => {
+                                error!("Malformed packet from proxied server: {}", e);
+                                return TrafficForwardingResult::ServerErrored;
+                            }
+                        };
+
+                        let mut server_guard = self.server.lock().await;
+                        let server = &mut server_guard.as_mut().unwrap();
+
+                        let state = {
+                            server.state.clone().unwrap_or(ConnectionState::Handshake)
+                        };
+
+                        let kind = registry::packet_kind(&state, PacketDirection::Playerbound, packet.id, self.protocol);
+                        match kind {
+                            Some(PacketKind::SetCompression) => {
+                                match ByteBuf::new(&packet.data).read_varint() {
+                                    Ok(threshold) => {
+                                        compression_threshold = threshold;
+                                        server.compression_threshold = threshold;
+                                        self.compression_threshold.store(threshold, Ordering::Relaxed);
+                                        let _ = server.outbound.send(OutboundMessage::Compression(threshold));
+                                        compression_changed = Some(threshold);
+                                    }
+                                    Err(e) => {
+                                        error!("Malformed packet from proxied server: {}", e);
+                                        return TrafficForwardingResult::ServerErrored;
+                                    }
+                                }
+                            } // Change this so the proxy is the one to set the compression threshold.
+                            Some(PacketKind::LoginSuccess) => {
+                                server.state = Some(ConnectionState::Configuration);
+                                self.state = ConnectionState::Configuration;
                             }
+                            Some(PacketKind::FinishConfiguration) => {
+                                server.state = Some(ConnectionState::Play);
+                                self.state = ConnectionState::Play;
+                            }
+                            Some(PacketKind::PlayDisconnect) => {
+                                kicked = true;
+                                break;
+                            }
+                            _ => (),
+                        }
+
+                        drop(server_guard);
+
+                        {
+                            let cloned_player = Arc::new(Mutex::new(self.clone()));
 
-                            if id == 0x1D {
-                                return TrafficForwardingResult::ServerKickedPlayer;
+                            let event = Arc::new(ServerSentPacket {connection:cloned_player, packet, kind });
+                            let proceed = self.event_bus.dispatch(&event).await;
+
+                            if proceed == Some(EventResult::Stop) {
+                                trace!("Skipped sending a packet");
+                            } else if transcode {
+                                forwarded.push(Forward::Packet(reencode(&event.packet)));
+                            } else {
+                                forward(&mut forwarded, range);
                             }
+                        }
 
-                            drop(server_guard);
+                        // The player compresses once it received the packet, so the
+                        // threshold changes right after it in the queue.
+                        if let Some(threshold) = compression_changed.take() {
+                            forwarded.push(Forward::Compression(threshold));
+                        }
+                    }
 
-                            {
-                                let cloned_player = Arc::new(Mutex::new(self.clone()));
+                    // Forwarded frames are slices of what was read, not copies
+                    let received = pending.split_to(consumed).freeze();
 
-                                let event = Arc::new(ServerSentPacket {connection:cloned_player, packet: (length,id,data.clone()) });
-                                let proceed = self.event_bus.dispatch(&event).await;
+                    for forward in forwarded {
+                        let message = match forward {
+                            Forward::Frames(range) => OutboundMessage::Raw(received.slice(range)),
+                            Forward::Packet(data) => OutboundMessage::Packet(data),
+                            Forward::Compression(threshold) => OutboundMessage::Compression(threshold),
+                        };
 
-                                if proceed == Some(EventResult::Stop) {
-                                    println!("Skipped sending a packet");
-                                    continue; // Skip sending this packet.
-                                }
+                        if self.outbound.send(message).is_err() {
+                            return TrafficForwardingResult::PlayerDisconnected;
+                        }
+                    }
+
+                    // A player that cannot keep up holds back the server.
+                    if let Err(e) = self.outbound.writable(&self.outbound_limits).await {
+                        if e.kind() == ErrorKind::NotConnected {
+                            return TrafficForwardingResult::PlayerDisconnected;
+                        }
+
+                        warn!("Disconnecting slow player: {}", e);
+                        return TrafficForwardingResult::PlayerErrored;
+                    }
+
+                    if kicked {
+                        return TrafficForwardingResult::ServerKickedPlayer;
+                    }
+                }
+                Err(e) => {
+                    error!("Error reading from proxied server: {:?}", e);
+                    return TrafficForwardingResult::ServerErrored;
+                }
+            }
+        }
+    }
+
+    /// Forwards everything the player sends to the server as it is, until the
+    /// player disconnects or `stop` fires. The read half is handed back so the
+    /// player can be moved to another server, which is why a packet that only
+    /// partly arrived when stopping is waited for.
+    async fn forward_player(
+        &self,
+        mut reader: OwnedReadHalf,
+        server: Outbound,
+        mut stop: oneshot::Receiver<()>,
+    ) -> (OwnedReadHalf, TrafficForwardingResult) {
+        // Bytes received from the player that do not form a whole packet yet
+        let mut pending = BytesMut::with_capacity(4096*12);
+        let mut stopping = None;
+
+        loop {
+            if stopping.is_some() && pending.is_empty() {
+                return (reader, TrafficForwardingResult::ServerDisconnectedPlayer());
+            }
+
+            pending.reserve(4096*12);
+
+            let result = tokio::select! {
+                result = reader.read_buf(&mut pending) => result,
+                _ = &mut stop, if stopping.is_none() => {
+                    stopping = Some(Instant::now() + STOP_TIMEOUT);
+                    continue;
+                }
+                _ = sleep_until(stopping.unwrap_or_else(Instant::now)), if stopping.is_some() => {
+                    warn!("Player did not finish sending a packet in time");
+                    return (reader, TrafficForwardingResult::PlayerErrored);
+                }
+            };
+
+            match result {
+                Ok(0) => return (reader, TrafficForwardingResult::PlayerDisconnected), // Client disconnected
+                Ok(_) => {
+                    // Packets are only counted here, so whole ones are passed on
+                    // together and a partial one waits for the rest.
+                    let (state, server_threshold) = {
+                        let server = self.server.lock().await;
+                        match server.as_ref() {
+                            Some(server) => (
+                                server.state.clone().unwrap_or(ConnectionState::Handshake),
+                                server.compression_threshold,
+                            ),
+                            None => (ConnectionState::Handshake, 0),
+                        }
+                    };
+                    let limits = self.limits.frame_limits(&state, PacketDirection::Proxybound);
+                    let compression_threshold = self.compression_threshold();
+                    // After a server switch the two sides may compress differently
+                    let transcode = compression_threshold != server_threshold;
+                    let mut consumed = 0;
+                    // Frames of proxy commands, which the server never sees
+                    let mut intercepted = Vec::new();
+
+                    while let Some(frame_length) = match packet::frame_length(&pending[consumed..], limits) {
+                        Ok(frame_length) => frame_length,
+                        Err(e) => {
+                            warn!("Disconnecting after a malformed packet: {}", e);
+                            return (reader, TrafficForwardingResult::PlayerErrored);
+                        }
+                    } {
+                        let range = consumed..consumed + frame_length;
+                        let frame = &pending[range.clone()];
+                        consumed += frame_length;
+
+                        let id = match packet::peek_packet_id(frame, compression_threshold) {
+                            Ok(id) => id,
+                            Err(e) => {
+                                warn!("Disconnecting after a malformed packet: {}", e);
+                                return (reader, TrafficForwardingResult::PlayerErrored);
                             }
+                        };
+
+                        self.stats.record(
+                            PacketDirection::Proxybound,
+                            &state,
+                            id,
+                            frame_length,
+                            packet::peek_uncompressed_length(frame, compression_threshold),
+                        );
+
+                        if self.stats.awaiting_keep_alive() {
+                            self.answer_keep_alive(&state, id, frame, compression_threshold, limits);
+                        }
+
+                        let kind = registry::packet_kind(&state, PacketDirection::Proxybound, id, self.protocol);
+                        if kind == Some(PacketKind::ChatCommand) && self.run_command(frame, compression_threshold, limits).await {
+                            intercepted.push(range);
+                            continue;
+                        }
 
-                            let stream = &mut self.cnx;
-                            let mut stream = stream.lock().await;
-                            if let Err(e) = stream.write_all(&buffer_recv[..n]).await {
-                                if e.kind() == std::io::ErrorKind::BrokenPipe {
-                                    return TrafficForwardingResult::ServerDisconnectedPlayer();
+                        if transcode {
+                            let packet = match packet::read_packet_from_bytes(frame, compression_threshold, limits) {
+                                Ok(packet) => packet,
+                                Err(e) => {
+                                    warn!("Disconnecting after a malformed packet: {}", e);
+                                    return (reader, TrafficForwardingResult::PlayerErrored);
                                 }
+                            };
+
+                            if server.send(OutboundMessage::Packet(reencode(&packet))).is_err() {
+                                return (reader, TrafficForwardingResult::ServerDisconnectedPlayer());
                             }
                         }
-                        Err(e) => {
-                            eprintln!("Error reading from proxied server: {:?}", e);
-                            return TrafficForwardingResult::ServerErrored;
+                    }
+
+                    let received = pending.split_to(consumed).freeze();
+                    if !transcode {
+                        let mut start = 0;
+                        for skipped in intercepted.into_iter().chain(std::iter::once(consumed..consumed)) {
+                            if skipped.start > start
+                                && server.send(OutboundMessage::Raw(received.slice(start..skipped.start))).is_err()
+                            {
+                                return (reader, TrafficForwardingResult::ServerDisconnectedPlayer());
+                            }
+
+                            start = skipped.end;
                         }
                     }
+
+                    if stopping.is_some() {
+                        continue;
+                    }
+
+                    // Stop reading from the player while the server catches up.
+                    let writable = tokio::select! {
+                        writable = server.writable(&self.outbound_limits) => writable,
+                        _ = &mut stop => {
+                            stopping = Some(Instant::now() + STOP_TIMEOUT);
+                            continue;
+                        }
+                    };
+
+                    if let Err(e) = writable {
+                        if e.kind() == ErrorKind::NotConnected {
+                            return (reader, TrafficForwardingResult::ServerDisconnectedPlayer());
+                        }
+
+                        warn!("Disconnecting player from slow server: {}", e);
+                        return (reader, TrafficForwardingResult::PlayerErrored);
+                    }
+                }
+                Err(e) => {
+                    warn!("Error reading from player: {:?}", e);
+                    return (reader, TrafficForwardingResult::PlayerErrored);
                 }
             }
         }
     }
 
-    pub async fn read_packet(&mut self) -> Result<RawPacket, Error> {
-        let mut locked_connection = self.cnx.lock().await;
-        packet::read_packet(&mut locked_connection, self.compression_threshold).await
+    /// Runs the command in `frame` if it is a proxy command the player may
+    /// use, answering in chat. Returns whether the command was taken.
+    async fn run_command(&self, frame: &[u8], compression_threshold: u32, limits: FrameLimits) -> bool {
+        let Ok(command) = packet::read_packet_from_bytes(frame, compression_threshold, limits)
+            .and_then(|packet| packet.decode_as::<ChatCommandPacket>())
+        else {
+            return false;
+        };
+
+        self.execute_command(command.command).await
     }
 
-    pub async fn send_packet<P: PlayerboundPacket>(&mut self, packet: &P) -> Result<(), Error> {
-        let mut locked_connection = self.cnx.lock().await;
-        packet::send_packet(packet, &mut locked_connection, self.compression_threshold).await
+    /// Runs `command` if it is a proxy command the player may use, answering
+    /// in chat. Returns whether the command was taken.
+    async fn execute_command(&self, command: String) -> bool {
+        let (commands, instance) = {
+            let instance = self.proxy_instance.read().await;
+            (Arc::clone(&instance.commands), Arc::clone(&self.proxy_instance))
+        };
+
+        let sender = CommandSender::Player(self.clone());
+        if commands.find(&sender, &command).await.is_none() {
+            return false;
+        }
+
+        info!("Ran command: /{}", command);
+
+        let player = self.clone();
+        task::spawn(
+            async move {
+                let output = commands.execute_to_text(&instance, sender, &command).await;
+
+                for line in output.lines().filter(|line| !line.is_empty()) {
+                    let _ = player.send_message(legacy_text(line)).await;
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        true
     }
 
-    pub async fn send_packet_to_server<P: PlayerboundPacket>(
-        &mut self,
-        packet: &P,
-    ) -> Result<(), Error> {
-        let mut server_connection = self.server.lock().await;
+    /// Measures the round trip if `frame` answers the keep-alive the server
+    /// sent last.
+    fn answer_keep_alive(
+        &self,
+        state: &ConnectionState,
+        id: u32,
+        frame: &[u8],
+        compression_threshold: u32,
+        limits: FrameLimits,
+    ) {
+        let kind = registry::packet_kind(state, PacketDirection::Proxybound, id, self.protocol);
+        if !matches!(kind, Some(PacketKind::PlayKeepAliveResponse | PacketKind::ConfigurationKeepAliveResponse)) {
+            return;
+        }
+
+        let Some(round_trip) = read_keep_alive(frame, compression_threshold, limits)
+            .and_then(|id| self.stats.keep_alive_answered(id))
+        else {
+            return;
+        };
+
+        let event = Arc::new(PlayerLatencyMeasured {
+            connection: Arc::new(Mutex::new(self.clone())),
+            round_trip,
+            stats: self.stats.snapshot(),
+        });
+
+        let event_bus = Arc::clone(&self.event_bus);
+        task::spawn(async move { event_bus.dispatch(&event).await });
+    }
 
-        let mut locked_connection = &mut server_connection.as_mut().unwrap().cnx;
-        packet::send_packet(packet, &mut locked_connection, self.compression_threshold).await
+    /// Reads a packet from the player. Only the original handle can read,
+    /// and not while traffic is being forwarded.
+    pub async fn read_packet(&mut self) -> Result<RawPacket, PacketError> {
+        let limits = self.limits.frame_limits(&self.state, PacketDirection::Proxybound);
+        let compression_threshold = self.compression_threshold();
+
+        let Some(reader) = self.reader.as_mut() else {
+            return Err(Error::new(ErrorKind::Unsupported, "this handle cannot read from the player").into());
+        };
+
+        packet::read_packet(reader, compression_threshold, limits).await
+    }
+
+    /// The limits for packets read from the server the player is connected to.
+    async fn server_limits(&self) -> FrameLimits {
+        let server = self.server.lock().await;
+        let state = server
+            .as_ref()
+            .and_then(|server| server.state.clone())
+            .unwrap_or(ConnectionState::Handshake);
+
+        self.limits.frame_limits(&state, PacketDirection::Playerbound)
+    }
+
+    /// Queues a packet for the player. It is written in order with the
+    /// traffic forwarded from the server.
+    pub fn send_packet<P: PlayerboundPacket>(&self, packet: &P) -> Result<(), PacketError> {
+        self.outbound.send_packet(packet, self.protocol)
     }
+
+    /// Queues a packet for the server the player is connected to.
+    pub async fn send_packet_to_server<P: PlayerboundPacket>(&self, packet: &P) -> Result<(), PacketError> {
+        let server = self.server.lock().await;
+
+        match server.as_ref() {
+            Some(server) => server.outbound.send_packet(packet, self.protocol),
+            None => Err(Error::new(ErrorKind::NotConnected, "not connected to a server").into()),
+        }
+    }
+}
+
+/// How long a player that is being moved may take to finish sending the
+/// packet it started.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What to send the player once a batch of frames has been inspected.
+enum Forward {
+    /// Received bytes to pass on as they are.
+    Frames(Range<usize>),
+    /// A packet to frame again with the player's compression threshold.
+    Packet(Vec<u8>),
+    Compression(u32),
+}
+
+/// Adds a frame to what is being forwarded, merging it into the previous
+/// range when the two are adjacent.
+fn forward(forwarded: &mut Vec<Forward>, range: Range<usize>) {
+    match forwarded.last_mut() {
+        Some(Forward::Frames(last)) if last.end == range.start => last.end = range.end,
+        _ => forwarded.push(Forward::Frames(range)),
+    }
+}
+
+/// Puts a decoded packet back together so it can be framed for the other
+/// side of the proxy.
+fn reencode(packet: &RawPacket) -> Vec<u8> {
+    let mut buffer = ByteBufMut::with_capacity(packet.data.len() + 5);
+    buffer.write_varint(packet.id);
+    buffer.write_bytes(&packet.data);
+    buffer.into_inner()
+}
+
+/// The ID carried by a keep-alive or its response.
+fn read_keep_alive(frame: &[u8], compression_threshold: u32, limits: FrameLimits) -> Option<i64> {
+    let packet = packet::read_packet_from_bytes(frame, compression_threshold, limits).ok()?;
+    ByteBuf::new(&packet.data).read_i64().ok()
 }
 
 impl PlayerProxyConnection {
     pub async fn close(&mut self) -> Result<(), Error> {
-        self.cnx.shutdown().await?;
+        self.outbound.close();
         Ok(())
     }
 }
@@ -0,0 +1,220 @@
+use std::{
+    collections::VecDeque,
+    io::{Error, ErrorKind, IoSlice},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use bytes::{Buf, Bytes};
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    task,
+};
+
+use crate::packet::{self, Packet, PacketError};
+
+use super::{OutboundLimits, OverflowAction};
+
+/// Most messages coalesced into a single vectored write.
+const MAX_BATCH: usize = 64;
+
+pub(crate) enum OutboundMessage {
+    /// An encoded packet, framed with the compression threshold in effect
+    /// when it is written.
+    Packet(Vec<u8>),
+    /// Bytes forwarded as they were received.
+    Raw(Bytes),
+    /// Packets queued after this message use the new threshold.
+    Compression(u32),
+    /// Shuts the socket down once everything queued before is written.
+    Close,
+}
+
+impl OutboundMessage {
+    /// The bytes this message accounts for while it is queued.
+    fn size(&self) -> usize {
+        match self {
+            OutboundMessage::Packet(data) => data.len(),
+            OutboundMessage::Raw(bytes) => bytes.len(),
+            OutboundMessage::Compression(_) | OutboundMessage::Close => 0,
+        }
+    }
+}
+
+/// The queue of one socket's write half, drained by a dedicated task. It can
+/// be cloned and written to from anywhere without waiting on the socket.
+#[derive(Clone)]
+pub(crate) struct Outbound {
+    sender: UnboundedSender<OutboundMessage>,
+    /// Bytes queued but not written to the socket yet.
+    queued: Arc<AtomicUsize>,
+    /// Notified whenever the writer drained part of the queue or stopped.
+    drained: Arc<Notify>,
+}
+
+impl Outbound {
+    pub(crate) fn spawn(writer: OwnedWriteHalf) -> Outbound {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let drained = Arc::new(Notify::new());
+
+        task::spawn(write_queued(writer, receiver, Arc::clone(&queued), Arc::clone(&drained)));
+
+        Outbound { sender, queued, drained }
+    }
+
+    pub(crate) fn send(&self, message: OutboundMessage) -> Result<(), PacketError> {
+        let size = message.size();
+        self.queued.fetch_add(size, Ordering::Relaxed);
+
+        self.sender.send(message).map_err(|_| {
+            self.queued.fetch_sub(size, Ordering::Relaxed);
+            Error::new(ErrorKind::NotConnected, "connection closed").into()
+        })
+    }
+
+    pub(crate) fn send_packet<P: Packet>(&self, packet: &P, protocol: u32) -> Result<(), PacketError> {
+        let (_, data) = packet::encode_packet(packet, protocol)?;
+        self.send(OutboundMessage::Packet(data))
+    }
+
+    pub(crate) fn close(&self) {
+        let _ = self.sender.send(OutboundMessage::Close);
+    }
+
+    /// The bytes waiting to be written to the socket.
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Waits until the queue is below the high-water mark. Once over it,
+    /// either fails or waits for the queue to drain to half the mark, so
+    /// whoever feeds the queue stops reading in the meantime.
+    pub(crate) async fn writable(&self, limits: &OutboundLimits) -> Result<(), Error> {
+        let mut mark = limits.high_water_mark;
+
+        loop {
+            // Created before checking so a drain in between is not missed
+            let drained = self.drained.notified();
+
+            if self.sender.is_closed() {
+                return Err(Error::new(ErrorKind::NotConnected, "connection closed"));
+            }
+
+            let queued = self.queued();
+            if queued <= mark {
+                return Ok(());
+            }
+
+            match limits.on_overflow {
+                OverflowAction::Disconnect => {
+                    return Err(Error::other(format!(
+                        "{} bytes queued exceed the high-water mark of {}",
+                        queued, limits.high_water_mark
+                    )))
+                }
+                OverflowAction::Pause => {
+                    mark = limits.high_water_mark / 2;
+                    drained.await;
+                }
+            }
+        }
+    }
+}
+
+async fn write_queued(
+    mut writer: OwnedWriteHalf,
+    mut receiver: UnboundedReceiver<OutboundMessage>,
+    queued: Arc<AtomicUsize>,
+    drained: Arc<Notify>,
+) {
+    let mut compression_threshold = 0;
+    let mut frames = VecDeque::new();
+    let mut closing = false;
+
+    while let Some(message) = receiver.recv().await {
+        // Everything already queued goes out in one write, up to a limit.
+        let mut next = Some(message);
+        let mut batched = 0;
+        let mut size = 0;
+
+        while let Some(message) = next.take() {
+            size += message.size();
+
+            match message {
+                OutboundMessage::Packet(data) => {
+                    match packet::frame_packet(&data, compression_threshold) {
+                        Ok(frame) => frames.push_back(Bytes::from(frame)),
+                        Err(_) => {
+                            closing = true;
+                            break;
+                        }
+                    }
+                }
+                OutboundMessage::Raw(bytes) => frames.push_back(bytes),
+                OutboundMessage::Compression(threshold) => compression_threshold = threshold,
+                OutboundMessage::Close => {
+                    closing = true;
+                    break;
+                }
+            }
+
+            batched += 1;
+            if batched < MAX_BATCH {
+                next = receiver.try_recv().ok();
+            }
+        }
+
+        let result = write_all_vectored(&mut writer, &mut frames).await;
+
+        queued.fetch_sub(size, Ordering::Relaxed);
+        drained.notify_waiters();
+
+        if closing || result.is_err() {
+            break;
+        }
+    }
+
+    // Anyone waiting for the queue to drain sees it closed from now on.
+    drop(receiver);
+    drained.notify_waiters();
+
+    let _ = writer.shutdown().await;
+}
+
+/// Writes every frame, passing as many as possible to each system call.
+async fn write_all_vectored(writer: &mut OwnedWriteHalf, frames: &mut VecDeque<Bytes>) -> Result<(), Error> {
+    while !frames.is_empty() {
+        let mut written = {
+            let slices: Vec<IoSlice> = frames.iter().map(|frame| IoSlice::new(frame)).collect();
+            writer.write_vectored(&slices).await?
+        };
+
+        if written == 0 {
+            return Err(ErrorKind::WriteZero.into());
+        }
+
+        while written > 0 {
+            let Some(frame) = frames.front_mut() else {
+                break;
+            };
+
+            if written >= frame.len() {
+                written -= frame.len();
+                frames.pop_front();
+            } else {
+                frame.advance(written);
+                written = 0;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::packet::PacketDirection;
+
+use super::ConnectionState;
+
+/// Counters for the traffic forwarded between a player and its server,
+/// shared by every handle to the connection.
+#[derive(Default)]
+pub struct ConnectionStats {
+    playerbound: DirectionCounters,
+    proxybound: DirectionCounters,
+    keep_alive: Mutex<KeepAlive>,
+}
+
+#[derive(Default)]
+struct DirectionCounters {
+    bytes: AtomicU64,
+    packets: AtomicU64,
+    compressed_bytes: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    by_id: Mutex<HashMap<(ConnectionState, u32), PacketCount>>,
+}
+
+#[derive(Default)]
+struct KeepAlive {
+    /// The last keep-alive the server sent and when it was forwarded.
+    pending: Option<(i64, Instant)>,
+    round_trip: Option<Duration>,
+}
+
+/// How often a packet ID was seen, and how many bytes it took on the wire.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PacketCount {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// The counters of one direction at the time of a snapshot.
+#[derive(Clone, Default, Debug)]
+pub struct DirectionStats {
+    /// Bytes on the wire, including length prefixes.
+    pub bytes: u64,
+    pub packets: u64,
+    /// Wire size of the packets that were compressed.
+    pub compressed_bytes: u64,
+    /// Size of the same packets once inflated.
+    pub uncompressed_bytes: u64,
+    pub by_id: HashMap<(ConnectionState, u32), PacketCount>,
+}
+
+impl DirectionStats {
+    /// How many times smaller compressed packets are on the wire, if any
+    /// packet was compressed.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.compressed_bytes == 0 {
+            return None;
+        }
+
+        Some(self.uncompressed_bytes as f64 / self.compressed_bytes as f64)
+    }
+
+    /// The packet IDs that took the most bytes, largest first.
+    pub fn top_packets(&self, count: usize) -> Vec<((ConnectionState, u32), PacketCount)> {
+        let mut packets: Vec<_> = self.by_id.iter().map(|(key, count)| (key.clone(), *count)).collect();
+        packets.sort_by_key(|(_, count)| std::cmp::Reverse(count.bytes));
+        packets.truncate(count);
+        packets
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct StatsSnapshot {
+    /// Traffic from the server to the player.
+    pub playerbound: DirectionStats,
+    /// Traffic from the player to the server.
+    pub proxybound: DirectionStats,
+    /// The last measured keep-alive round trip between the proxy and the
+    /// player.
+    pub round_trip: Option<Duration>,
+}
+
+impl ConnectionStats {
+    /// Counts a packet forwarded in `direction`. `uncompressed_length` is the
+    /// declared size of a compressed packet.
+    pub(crate) fn record(
+        &self,
+        direction: PacketDirection,
+        state: &ConnectionState,
+        id: u32,
+        frame_length: usize,
+        uncompressed_length: Option<usize>,
+    ) {
+        let counters = self.counters(direction);
+        let frame_length = frame_length as u64;
+
+        counters.bytes.fetch_add(frame_length, Ordering::Relaxed);
+        counters.packets.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(uncompressed_length) = uncompressed_length {
+            counters.compressed_bytes.fetch_add(frame_length, Ordering::Relaxed);
+            counters
+                .uncompressed_bytes
+                .fetch_add(uncompressed_length as u64, Ordering::Relaxed);
+        }
+
+        let mut by_id = counters.by_id.lock().unwrap();
+        let count = by_id.entry((state.clone(), id)).or_default();
+        count.packets += 1;
+        count.bytes += frame_length;
+    }
+
+    /// Remembers a keep-alive the server sent to the player.
+    pub(crate) fn keep_alive_sent(&self, id: i64) {
+        self.keep_alive.lock().unwrap().pending = Some((id, Instant::now()));
+    }
+
+    /// Completes the round trip if the player answered the pending
+    /// keep-alive, returning its duration.
+    pub(crate) fn keep_alive_answered(&self, id: i64) -> Option<Duration> {
+        let mut keep_alive = self.keep_alive.lock().unwrap();
+
+        match keep_alive.pending {
+            Some((pending, sent)) if pending == id => {
+                let round_trip = sent.elapsed();
+                keep_alive.pending = None;
+                keep_alive.round_trip = Some(round_trip);
+                Some(round_trip)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the player still has to answer a keep-alive.
+    pub(crate) fn awaiting_keep_alive(&self) -> bool {
+        self.keep_alive.lock().unwrap().pending.is_some()
+    }
+
+    /// The last measured keep-alive round trip, what `/ping` would show.
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.keep_alive.lock().unwrap().round_trip
+    }
+
+    pub fn bytes(&self, direction: PacketDirection) -> u64 {
+        self.counters(direction).bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn packets(&self, direction: PacketDirection) -> u64 {
+        self.counters(direction).packets.load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            playerbound: self.playerbound.snapshot(),
+            proxybound: self.proxybound.snapshot(),
+            round_trip: self.round_trip(),
+        }
+    }
+
+    fn counters(&self, direction: PacketDirection) -> &DirectionCounters {
+        match direction {
+            PacketDirection::Playerbound => &self.playerbound,
+            PacketDirection::Proxybound => &self.proxybound,
+        }
+    }
+}
+
+impl DirectionCounters {
+    fn snapshot(&self) -> DirectionStats {
+        DirectionStats {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            packets: self.packets.load(Ordering::Relaxed),
+            compressed_bytes: self.compressed_bytes.load(Ordering::Relaxed),
+            uncompressed_bytes: self.uncompressed_bytes.load(Ordering::Relaxed),
+            by_id: self.by_id.lock().unwrap().clone(),
+        }
+    }
+}
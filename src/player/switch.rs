@@ -0,0 +1,215 @@
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
+
+use azalea_chat::FormattedText;
+use tokio::{
+    sync::{oneshot, Mutex},
+    time,
+};
+use tracing::{info, Span};
+
+use crate::{
+    event::{EventResult, PlayerJoinedServer},
+    packet::{
+        self,
+        configuration::ConfigurationDisconnectPacket,
+        login::{LoginAcknowledgedPacket, LoginDisconnectPacket, LoginSuccessPacket, SetCompressionPacket},
+        play::{ConfigurationAcknowledgedPacket, PlayDisconnectPacket, StartConfigurationPacket, SystemChatMessagePacket},
+        PacketDirection, PacketError,
+    },
+    server::ProxiedServer,
+};
+
+use super::{outbound::OutboundMessage, ConnectionState, PlayerConnection, PlayerProxyConnection};
+
+/// How long a server may take to log the player in, and the player to
+/// acknowledge going back to the Configuration state.
+const SWITCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something the task forwarding a player's traffic is asked to do.
+pub(crate) enum Request {
+    /// Move the player to another server, reporting the outcome to `done`.
+    Switch {
+        server: Arc<ProxiedServer>,
+        done: oneshot::Sender<Result<(), Error>>,
+    },
+}
+
+impl PlayerConnection {
+    /// Moves the player to `server`, waiting until it is logged in there.
+    /// Only players that are playing on a server can be moved.
+    pub async fn send_to(&self, server: &Arc<ProxiedServer>) -> Result<(), Error> {
+        let (done, result) = oneshot::channel();
+
+        self.requests
+            .send(Request::Switch {
+                server: Arc::clone(server),
+                done,
+            })
+            .map_err(|_| Error::new(ErrorKind::NotConnected, "the player disconnected"))?;
+
+        result
+            .await
+            .map_err(|_| Error::new(ErrorKind::NotConnected, "the player disconnected"))?
+    }
+
+    /// The state of the player's connection, as followed through the server
+    /// it is connected to.
+    pub async fn current_state(&self) -> ConnectionState {
+        let server = self.server.lock().await;
+        server
+            .as_ref()
+            .and_then(|server| server.state.clone())
+            .unwrap_or_else(|| match self.in_limbo() {
+                true => ConnectionState::Play,
+                false => self.state.clone(),
+            })
+    }
+
+    /// Disconnects the player with `reason`, using the disconnect packet of
+    /// the state it is in.
+    pub async fn kick(&mut self, reason: FormattedText) -> Result<(), Error> {
+        let sent = match self.current_state().await {
+            ConnectionState::Login => self.send_packet(&LoginDisconnectPacket { reason }),
+            ConnectionState::Configuration => self.send_packet(&ConfigurationDisconnectPacket { reason }),
+            ConnectionState::Play => self.send_packet(&PlayDisconnectPacket { reason }),
+            ConnectionState::Handshake | ConnectionState::Status => Ok(()),
+        };
+
+        self.close().await?;
+        sent.map_err(Error::from)
+    }
+
+    /// Shows a message in the player's chat.
+    pub async fn send_message(&self, text: FormattedText) -> Result<(), Error> {
+        if self.current_state().await != ConnectionState::Play {
+            return Err(Error::new(ErrorKind::Unsupported, "the player is not playing yet"));
+        }
+
+        self.send_packet(&SystemChatMessagePacket { text, overlay: false })?;
+        Ok(())
+    }
+
+    /// Shows a message above the player's hotbar.
+    pub async fn send_action_bar(&self, text: FormattedText) -> Result<(), Error> {
+        if self.current_state().await != ConnectionState::Play {
+            return Err(Error::new(ErrorKind::Unsupported, "the player is not playing yet"));
+        }
+
+        self.send_packet(&SystemChatMessagePacket { text, overlay: true })?;
+        Ok(())
+    }
+
+    /// Connects to `server` and logs in there while the player keeps playing
+    /// on the current one.
+    pub(super) async fn prepare_switch(&self, server: &Arc<ProxiedServer>) -> Result<PlayerProxyConnection, Error> {
+        if self.current_state().await != ConnectionState::Play {
+            return Err(Error::new(ErrorKind::Unsupported, "the player is not playing yet"));
+        }
+
+        let event = Arc::new(PlayerJoinedServer {
+            connection: Arc::new(Mutex::new(self.clone())),
+            server: Arc::clone(server),
+        });
+
+        if self.event_bus.dispatch(&event).await == Some(EventResult::Stop) {
+            return Err(Error::new(ErrorKind::ConnectionAborted, "Cancelled"));
+        }
+
+        let mut connection = self.open_connection(server).await?;
+
+        match time::timeout(SWITCH_TIMEOUT, self.log_in(&mut connection)).await {
+            Ok(Ok(())) => Ok(connection),
+            Ok(Err(e)) => {
+                let _ = connection.close().await;
+                Err(e)
+            }
+            Err(_) => {
+                let _ = connection.close().await;
+                Err(Error::new(ErrorKind::TimedOut, "the server did not log the player in"))
+            }
+        }
+    }
+
+    /// Reads the server's side of the login, up to the Configuration state.
+    async fn log_in(&self, connection: &mut PlayerProxyConnection) -> Result<(), Error> {
+        let limits = self.limits.frame_limits(&ConnectionState::Login, PacketDirection::Playerbound);
+        let Some(reader) = connection.reader.as_mut() else {
+            return Err(Error::new(ErrorKind::Unsupported, "the connection is already forwarded"));
+        };
+
+        loop {
+            let packet = packet::read_packet(reader, connection.compression_threshold, limits).await?;
+
+            if packet.is::<SetCompressionPacket>(self.protocol) {
+                let threshold = packet.decode_as::<SetCompressionPacket>()?.threshold;
+                connection.compression_threshold = threshold;
+                connection.outbound.send(OutboundMessage::Compression(threshold))?;
+            } else if packet.is::<LoginDisconnectPacket>(self.protocol) {
+                let reason = packet.decode_as::<LoginDisconnectPacket>()?.reason;
+                return Err(Error::new(ErrorKind::ConnectionRefused, reason.to_string()));
+            } else if packet.is::<LoginSuccessPacket>(self.protocol) {
+                connection.outbound.send_packet(&LoginAcknowledgedPacket {}, self.protocol)?;
+                connection.state = Some(ConnectionState::Configuration);
+                return Ok(());
+            } else {
+                return Err(PacketError::IdMismatch { expected: None, actual: packet.id }.into());
+            }
+        }
+    }
+
+    /// Sends the player back to the Configuration state and replaces its
+    /// server connection with `connection`, which continues from there.
+    pub(super) async fn complete_switch(&mut self, connection: PlayerProxyConnection) -> Result<(), Error> {
+        self.send_packet(&StartConfigurationPacket {})?;
+        self.state = ConnectionState::Play;
+
+        // Whatever the player sends until it acknowledges was meant for the
+        // server it leaves.
+        let acknowledged = time::timeout(SWITCH_TIMEOUT, async {
+            loop {
+                let packet = self.read_packet().await?;
+                if packet.is::<ConfigurationAcknowledgedPacket>(self.protocol) {
+                    return Ok::<_, PacketError>(());
+                }
+            }
+        })
+        .await;
+
+        let acknowledged = match acknowledged {
+            Ok(acknowledged) => acknowledged.map_err(Error::from),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "the player did not acknowledge the switch")),
+        };
+
+        self.finish_switch(connection, acknowledged).await
+    }
+
+    /// Replaces the player's server connection with `connection` once the
+    /// player went back to the Configuration state, or failed to.
+    pub(super) async fn finish_switch(
+        &mut self,
+        connection: PlayerProxyConnection,
+        acknowledged: Result<(), Error>,
+    ) -> Result<(), Error> {
+        let name = connection.server.name.clone();
+
+        let previous = {
+            let mut server = self.server.lock().await;
+            server.replace(connection)
+        };
+
+        if let Some(mut previous) = previous {
+            let _ = previous.close().await;
+        }
+
+        acknowledged?;
+
+        self.state = ConnectionState::Configuration;
+        Span::current().record("server", name.as_str());
+        info!("Switched to {}", name);
+        Ok(())
+    }
+}
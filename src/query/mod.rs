@@ -0,0 +1,355 @@
+//! Answers the GameSpy4 query protocol over UDP, as Minecraft servers do with
+//! `enable-query`, describing the whole network.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tracing::{error, trace};
+use uuid::Uuid;
+
+use crate::{packet::registry, player::PlayerConnection, SharedProxyInstance};
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const TYPE_HANDSHAKE: u8 = 9;
+const TYPE_STAT: u8 = 0;
+
+/// How long a challenge token stays valid.
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Where to answer queries, e.g. `[query]` with `port = 25566`.
+#[derive(Deserialize, Clone)]
+pub struct QueryConfiguration {
+    /// Defaults to the address of the proxy itself.
+    pub address: Option<String>,
+    pub port: u16,
+}
+
+/// What a query reports, gathered from the proxy once per request.
+struct QueryStatus {
+    motd: String,
+    version: String,
+    plugins: String,
+    online: usize,
+    max: u32,
+    port: u16,
+    address: String,
+    players: Vec<String>,
+}
+
+/// Answers queries on `socket` until the proxy stops.
+pub async fn serve(socket: UdpSocket, instance: SharedProxyInstance) {
+    let mut challenges: HashMap<IpAddr, (i32, Instant)> = HashMap::new();
+    let mut buffer = [0u8; 1460];
+
+    loop {
+        let (length, addr) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                error!("Failed to receive query: {:?}", e);
+                continue;
+            }
+        };
+
+        challenges.retain(|_, (_, issued)| issued.elapsed() < CHALLENGE_LIFETIME);
+
+        if let Some(response) = respond(&buffer[..length], addr, &mut challenges, &instance).await {
+            if let Err(e) = socket.send_to(&response, addr).await {
+                trace!("Failed to answer query from {}: {:?}", addr, e);
+            }
+        }
+    }
+}
+
+async fn respond(
+    request: &[u8],
+    addr: SocketAddr,
+    challenges: &mut HashMap<IpAddr, (i32, Instant)>,
+    instance: &SharedProxyInstance,
+) -> Option<Vec<u8>> {
+    if request.len() < 7 || request[..2] != MAGIC {
+        return None;
+    }
+
+    let kind = request[2];
+    let session = &request[3..7];
+
+    match kind {
+        TYPE_HANDSHAKE => {
+            let token = Uuid::new_v4().as_u128() as i32 & 0x7FFF_FFFF;
+            challenges.insert(addr.ip(), (token, Instant::now()));
+
+            let mut response = vec![TYPE_HANDSHAKE];
+            response.extend_from_slice(session);
+            push_string(&mut response, &token.to_string());
+            Some(response)
+        }
+        TYPE_STAT if request.len() >= 11 => {
+            let token = i32::from_be_bytes(request[7..11].try_into().unwrap());
+            if challenges.get(&addr.ip()).map(|(expected, _)| *expected) != Some(token) {
+                return None;
+            }
+
+            let status = gather(instance).await;
+            let mut response = vec![TYPE_STAT];
+            response.extend_from_slice(session);
+
+            // A full stat request pads the token with four more bytes
+            if request.len() >= 15 {
+                full_stat(&mut response, &status);
+            } else {
+                basic_stat(&mut response, &status);
+            }
+
+            Some(response)
+        }
+        _ => None,
+    }
+}
+
+async fn gather(instance: &SharedProxyInstance) -> QueryStatus {
+    let (status, version, plugins, port, address, players) = {
+        let instance = instance.read().await;
+        let status = instance.status(registry::LATEST_PROTOCOL, true);
+        let players: Vec<PlayerConnection> = instance.players.values().cloned().collect();
+
+        (
+            status,
+            instance.supported_versions(),
+            instance.plugins.join("; "),
+            instance.config.proxy_port as u16,
+            instance.config.address.clone().unwrap_or_else(|| "0.0.0.0".to_owned()),
+            players,
+        )
+    };
+
+    let mut names = Vec::with_capacity(players.len());
+    for player in &players {
+        if let Some(info) = player.player_info.lock().await.as_ref() {
+            names.push(info.username.clone());
+        }
+    }
+
+    QueryStatus {
+        motd: status.description.to_string(),
+        version,
+        plugins: match plugins.as_str() {
+            "" => format!("rustyproxy {}", env!("CARGO_PKG_VERSION")),
+            plugins => format!("rustyproxy {}: {}", env!("CARGO_PKG_VERSION"), plugins),
+        },
+        online: status.players.online as usize,
+        max: status.players.max,
+        port,
+        address,
+        players: names,
+    }
+}
+
+fn basic_stat(response: &mut Vec<u8>, status: &QueryStatus) {
+    push_string(response, &status.motd);
+    push_string(response, "SMP");
+    push_string(response, "world");
+    push_string(response, &status.online.to_string());
+    push_string(response, &status.max.to_string());
+    response.extend_from_slice(&status.port.to_le_bytes());
+    push_string(response, &status.address);
+}
+
+fn full_stat(response: &mut Vec<u8>, status: &QueryStatus) {
+    response.extend_from_slice(b"splitnum\0\x80\0");
+
+    let values = [
+        ("hostname", status.motd.clone()),
+        ("gametype", "SMP".to_owned()),
+        ("game_id", "MINECRAFT".to_owned()),
+        ("version", status.version.clone()),
+        ("plugins", status.plugins.clone()),
+        ("map", "world".to_owned()),
+        ("numplayers", status.online.to_string()),
+        ("maxplayers", status.max.to_string()),
+        ("hostport", status.port.to_string()),
+        ("hostip", status.address.clone()),
+    ];
+
+    for (key, value) in values {
+        push_string(response, key);
+        push_string(response, &value);
+    }
+
+    response.push(0);
+    response.extend_from_slice(b"\x01player_\0\0");
+
+    for player in &status.players {
+        push_string(response, player);
+    }
+
+    response.push(0);
+}
+
+/// Writes `value` null-terminated, as every string in the protocol is.
+fn push_string(response: &mut Vec<u8>, value: &str) {
+    response.extend_from_slice(value.replace('\0', "").as_bytes());
+    response.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProxyConfiguration;
+
+    const SESSION: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+    fn instance() -> SharedProxyInstance {
+        let config: ProxyConfiguration = toml::from_str(
+            r#"
+                proxy_port = 25565
+                address = "127.0.0.1"
+                motd = "A\u0000server"
+                max_players = 20
+            "#,
+        )
+        .unwrap();
+        crate::new_instance(config).unwrap()
+    }
+
+    fn request(kind: u8, token: Option<i32>, full: bool) -> Vec<u8> {
+        let mut request = MAGIC.to_vec();
+        request.push(kind);
+        request.extend_from_slice(&SESSION);
+        if let Some(token) = token {
+            request.extend_from_slice(&token.to_be_bytes());
+        }
+        if full {
+            request.extend_from_slice(&[0; 4]);
+        }
+        request
+    }
+
+    /// The null-terminated strings of `response` after its type and session.
+    fn strings(response: &[u8]) -> Vec<String> {
+        assert_eq!(response[1..5], SESSION);
+        let mut strings: Vec<String> = response[5..]
+            .split(|byte| *byte == 0)
+            .map(|string| String::from_utf8_lossy(string).into_owned())
+            .collect();
+        // Everything ends in a null byte, which leaves an empty string.
+        assert_eq!(strings.pop().as_deref(), Some(""));
+        strings
+    }
+
+    /// Shakes hands from `addr` and returns the challenge token.
+    async fn handshake(
+        addr: SocketAddr,
+        challenges: &mut HashMap<IpAddr, (i32, Instant)>,
+        instance: &SharedProxyInstance,
+    ) -> i32 {
+        let response = respond(&request(TYPE_HANDSHAKE, None, false), addr, challenges, instance)
+            .await
+            .unwrap();
+        assert_eq!(response[0], TYPE_HANDSHAKE);
+
+        let strings = strings(&response);
+        assert_eq!(strings.len(), 1);
+        strings[0].parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn garbage_is_ignored() {
+        let instance = instance();
+        let addr = "127.0.0.1:50000".parse().unwrap();
+        let mut challenges = HashMap::new();
+
+        for request in [
+            Vec::new(),
+            vec![0xFE, 0xFD, TYPE_HANDSHAKE, 0, 0, 0],
+            vec![0xFE, 0xFE, TYPE_HANDSHAKE, 0, 0, 0, 1],
+            request(7, None, false),
+            // A stat request without its token
+            request(TYPE_STAT, None, false),
+        ] {
+            assert!(respond(&request, addr, &mut challenges, &instance).await.is_none());
+        }
+        assert!(challenges.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stats_need_the_challenge() {
+        let instance = instance();
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let mut challenges = HashMap::new();
+
+        let token = handshake(addr, &mut challenges, &instance).await;
+        assert!(token >= 0);
+        assert_eq!(challenges[&addr.ip()].0, token);
+
+        let wrong = request(TYPE_STAT, Some(token.wrapping_add(1)), false);
+        assert!(respond(&wrong, addr, &mut challenges, &instance).await.is_none());
+
+        // The token belongs to the address that asked for it.
+        let other = "127.0.0.2:50000".parse().unwrap();
+        let stolen = request(TYPE_STAT, Some(token), false);
+        assert!(respond(&stolen, other, &mut challenges, &instance).await.is_none());
+
+        assert!(respond(&stolen, addr, &mut challenges, &instance).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn basic_stats_describe_the_network() {
+        let instance = instance();
+        let addr = "127.0.0.1:50000".parse().unwrap();
+        let mut challenges = HashMap::new();
+
+        let token = handshake(addr, &mut challenges, &instance).await;
+        let response = respond(&request(TYPE_STAT, Some(token), false), addr, &mut challenges, &instance)
+            .await
+            .unwrap();
+        assert_eq!(response[0], TYPE_STAT);
+
+        // The port is a little-endian short between the strings.
+        let port = 25565u16.to_le_bytes();
+        let split = response.windows(2).position(|window| window == port).unwrap();
+        let mut fields = strings(&response[..split]);
+        fields.extend(strings(&[&response[..5], &response[split + 2..]].concat()));
+
+        assert_eq!(fields, ["Aserver", "SMP", "world", "0", "20", "127.0.0.1"]);
+    }
+
+    #[tokio::test]
+    async fn full_stats_list_keys_and_players() {
+        let instance = instance();
+        let addr = "127.0.0.1:50000".parse().unwrap();
+        let mut challenges = HashMap::new();
+
+        let token = handshake(addr, &mut challenges, &instance).await;
+        let response = respond(&request(TYPE_STAT, Some(token), true), addr, &mut challenges, &instance)
+            .await
+            .unwrap();
+
+        let padding = b"splitnum\0\x80\0";
+        assert_eq!(response[5..5 + padding.len()], *padding);
+
+        let rest = &response[5 + padding.len()..];
+        let marker = b"\0\x01player_\0\0";
+        let players_start = rest.windows(marker.len()).position(|window| window == marker).unwrap();
+        let pairs = strings(&[&response[..5], &rest[..players_start]].concat());
+        let values: HashMap<_, _> = pairs.chunks(2).map(|pair| (pair[0].as_str(), pair[1].as_str())).collect();
+
+        assert_eq!(values["hostname"], "Aserver");
+        assert_eq!(values["gametype"], "SMP");
+        assert_eq!(values["game_id"], "MINECRAFT");
+        assert_eq!(values["version"], instance.read().await.supported_versions());
+        assert!(values["plugins"].starts_with("rustyproxy "));
+        assert_eq!(values["numplayers"], "0");
+        assert_eq!(values["maxplayers"], "20");
+        assert_eq!(values["hostport"], "25565");
+        assert_eq!(values["hostip"], "127.0.0.1");
+
+        // The keys end with an empty one, and there are no players, so the
+        // list ends right away.
+        assert_eq!(rest[players_start..], *b"\0\x01player_\0\0\0");
+    }
+}
@@ -0,0 +1,315 @@
+//! A remote console speaking the Source RCON protocol, as Minecraft servers
+//! do. Commands run as the console, with every permission.
+
+use std::{io::Error, net::SocketAddr, sync::Arc};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    task,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    command::{strip_formatting, CommandSender},
+    SharedProxyInstance,
+};
+
+const TYPE_RESPONSE: i32 = 0;
+const TYPE_COMMAND: i32 = 2;
+const TYPE_AUTH_RESPONSE: i32 = 2;
+const TYPE_AUTH: i32 = 3;
+
+/// Longest request accepted, as in the protocol's reference implementation.
+const MAX_REQUEST_LENGTH: usize = 1460;
+/// Longest body sent in one response packet; longer output is split.
+const MAX_RESPONSE_BODY: usize = 4096;
+
+/// Where to listen for remote consoles, e.g. `[rcon]` with `port = 25575`
+/// and a `password`.
+#[derive(Deserialize, Clone)]
+pub struct RconConfiguration {
+    /// Defaults to `127.0.0.1`.
+    pub address: Option<String>,
+    pub port: u16,
+    pub password: String,
+}
+
+struct RconPacket {
+    id: i32,
+    kind: i32,
+    body: String,
+}
+
+/// Accepts remote consoles on `listener` until the proxy stops.
+pub async fn serve(listener: TcpListener, instance: SharedProxyInstance, password: String) {
+    let password = Arc::new(password);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let instance = Arc::clone(&instance);
+                let password = Arc::clone(&password);
+
+                task::spawn(async move {
+                    if let Err(e) = handle(stream, addr, &instance, &password).await {
+                        warn!("Remote console {} failed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => error!("Failed to accept remote console: {:?}", e),
+        }
+    }
+}
+
+async fn handle(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    addr: SocketAddr,
+    instance: &SharedProxyInstance,
+    password: &str,
+) -> Result<(), Error> {
+    let mut authenticated = false;
+
+    while let Some(packet) = read_packet(&mut stream).await? {
+        match packet.kind {
+            TYPE_AUTH => {
+                authenticated = !password.is_empty() && packet.body == password;
+
+                if authenticated {
+                    info!("Remote console {} logged in", addr);
+                } else {
+                    warn!("Remote console {} used a wrong password", addr);
+                }
+
+                let id = if authenticated { packet.id } else { -1 };
+                write_packet(&mut stream, id, TYPE_AUTH_RESPONSE, "").await?;
+            }
+            TYPE_COMMAND if authenticated => {
+                info!("Remote console {} ran: {}", addr, packet.body);
+
+                let commands = Arc::clone(&instance.read().await.commands);
+                let output = commands
+                    .execute_to_text(instance, CommandSender::Console, &packet.body)
+                    .await;
+                let output = strip_formatting(&output);
+
+                let mut chunks = chunks(&output, MAX_RESPONSE_BODY).peekable();
+                if chunks.peek().is_none() {
+                    write_packet(&mut stream, packet.id, TYPE_RESPONSE, "").await?;
+                }
+
+                for chunk in chunks {
+                    write_packet(&mut stream, packet.id, TYPE_RESPONSE, chunk).await?;
+                }
+            }
+            _ => write_packet(&mut stream, -1, TYPE_AUTH_RESPONSE, "").await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits `text` into pieces of at most `size` bytes on character
+/// boundaries.
+fn chunks(text: &str, size: usize) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut end = rest.len().min(size);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some(chunk)
+    })
+}
+
+/// Reads a packet, or `None` once the client disconnected.
+async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<RconPacket>, Error> {
+    let length = match stream.read_i32_le().await {
+        Ok(length) => length,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    // An ID, a type and two terminating zeros at the very least
+    if !(10..=MAX_REQUEST_LENGTH as i32).contains(&length) {
+        return Err(Error::other(format!("invalid packet length {}", length)));
+    }
+
+    let mut buffer = vec![0u8; length as usize];
+    stream.read_exact(&mut buffer).await?;
+
+    let id = i32::from_le_bytes(buffer[0..4].try_into().unwrap());
+    let kind = i32::from_le_bytes(buffer[4..8].try_into().unwrap());
+    let body = &buffer[8..buffer.len() - 2];
+    let body = body.split(|byte| *byte == 0).next().unwrap_or_default();
+
+    Ok(Some(RconPacket {
+        id,
+        kind,
+        body: String::from_utf8_lossy(body).into_owned(),
+    }))
+}
+
+async fn write_packet(stream: &mut (impl AsyncWrite + Unpin), id: i32, kind: i32, body: &str) -> Result<(), Error> {
+    let mut packet = Vec::with_capacity(body.len() + 14);
+    packet.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&kind.to_le_bytes());
+    packet.extend_from_slice(body.as_bytes());
+    packet.extend_from_slice(&[0, 0]);
+
+    stream.write_all(&packet).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::{command::Command, ProxyConfiguration};
+
+    const PASSWORD: &str = "hunter2";
+
+    fn encoded(id: i32, kind: i32, body: &str) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&(body.len() as i32 + 10).to_le_bytes());
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.extend_from_slice(&kind.to_le_bytes());
+        packet.extend_from_slice(body.as_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet
+    }
+
+    async fn read(bytes: &[u8]) -> Result<Option<RconPacket>, Error> {
+        read_packet(&mut &bytes[..]).await
+    }
+
+    #[tokio::test]
+    async fn packets_round_trip() {
+        let mut written = Vec::new();
+        write_packet(&mut written, 7, TYPE_COMMAND, "list").await.unwrap();
+        assert_eq!(written, encoded(7, TYPE_COMMAND, "list"));
+
+        let packet = read(&written).await.unwrap().unwrap();
+        assert_eq!((packet.id, packet.kind, packet.body.as_str()), (7, TYPE_COMMAND, "list"));
+
+        // Some clients pad the body with more zeros.
+        let mut padded = encoded(8, TYPE_AUTH, "pass\0\0");
+        padded.extend_from_slice(&encoded(9, TYPE_COMMAND, ""));
+        let mut stream = &padded[..];
+        assert_eq!(read_packet(&mut stream).await.unwrap().unwrap().body, "pass");
+        let packet = read_packet(&mut stream).await.unwrap().unwrap();
+        assert_eq!((packet.id, packet.body.as_str()), (9, ""));
+        assert!(read_packet(&mut stream).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn malformed_packets_are_errors() {
+        assert!(read(&[]).await.unwrap().is_none());
+
+        for length in [i32::MIN, -1, 0, 9, MAX_REQUEST_LENGTH as i32 + 1, i32::MAX] {
+            let mut packet = length.to_le_bytes().to_vec();
+            packet.resize(64, 0);
+            assert!(read(&packet).await.is_err(), "length {}", length);
+        }
+
+        let packet = encoded(1, TYPE_COMMAND, "list");
+        assert!(read(&packet[..packet.len() - 1]).await.is_err());
+        // A client hanging up within the length just left.
+        assert!(read(&packet[..2]).await.unwrap().is_none());
+
+        let longest = "a".repeat(MAX_REQUEST_LENGTH - 10);
+        assert_eq!(read(&encoded(1, TYPE_COMMAND, &longest)).await.unwrap().unwrap().body, longest);
+    }
+
+    #[test]
+    fn output_is_split_on_characters() {
+        assert_eq!(chunks("", 4).count(), 0);
+        assert_eq!(chunks("abcdefgh", 4).collect::<Vec<_>>(), ["abcd", "efgh"]);
+        assert_eq!(chunks("abcdefghi", 4).collect::<Vec<_>>(), ["abcd", "efgh", "i"]);
+        // é takes two bytes and is not cut in half.
+        assert_eq!(chunks("aéé", 4).collect::<Vec<_>>(), ["aé", "é"]);
+    }
+
+    /// Runs a console session with `requests` and returns the packets it
+    /// answered with.
+    async fn session(requests: &[(i32, i32, &str)]) -> Vec<RconPacket> {
+        let config: ProxyConfiguration = toml::from_str("proxy_port = 25565\naddress = \"127.0.0.1\"").unwrap();
+        let instance = crate::new_instance(config).unwrap();
+        instance.read().await.commands.register(Command::new("long", "", |_, _| async {
+            Ok("é".repeat(MAX_RESPONSE_BODY))
+        }));
+
+        let (mut client, server) = duplex(64 * 1024);
+        for (id, kind, body) in requests {
+            client.write_all(&encoded(*id, *kind, body)).await.unwrap();
+        }
+        client.shutdown().await.unwrap();
+
+        handle(server, "127.0.0.1:50000".parse().unwrap(), &instance, PASSWORD).await.unwrap();
+
+        // Responses may be longer than requests, so they are read here.
+        let mut responses = Vec::new();
+        while let Ok(length) = client.read_i32_le().await {
+            let mut packet = vec![0; length as usize];
+            client.read_exact(&mut packet).await.unwrap();
+            responses.push(RconPacket {
+                id: i32::from_le_bytes(packet[0..4].try_into().unwrap()),
+                kind: i32::from_le_bytes(packet[4..8].try_into().unwrap()),
+                body: String::from_utf8(packet[8..packet.len() - 2].to_vec()).unwrap(),
+            });
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn commands_need_the_password() {
+        let responses = session(&[
+            (1, TYPE_COMMAND, "long"),
+            (2, TYPE_AUTH, "wrong"),
+            (3, TYPE_COMMAND, "long"),
+            (4, TYPE_AUTH, PASSWORD),
+        ])
+        .await;
+
+        let answers: Vec<_> = responses.iter().map(|packet| (packet.id, packet.kind)).collect();
+        assert_eq!(
+            answers,
+            [
+                (-1, TYPE_AUTH_RESPONSE),
+                (-1, TYPE_AUTH_RESPONSE),
+                (-1, TYPE_AUTH_RESPONSE),
+                (4, TYPE_AUTH_RESPONSE),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn long_output_spans_several_packets() {
+        let responses = session(&[
+            (1, TYPE_AUTH, PASSWORD),
+            (2, TYPE_COMMAND, "long"),
+            (3, TYPE_COMMAND, "nothing"),
+        ])
+        .await;
+
+        let output = &responses[1..responses.len() - 1];
+        assert_eq!(output.len(), 2);
+        assert!(output.iter().all(|packet| packet.id == 2 && packet.kind == TYPE_RESPONSE));
+        assert!(output.iter().all(|packet| packet.body.len() <= MAX_RESPONSE_BODY));
+        let joined: String = output.iter().map(|packet| packet.body.as_str()).collect();
+        assert_eq!(joined, "é".repeat(MAX_RESPONSE_BODY));
+
+        let unknown = responses.last().unwrap();
+        assert_eq!((unknown.id, unknown.body.as_str()), (3, "Unknown command: nothing"));
+    }
+}
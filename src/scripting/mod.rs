@@ -0,0 +1,751 @@
+use std::{
+    collections::HashSet,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock as StdRwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use azalea_chat::{text_component::TextComponent, FormattedText};
+use mlua::{Debug, Function, HookTriggers, Lua, Table, UserData, UserDataFields, UserDataMethods, Value};
+use tokio::{
+    runtime::{Builder, Handle},
+    sync::{mpsc, oneshot, Mutex},
+    time,
+};
+use tracing::{info, warn};
+
+use crate::{
+    event::{EventBus, EventResult, PlayerJoinedProxy, PlayerJoinedServer, ProxyPinged},
+    packet::{play::SystemChatMessagePacket, status::ServerStatus},
+    player::{ConnectionState, PlayerConnection, PlayerInfo},
+    server::ProxiedServer,
+    SharedProxyInstance,
+};
+
+const HANDLERS: &str = "rustyproxy.handlers";
+
+/// How long the scripts may run to handle an event, or to load one script,
+/// before they are aborted. They all share one thread, so a slow handler
+/// holds up every other event.
+const SCRIPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the proxy waits for the scripts to handle an event. Longer than
+/// `SCRIPT_TIMEOUT`, so that what aborted handlers did still counts, and
+/// for handlers waiting on something rather than running.
+const DISPATCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks the deadline every 10000 Lua instructions.
+const DEADLINE_CHECKS: HookTriggers = HookTriggers::new().every_nth_instruction(10_000);
+
+/// When the handler or script running now has to be done.
+struct Deadline(Instant);
+
+/// Runs `.lua` scripts from a directory on a dedicated thread and forwards
+/// proxy events to the handlers they register with `proxy.on`.
+///
+/// ```lua
+/// proxy.on("PlayerJoinedProxy", function(event)
+///     proxy.log(event.player.username .. " joined from " .. event.player.address)
+/// end)
+///
+/// proxy.on("ProxyPinged", function(event)
+///     event.status.motd = "§aWelcome to the network!"
+/// end)
+/// ```
+///
+/// Returning `false` from a handler stops the event, like `EventResult::Stop`.
+/// Handlers still running after a second are aborted, and the event goes on
+/// without them.
+pub struct ScriptEngine {
+    requests: mpsc::UnboundedSender<ScriptRequest>,
+    registered: Arc<StdRwLock<HashSet<String>>>,
+}
+
+enum ScriptRequest {
+    Dispatch {
+        event: ScriptEvent,
+        reply: oneshot::Sender<ScriptReply>,
+    },
+    Reload {
+        reply: oneshot::Sender<Result<usize, mlua::Error>>,
+    },
+}
+
+enum ScriptEvent {
+    PlayerJoinedProxy {
+        player: LuaPlayer,
+    },
+    PlayerJoinedServer {
+        player: LuaPlayer,
+        server: ProxiedServer,
+    },
+    ServerSentPacket {
+        player: LuaPlayer,
+        id: u32,
+        data: Vec<u8>,
+        state: Option<ConnectionState>,
+    },
+    ProxyPinged {
+        address: SocketAddr,
+        protocol: u32,
+        status: ServerStatus,
+    },
+}
+
+#[derive(Default)]
+struct ScriptReply {
+    result: Option<EventResult>,
+    status: Option<ServerStatus>,
+}
+
+impl ScriptEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            ScriptEvent::PlayerJoinedProxy { .. } => "PlayerJoinedProxy",
+            ScriptEvent::PlayerJoinedServer { .. } => "PlayerJoinedServer",
+            ScriptEvent::ServerSentPacket { .. } => "ServerSentPacket",
+            ScriptEvent::ProxyPinged { .. } => "ProxyPinged",
+        }
+    }
+
+    fn into_table(self, lua: &Lua) -> mlua::Result<Table<'_>> {
+        let table = lua.create_table()?;
+
+        match self {
+            ScriptEvent::PlayerJoinedProxy { player } => {
+                table.set("player", player)?;
+            }
+            ScriptEvent::PlayerJoinedServer { player, server } => {
+                table.set("player", player)?;
+                table.set("server", server_table(lua, None, &server)?)?;
+            }
+            ScriptEvent::ServerSentPacket {
+                player,
+                id,
+                data,
+                state,
+            } => {
+                table.set("player", player)?;
+                table.set("id", id)?;
+                table.set("data", lua.create_string(&data)?)?;
+                table.set("state", state.map(state_name))?;
+            }
+            ScriptEvent::ProxyPinged {
+                address,
+                protocol,
+                status,
+            } => {
+                let status_table = lua.create_table()?;
+                status_table.set("motd", status.description.to_string())?;
+                status_table.set("max_players", status.players.max)?;
+                status_table.set("online_players", status.players.online)?;
+                status_table.set("version_name", status.version.name)?;
+                status_table.set("version_protocol", status.version.protocol)?;
+
+                table.set("address", address.to_string())?;
+                table.set("protocol", protocol)?;
+                table.set("status", status_table)?;
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+fn state_name(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Handshake => "Handshake",
+        ConnectionState::Status => "Status",
+        ConnectionState::Login => "Login",
+        ConnectionState::Configuration => "Configuration",
+        ConnectionState::Play => "Play",
+    }
+}
+
+fn server_table<'lua>(
+    lua: &'lua Lua,
+    key: Option<&str>,
+    server: &ProxiedServer,
+) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("key", key)?;
+    table.set("name", server.name.as_str())?;
+    table.set("address", server.address.as_str())?;
+    table.set("port", server.port)?;
+    Ok(table)
+}
+
+/// A player handed to scripts. Packet writes are spawned onto the proxy's
+/// runtime, since the player's socket belongs to it.
+#[derive(Clone)]
+struct LuaPlayer {
+    connection: Arc<Mutex<PlayerConnection>>,
+    info: Option<PlayerInfo>,
+    addr: SocketAddr,
+    handle: Handle,
+}
+
+impl LuaPlayer {
+    async fn from_connection(connection: &Arc<Mutex<PlayerConnection>>, handle: &Handle) -> Self {
+        let cnx = connection.lock().await;
+        let info = cnx.player_info.lock().await.clone();
+
+        LuaPlayer {
+            connection: Arc::clone(connection),
+            info,
+            addr: cnx.addr,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl UserData for LuaPlayer {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("username", |_, this| {
+            Ok(this.info.as_ref().map(|info| info.username.clone()))
+        });
+        fields.add_field_method_get("uuid", |_, this| {
+            Ok(this.info.as_ref().map(|info| info.uuid.to_string()))
+        });
+        fields.add_field_method_get("address", |_, this| Ok(this.addr.to_string()));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Only valid once the player reached the Play state.
+        methods.add_async_method("send_message", |_, this, text: String| async move {
+            let connection = Arc::clone(&this.connection);
+
+            this.handle
+                .spawn(async move {
+                    let cnx = connection.lock().await;
+                    cnx.send_packet(&SystemChatMessagePacket {
+                        text: FormattedText::Text(TextComponent::new(text)),
+                        overlay: false,
+                    })
+                })
+                .await
+                .map_err(mlua::Error::external)?
+                .map_err(mlua::Error::external)
+        });
+    }
+}
+
+impl ScriptEngine {
+    /// Starts the scripting thread and loads every `.lua` file in `directory`.
+    pub async fn load(
+        directory: impl Into<PathBuf>,
+        instance: &SharedProxyInstance,
+    ) -> Result<Arc<Self>, mlua::Error> {
+        let directory = directory.into();
+        let instance = Arc::clone(instance);
+        let handle = Handle::current();
+        let registered = Arc::new(StdRwLock::new(HashSet::new()));
+
+        let (requests, mut receiver) = mpsc::unbounded_channel::<ScriptRequest>();
+        let (loaded, loaded_receiver) = oneshot::channel();
+
+        let thread_registered = Arc::clone(&registered);
+        thread::Builder::new()
+            .name("rustyproxy-lua".to_owned())
+            .spawn(move || {
+                let runtime = match Builder::new_current_thread().enable_all().build() {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = loaded.send(Err(mlua::Error::external(e)));
+                        return;
+                    }
+                };
+
+                runtime.block_on(async move {
+                    let mut lua = match new_state(&directory, &instance, &handle) {
+                        Ok((lua, names)) => {
+                            publish_plugins(&instance, &handle, names);
+                            *thread_registered.write().unwrap() = registered_events(&lua);
+                            let _ = loaded.send(Ok(()));
+                            lua
+                        }
+                        Err(e) => {
+                            let _ = loaded.send(Err(e));
+                            return;
+                        }
+                    };
+
+                    while let Some(request) = receiver.recv().await {
+                        match request {
+                            ScriptRequest::Dispatch { event, reply } => {
+                                // The proxy gave up waiting for this one already.
+                                if reply.is_closed() {
+                                    continue;
+                                }
+
+                                let _ = reply.send(dispatch(&lua, event).await);
+                            }
+                            ScriptRequest::Reload { reply } => {
+                                match new_state(&directory, &instance, &handle) {
+                                    Ok((new_lua, names)) => {
+                                        lua = new_lua;
+                                        *thread_registered.write().unwrap() = registered_events(&lua);
+                                        let _ = reply.send(Ok(names.len()));
+                                        publish_plugins(&instance, &handle, names);
+                                    }
+                                    Err(e) => {
+                                        let _ = reply.send(Err(e));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                });
+            })
+            .map_err(mlua::Error::external)?;
+
+        loaded_receiver
+            .await
+            .map_err(mlua::Error::external)??;
+
+        Ok(Arc::new(ScriptEngine {
+            requests,
+            registered,
+        }))
+    }
+
+    /// Reloads every script from disk, returning how many were loaded. The
+    /// previous scripts stay active if any of the new ones fail to load.
+    pub async fn reload(&self) -> Result<usize, mlua::Error> {
+        let (reply, receiver) = oneshot::channel();
+        self.requests
+            .send(ScriptRequest::Reload { reply })
+            .map_err(|_| mlua::Error::external("scripting thread stopped"))?;
+
+        receiver.await.map_err(mlua::Error::external)?
+    }
+
+    fn handles(&self, event: &str) -> bool {
+        self.registered.read().unwrap().contains(event)
+    }
+
+    async fn dispatch(&self, event: ScriptEvent) -> ScriptReply {
+        let name = event.name();
+        if !self.handles(name) {
+            return ScriptReply::default();
+        }
+
+        let (reply, receiver) = oneshot::channel();
+        if self
+            .requests
+            .send(ScriptRequest::Dispatch { event, reply })
+            .is_err()
+        {
+            return ScriptReply::default();
+        }
+
+        match time::timeout(DISPATCH_TIMEOUT, receiver).await {
+            Ok(reply) => reply.unwrap_or_default(),
+            Err(_) => {
+                warn!("Scripts took longer than {:?} to handle {}", DISPATCH_TIMEOUT, name);
+                ScriptReply::default()
+            }
+        }
+    }
+
+    /// Registers the listeners that forward events to the loaded scripts.
+    pub async fn install(self: &Arc<Self>, event_bus: &Arc<EventBus>) {
+        let handle = Handle::current();
+
+        let engine = Arc::clone(self);
+        let listener_handle = handle.clone();
+        event_bus
+            .listen::<PlayerJoinedProxy, _, _, _>(false, move |_, event| {
+                let engine = Arc::clone(&engine);
+                let handle = listener_handle.clone();
+                async move {
+                    if !engine.handles("PlayerJoinedProxy") {
+                        return None;
+                    }
+
+                    let player = LuaPlayer::from_connection(&event.connection, &handle).await;
+                    engine
+                        .dispatch(ScriptEvent::PlayerJoinedProxy { player })
+                        .await
+                        .result
+                }
+            })
+            .await;
+
+        let engine = Arc::clone(self);
+        let listener_handle = handle.clone();
+        event_bus
+            .listen::<PlayerJoinedServer, _, _, _>(false, move |_, event| {
+                let engine = Arc::clone(&engine);
+                let handle = listener_handle.clone();
+                async move {
+                    if !engine.handles("PlayerJoinedServer") {
+                        return None;
+                    }
+
+                    let player = LuaPlayer::from_connection(&event.connection, &handle).await;
+                    engine
+                        .dispatch(ScriptEvent::PlayerJoinedServer {
+                            player,
+                            server: event.server.as_ref().clone(),
+                        })
+                        .await
+                        .result
+                }
+            })
+            .await;
+
+        // Every packet would be decoded for the scripts otherwise, even when
+        // none of them handles packets.
+        let engine = Arc::clone(self);
+        let wanted_engine = Arc::clone(self);
+        let listener_handle = handle.clone();
+        event_bus
+            .listen_packets_while(move || wanted_engine.handles("ServerSentPacket"), false, move |_, event| {
+                let engine = Arc::clone(&engine);
+                let handle = listener_handle.clone();
+                async move {
+                    if !engine.handles("ServerSentPacket") {
+                        return None;
+                    }
+
+                    let player = LuaPlayer::from_connection(&event.connection, &handle).await;
+                    let state = {
+                        let cnx = event.connection.lock().await;
+                        let server = cnx.server.lock().await;
+                        server.as_ref().and_then(|server| server.state.clone())
+                    };
+
+                    engine
+                        .dispatch(ScriptEvent::ServerSentPacket {
+                            player,
+                            id: event.packet.id,
+                            data: event.packet.data.clone(),
+                            state,
+                        })
+                        .await
+                        .result
+                }
+            })
+            .await;
+
+        let engine = Arc::clone(self);
+        event_bus
+            .listen::<ProxyPinged, _, _, _>(false, move |_, event| {
+                let engine = Arc::clone(&engine);
+                async move {
+                    if !engine.handles("ProxyPinged") {
+                        return None;
+                    }
+
+                    let address = event.connection.lock().await.addr;
+                    let status = event.status.lock().await.clone();
+
+                    let reply = engine
+                        .dispatch(ScriptEvent::ProxyPinged {
+                            address,
+                            protocol: event.handshake.protocol,
+                            status,
+                        })
+                        .await;
+
+                    if let Some(status) = reply.status {
+                        *event.status.lock().await = status;
+                    }
+
+                    reply.result
+                }
+            })
+            .await;
+    }
+}
+
+fn new_state(
+    directory: &Path,
+    instance: &SharedProxyInstance,
+    handle: &Handle,
+) -> Result<(Lua, Vec<String>), mlua::Error> {
+    let lua = Lua::new();
+    lua.set_named_registry_value(HANDLERS, lua.create_table()?)?;
+    lua.set_hook(DEADLINE_CHECKS, check_deadline);
+
+    let proxy = lua.create_table()?;
+
+    proxy.set(
+        "on",
+        lua.create_function(|lua, (event, handler): (String, Function)| {
+            let handlers: Table = lua.named_registry_value(HANDLERS)?;
+            let list = match handlers.get::<_, Option<Table>>(event.as_str())? {
+                Some(list) => list,
+                None => {
+                    let list = lua.create_table()?;
+                    handlers.set(event.as_str(), list.clone())?;
+                    list
+                }
+            };
+
+            list.push(handler)
+        })?,
+    )?;
+
+    proxy.set(
+        "log",
+        lua.create_function(|_, message: String| {
+            info!(target: "script", "{}", message);
+            Ok(())
+        })?,
+    )?;
+
+    let servers_instance = Arc::clone(instance);
+    let servers_handle = handle.clone();
+    proxy.set(
+        "servers",
+        lua.create_async_function(move |lua, ()| {
+            let instance = Arc::clone(&servers_instance);
+            let handle = servers_handle.clone();
+            async move {
+                let servers = handle
+                    .spawn(async move {
+                        let instance = instance.read().await;
+                        instance
+                            .servers
+                            .iter()
+                            .map(|(key, server)| (key.clone(), server.as_ref().clone()))
+                            .collect::<Vec<_>>()
+                    })
+                    .await
+                    .map_err(mlua::Error::external)?;
+
+                let table = lua.create_table()?;
+                for (key, server) in servers {
+                    table.push(server_table(lua, Some(&key), &server)?)?;
+                }
+
+                Ok(table)
+            }
+        })?,
+    )?;
+
+    lua.globals().set("proxy", proxy)?;
+
+    let mut scripts = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "lua"))
+            .collect::<Vec<_>>(),
+        Err(e) => return Err(mlua::Error::external(e)),
+    };
+    scripts.sort();
+
+    for script in &scripts {
+        let source = fs::read_to_string(script).map_err(mlua::Error::external)?;
+        lua.set_app_data(Deadline(Instant::now() + SCRIPT_TIMEOUT));
+        lua.load(&source)
+            .set_name(script.display().to_string())
+            .exec()?;
+    }
+
+    let names = scripts
+        .iter()
+        .filter_map(|script| script.file_stem())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect();
+
+    Ok((lua, names))
+}
+
+/// Lists the loaded scripts as the proxy's plugins.
+fn publish_plugins(instance: &SharedProxyInstance, handle: &Handle, names: Vec<String>) {
+    let instance = Arc::clone(instance);
+    handle.spawn(async move { instance.write().await.plugins = names });
+}
+
+/// Aborts scripts that loop or compute past their deadline.
+fn check_deadline(lua: &Lua, _: Debug) -> mlua::Result<()> {
+    match lua.app_data_ref::<Deadline>() {
+        Some(deadline) if Instant::now() > deadline.0 => Err(mlua::Error::RuntimeError(format!(
+            "script ran longer than {:?}",
+            SCRIPT_TIMEOUT
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn registered_events(lua: &Lua) -> HashSet<String> {
+    lua.named_registry_value::<Table>(HANDLERS)
+        .map(|handlers| {
+            handlers
+                .pairs::<String, Value>()
+                .filter_map(|pair| pair.ok().map(|(event, _)| event))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn dispatch(lua: &Lua, event: ScriptEvent) -> ScriptReply {
+    let name = event.name();
+    let original_status = match &event {
+        ScriptEvent::ProxyPinged { status, .. } => Some(status.clone()),
+        _ => None,
+    };
+
+    let handlers = match lua
+        .named_registry_value::<Table>(HANDLERS)
+        .and_then(|handlers| handlers.get::<_, Option<Table>>(name))
+    {
+        Ok(Some(handlers)) => handlers,
+        _ => return ScriptReply::default(),
+    };
+
+    let table = match event.into_table(lua) {
+        Ok(table) => table,
+        Err(e) => {
+            warn!("Failed to pass {} to scripts: {}", name, e);
+            return ScriptReply::default();
+        }
+    };
+
+    let mut reply = ScriptReply::default();
+    lua.set_app_data(Deadline(Instant::now() + SCRIPT_TIMEOUT));
+
+    for handler in handlers.sequence_values::<Function>() {
+        // Each handler runs in its own coroutine, which the hook has to be
+        // set on, as mlua only calls it for the thread it was set for.
+        let thread = match handler.and_then(|handler| lua.create_thread(handler)) {
+            Ok(thread) => thread,
+            Err(_) => continue,
+        };
+        thread.set_hook(DEADLINE_CHECKS, check_deadline);
+
+        match thread.into_async::<_, Value>(table.clone()).await {
+            Ok(Value::Boolean(false)) => reply.result = Some(EventResult::Stop),
+            Ok(_) => (),
+            Err(e) => warn!("Script handler for {} failed: {}", name, e),
+        }
+    }
+
+    if let Some(mut status) = original_status {
+        if let Ok(status_table) = table.get::<_, Table>("status") {
+            if let Ok(motd) = status_table.get::<_, String>("motd") {
+                if motd != status.description.to_string() {
+                    status.description = FormattedText::Text(TextComponent::new(motd));
+                }
+            }
+            if let Ok(max) = status_table.get::<_, u32>("max_players") {
+                status.players.max = max;
+            }
+            if let Ok(online) = status_table.get::<_, u32>("online_players") {
+                status.players.online = online;
+            }
+            if let Ok(name) = status_table.get::<_, String>("version_name") {
+                status.version.name = name;
+            }
+            if let Ok(protocol) = status_table.get::<_, u32>("version_protocol") {
+                status.version.protocol = protocol;
+            }
+
+            reply.status = Some(status);
+        }
+    }
+
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packet::{
+            registry::PacketKind,
+            status::{StatusPlayers, StatusVersion},
+        },
+        ProxyConfiguration,
+    };
+
+    /// A fresh directory with `scripts`, by file name.
+    fn script_directory(scripts: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rustyproxy-scripts-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        for (name, source) in scripts {
+            fs::write(directory.join(name), source).unwrap();
+        }
+        directory
+    }
+
+    fn instance() -> SharedProxyInstance {
+        let config: ProxyConfiguration = toml::from_str("proxy_port = 25565\naddress = \"127.0.0.1\"").unwrap();
+        crate::new_instance(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn packets_skip_scripts_without_a_packet_handler() {
+        let directory = script_directory(&[(
+            "joins.lua",
+            r#"proxy.on("PlayerJoinedProxy", function(event) end)"#,
+        )]);
+        let instance = instance();
+        let event_bus = EventBus::new(&instance);
+
+        let engine = ScriptEngine::load(&directory, &instance).await.unwrap();
+        engine.install(&event_bus).await;
+
+        assert!(!event_bus.wants_packet(None));
+        for kind in PacketKind::ALL {
+            assert!(!event_bus.wants_packet(Some(kind)));
+        }
+
+        // A reloaded script that handles packets sees all of them.
+        fs::write(
+            directory.join("packets.lua"),
+            r#"proxy.on("ServerSentPacket", function(event) end)"#,
+        )
+        .unwrap();
+        assert_eq!(engine.reload().await.unwrap(), 2);
+        assert!(event_bus.wants_packet(None));
+        assert!(event_bus.wants_packet(Some(PacketKind::ALL[0])));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn pinged(protocol: u32) -> ScriptEvent {
+        ScriptEvent::ProxyPinged {
+            address: "127.0.0.1:50000".parse().unwrap(),
+            protocol,
+            status: ServerStatus {
+                version: StatusVersion { name: "rustyproxy".to_owned(), protocol },
+                players: StatusPlayers { max: 100, online: 0 },
+                description: FormattedText::Text(TextComponent::new("A server".to_owned())),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn runaway_handlers_are_aborted() {
+        let directory = script_directory(&[(
+            "loop.lua",
+            r#"
+                proxy.on("ProxyPinged", function(event)
+                    if event.protocol == 1 then
+                        while true do end
+                    end
+                    event.status.motd = "fine with " .. #proxy.servers() .. " servers"
+                end)
+            "#,
+        )]);
+        let engine = ScriptEngine::load(&directory, &instance()).await.unwrap();
+
+        let started = Instant::now();
+        let reply = engine.dispatch(pinged(1)).await;
+        assert!(started.elapsed() < DISPATCH_TIMEOUT);
+        assert_eq!(reply.status.unwrap().description.to_string(), "A server");
+
+        // The scripting thread is free again afterwards.
+        let reply = engine.dispatch(pinged(2)).await;
+        assert_eq!(reply.status.unwrap().description.to_string(), "fine with 0 servers");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Groups of interchangeable servers, e.g. several lobbies, that players are
+//! spread over. A group can be used wherever a server key can: in `try`, as
+//! the target of a drain, or with `/server` and `/send`.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::ProxiedServer;
+
+/// How a group picks the server a player goes to.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BalancingStrategy {
+    /// The server with the fewest players from this proxy.
+    #[default]
+    LeastPlayers,
+    /// Each server in turn.
+    RoundRobin,
+    Random,
+    /// At random, in proportion to each server's weight.
+    Weighted,
+    /// Always the same server for the same player, as long as it is
+    /// available. Only the players of a server that leaves move elsewhere.
+    ConsistentHash,
+}
+
+/// A group under `[groups]`, e.g.
+/// `lobby = { servers = ["lobby1", "lobby2"], strategy = "round_robin" }`.
+#[derive(Deserialize)]
+pub struct ServerGroup {
+    /// Keys of the servers in the group.
+    pub servers: Vec<String>,
+    #[serde(default)]
+    pub strategy: BalancingStrategy,
+    /// How many players a server gets relative to the others with
+    /// `weighted`, defaulting to 1.
+    #[serde(default)]
+    pub weights: HashMap<String, u32>,
+    #[serde(skip)]
+    next: AtomicUsize,
+}
+
+impl ServerGroup {
+    /// Picks one of `candidates`, the servers of the group that can take
+    /// players, for the player `uuid`. `players` counts the players on each
+    /// candidate, by key, and is only needed for `least_players`.
+    pub fn pick<'a>(
+        &self,
+        candidates: &'a [(String, Arc<ProxiedServer>)],
+        uuid: Uuid,
+        players: &HashMap<String, usize>,
+    ) -> Option<&'a (String, Arc<ProxiedServer>)> {
+        if candidates.is_empty() {
+            return None;
+        }
+
+        match self.strategy {
+            BalancingStrategy::LeastPlayers => candidates
+                .iter()
+                .min_by_key(|(key, _)| players.get(key).copied().unwrap_or(0)),
+            BalancingStrategy::RoundRobin => {
+                let next = self.next.fetch_add(1, Ordering::Relaxed);
+                candidates.get(next % candidates.len())
+            }
+            BalancingStrategy::Random => candidates.get(random() as usize % candidates.len()),
+            BalancingStrategy::Weighted => {
+                let weight = |key: &String| self.weights.get(key).copied().unwrap_or(1) as u64;
+                let total: u64 = candidates.iter().map(|(key, _)| weight(key)).sum();
+                if total == 0 {
+                    return None;
+                }
+
+                let mut roll = random() % total;
+                candidates.iter().find(|(key, _)| {
+                    let weight = weight(key);
+                    if roll < weight {
+                        return true;
+                    }
+                    roll -= weight;
+                    false
+                })
+            }
+            // Rendezvous hashing: every player ranks the servers the same way
+            // whichever of them are available.
+            BalancingStrategy::ConsistentHash => candidates
+                .iter()
+                .max_by_key(|(key, _)| fnv1a(&[uuid.as_bytes(), key.as_bytes()])),
+        }
+    }
+}
+
+/// 62 random bits. The two above them in the low half of a UUID are its
+/// variant, which is always the same.
+fn random() -> u64 {
+    Uuid::new_v4().as_u128() as u64 & (u64::MAX >> 2)
+}
+
+/// The 64-bit FNV-1a hash of `parts` one after another. Unlike the standard
+/// library's hasher it never changes, so players keep their server across
+/// upgrades, and proxies of different versions agree on it.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    parts.iter().flat_map(|part| part.iter()).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(strategy: BalancingStrategy, weights: &[(&str, u32)]) -> ServerGroup {
+        ServerGroup {
+            servers: Vec::new(),
+            strategy,
+            weights: weights.iter().map(|(key, weight)| (key.to_string(), *weight)).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn candidates(keys: &[&str]) -> Vec<(String, Arc<ProxiedServer>)> {
+        keys.iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let server = ProxiedServer::new(key.to_string(), "127.0.0.1".to_owned(), 25565 + i as u16);
+                (key.to_string(), Arc::new(server))
+            })
+            .collect()
+    }
+
+    fn picked(group: &ServerGroup, candidates: &[(String, Arc<ProxiedServer>)], uuid: Uuid) -> Option<String> {
+        group.pick(candidates, uuid, &HashMap::new()).map(|(key, _)| key.clone())
+    }
+
+    #[test]
+    fn no_candidates_pick_nothing() {
+        for strategy in [
+            BalancingStrategy::LeastPlayers,
+            BalancingStrategy::RoundRobin,
+            BalancingStrategy::Random,
+            BalancingStrategy::Weighted,
+            BalancingStrategy::ConsistentHash,
+        ] {
+            assert_eq!(picked(&group(strategy, &[]), &[], Uuid::new_v4()), None);
+        }
+    }
+
+    #[test]
+    fn least_players_picks_the_emptiest() {
+        let group = group(BalancingStrategy::LeastPlayers, &[]);
+        let candidates = candidates(&["a", "b", "c"]);
+        let players = HashMap::from([("a".to_owned(), 3), ("b".to_owned(), 1), ("c".to_owned(), 2)]);
+
+        let (key, _) = group.pick(&candidates, Uuid::new_v4(), &players).unwrap();
+        assert_eq!(key, "b");
+
+        // A server nobody counted has no players.
+        let players = HashMap::from([("a".to_owned(), 3), ("b".to_owned(), 1)]);
+        let (key, _) = group.pick(&candidates, Uuid::new_v4(), &players).unwrap();
+        assert_eq!(key, "c");
+    }
+
+    #[test]
+    fn round_robin_takes_turns() {
+        let group = group(BalancingStrategy::RoundRobin, &[]);
+        let candidates = candidates(&["a", "b", "c"]);
+
+        let picks: Vec<_> = (0..6).map(|_| picked(&group, &candidates, Uuid::new_v4()).unwrap()).collect();
+        assert_eq!(picks, ["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn random_picks_a_candidate() {
+        let group = group(BalancingStrategy::Random, &[]);
+        let candidates = candidates(&["a", "b"]);
+
+        for _ in 0..100 {
+            let key = picked(&group, &candidates, Uuid::new_v4()).unwrap();
+            assert!(key == "a" || key == "b");
+        }
+    }
+
+    #[test]
+    fn weighted_skips_servers_without_weight() {
+        let group = group(BalancingStrategy::Weighted, &[("a", 0), ("c", 5)]);
+        let candidates = candidates(&["a", "b", "c"]);
+
+        let mut counts = HashMap::new();
+        for _ in 0..600 {
+            *counts.entry(picked(&group, &candidates, Uuid::new_v4()).unwrap()).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.get("a"), None);
+        // b weighs 1 and c 5, so c comes up far more often.
+        assert!(counts["c"] > counts["b"]);
+    }
+
+    #[test]
+    fn weighted_without_any_weight_picks_nothing() {
+        let group = group(BalancingStrategy::Weighted, &[("a", 0), ("b", 0)]);
+        assert_eq!(picked(&group, &candidates(&["a", "b"]), Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn consistent_hash_is_stable() {
+        let group = group(BalancingStrategy::ConsistentHash, &[]);
+        let all = candidates(&["a", "b", "c", "d"]);
+        let uuids: Vec<Uuid> = (0..200).map(|_| Uuid::new_v4()).collect();
+
+        let before: Vec<String> = uuids.iter().map(|uuid| picked(&group, &all, *uuid).unwrap()).collect();
+        let again: Vec<String> = uuids.iter().map(|uuid| picked(&group, &all, *uuid).unwrap()).collect();
+        assert_eq!(before, again);
+
+        // Only the players of the server that left go somewhere else.
+        let without_b: Vec<_> = all.iter().filter(|(key, _)| key != "b").cloned().collect();
+        for (uuid, before) in uuids.iter().zip(&before) {
+            let after = picked(&group, &without_b, *uuid).unwrap();
+            if before == "b" {
+                assert_ne!(after, "b");
+            } else {
+                assert_eq!(&after, before);
+            }
+        }
+    }
+
+    #[test]
+    fn consistent_hash_is_fnv1a() {
+        assert_eq!(fnv1a(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(&[b"a"]), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(&[b"foo", b"bar"]), fnv1a(&[b"foobar"]));
+        assert_eq!(fnv1a(&[b"foobar"]), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn random_leaves_out_the_variant() {
+        for _ in 0..100 {
+            assert!(random() < 1 << 62);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Pings every server in the background, the way the server list does, to
+//! know which of them are up.
+
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use azalea_chat::FormattedText;
+use serde::Deserialize;
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    task::JoinSet,
+    time::{self, MissedTickBehavior},
+};
+use tracing::{info, warn};
+
+use crate::{
+    event::{EventBus, ServerCameUp, ServerWentDown},
+    packet::{
+        self,
+        handshake::HandshakePacket,
+        registry,
+        status::{PingRequestPacket, PongResponsePacket, StatusRequestPacket, StatusResponsePacket},
+        FrameLimits, Packet, PacketDirection, PacketLimits,
+    },
+    player::ConnectionState,
+    SharedProxyInstance,
+};
+
+use super::ProxiedServer;
+
+/// How often servers are pinged and how long they get to answer, e.g.
+/// `[health_checks]` with `interval = 10` and `timeout = 3`, in seconds.
+/// Every field is optional.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct HealthCheckConfiguration {
+    pub interval: u64,
+    pub timeout: u64,
+}
+
+impl Default for HealthCheckConfiguration {
+    fn default() -> Self {
+        HealthCheckConfiguration {
+            interval: 10,
+            timeout: 3,
+        }
+    }
+}
+
+/// What the last ping of a server found. A server counts as online until a
+/// ping fails.
+#[derive(Clone, Debug)]
+pub struct ServerHealth {
+    pub online: bool,
+    /// How long the ping after the status request took to come back.
+    pub latency: Option<Duration>,
+    pub players: Option<u32>,
+    pub max_players: Option<u32>,
+    pub motd: Option<FormattedText>,
+    pub checked_at: Option<Instant>,
+}
+
+impl Default for ServerHealth {
+    fn default() -> Self {
+        ServerHealth {
+            online: true,
+            latency: None,
+            players: None,
+            max_players: None,
+            motd: None,
+            checked_at: None,
+        }
+    }
+}
+
+/// The parts of a status response worth keeping.
+#[derive(Deserialize)]
+struct PingedStatus {
+    players: Option<PingedPlayers>,
+    description: Option<FormattedText>,
+}
+
+#[derive(Deserialize)]
+struct PingedPlayers {
+    max: u32,
+    online: u32,
+}
+
+/// Pings every server each interval until the proxy stops, firing
+/// `ServerWentDown` and `ServerCameUp` whenever one changes state.
+pub async fn monitor(instance: SharedProxyInstance, event_bus: Arc<EventBus>, config: HealthCheckConfiguration) {
+    let timeout = Duration::from_secs(config.timeout);
+    let mut interval = time::interval(Duration::from_secs(config.interval.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let servers: Vec<_> = instance
+            .read()
+            .await
+            .servers
+            .iter()
+            .map(|(key, server)| (key.clone(), Arc::clone(server)))
+            .collect();
+
+        let mut pings = JoinSet::new();
+        for (key, server) in servers {
+            pings.spawn(async move {
+                let result = match time::timeout(timeout, ping(&server, registry::LATEST_PROTOCOL)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(Error::new(ErrorKind::TimedOut, "no answer in time")),
+                };
+                (key, server, result)
+            });
+        }
+
+        while let Some(joined) = pings.join_next().await {
+            let Ok((key, server, result)) = joined else {
+                continue;
+            };
+
+            match result {
+                Ok(health) => {
+                    if !server.set_health(health) {
+                        info!("Server {} came back up", key);
+                        event_bus.dispatch(&Arc::new(ServerCameUp { key, server })).await;
+                    }
+                }
+                Err(e) => {
+                    let health = ServerHealth {
+                        online: false,
+                        checked_at: Some(Instant::now()),
+                        ..ServerHealth::default()
+                    };
+
+                    if server.set_health(health) {
+                        warn!("Server {} went down: {}", key, e);
+                        event_bus.dispatch(&Arc::new(ServerWentDown { key, server })).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Requests the status of `server` as a client speaking `protocol` would,
+/// timing the ping that follows it.
+pub async fn ping(server: &ProxiedServer, protocol: u32) -> Result<ServerHealth, Error> {
+    let mut stream = server.establish_connection().await?;
+    let limits = PacketLimits::default().frame_limits(&ConnectionState::Status, PacketDirection::Playerbound);
+
+    let handshake = HandshakePacket {
+        protocol,
+        server_address: server.address.clone(),
+        port: server.port,
+        next_state: 1,
+    };
+    write(&mut stream, &handshake, protocol).await?;
+    write(&mut stream, &StatusRequestPacket {}, protocol).await?;
+
+    let response: StatusResponsePacket = read(&mut stream, protocol, limits).await?;
+    let status: PingedStatus =
+        serde_json::from_str(&response.status).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let sent = Instant::now();
+    write(&mut stream, &PingRequestPacket { payload: 0 }, protocol).await?;
+    let _: PongResponsePacket = read(&mut stream, protocol, limits).await?;
+    let latency = sent.elapsed();
+
+    let _ = stream.shutdown().await;
+
+    Ok(ServerHealth {
+        online: true,
+        latency: Some(latency),
+        players: status.players.as_ref().map(|players| players.online),
+        max_players: status.players.as_ref().map(|players| players.max),
+        motd: status.description,
+        checked_at: Some(Instant::now()),
+    })
+}
+
+async fn write<P: Packet>(stream: &mut TcpStream, packet: &P, protocol: u32) -> Result<(), Error> {
+    let (_, data) = packet::encode_packet(packet, protocol)?;
+    stream.write_all(&packet::frame_packet(&data, 0)?).await
+}
+
+async fn read<P: Packet>(stream: &mut TcpStream, protocol: u32, limits: FrameLimits) -> Result<P, Error> {
+    let packet = packet::read_packet(stream, 0, limits).await?;
+
+    if !packet.is::<P>(protocol) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("expected {:?}, got packet {:#04x}", P::kind(), packet.id),
+        ));
+    }
+
+    Ok(packet.decode_as()?)
+}
@@ -1,16 +1,43 @@
-use std::io::Error;
+pub mod group;
+pub mod health;
+pub mod queue;
+
+use std::{
+    io::Error,
+    sync::{Arc, RwLock as StdRwLock},
+};
 
 use serde::Deserialize;
 use tokio::net::TcpStream;
 
+use health::ServerHealth;
 
-
-#[derive(Clone, Deserialize,serde::Serialize)]
+#[derive(Clone, Deserialize, serde::Serialize)]
 pub struct ProxiedServer {
     pub address: String,
     pub port: u16,
-    pub name: String
+    pub name: String,
+    /// Players beyond this wait in a queue to join.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_players: Option<u32>,
+    /// Updated by health checks, when they are enabled.
+    #[serde(skip)]
+    health: Arc<StdRwLock<ServerHealth>>,
+}
+
+// Servers are the same when they point at the same place, whatever their
+// health.
+impl PartialEq for ProxiedServer {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+            && self.port == other.port
+            && self.name == other.name
+            && self.max_players == other.max_players
+    }
 }
+
+impl Eq for ProxiedServer {}
+
 impl ProxiedServer {
     pub(crate) async  fn establish_connection(&self) -> Result<TcpStream, Error> {
         TcpStream::connect((self.address.as_str(), self.port)).await
@@ -21,18 +48,41 @@ impl ProxiedServer {
             address,
             port,
             name,
+            max_players: None,
+            health: Arc::default(),
         }
     }
+
+    /// What the last health check found.
+    pub fn health(&self) -> ServerHealth {
+        self.health.read().unwrap().clone()
+    }
+
+    /// Whether the server answered its last health check, or was never
+    /// checked.
+    pub fn is_online(&self) -> bool {
+        self.health.read().unwrap().online
+    }
+
+    /// Records a health check, returning whether the server was online
+    /// before it.
+    pub(crate) fn set_health(&self, health: ServerHealth) -> bool {
+        let mut current = self.health.write().unwrap();
+        let was_online = current.online;
+        *current = health;
+        was_online
+    }
 }
 
 pub mod plugin_channel {
-    use std::io::Error;
+    use crate::packet::{
+        data::{ByteBuf, ByteBufMut},
+        PacketError,
+    };
 
-    pub trait PluginChannel: Clone {
+    pub trait PluginChannel: Clone + Send {
         fn id() -> String;
-        fn write_to(&self, buffer: &mut Vec<u8>);
-        fn read_from(
-            buffer: &mut Vec<u8>,
-        ) -> impl std::future::Future<Output = Result<Box<Self>, Error>> + Send;
-        }
+        fn write_to(&self, buffer: &mut ByteBufMut);
+        fn read_from(buffer: &mut ByteBuf) -> Result<Self, PacketError>;
+    }
 }
\ No newline at end of file
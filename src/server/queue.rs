@@ -0,0 +1,263 @@
+//! Queues for servers and groups that are full. Queued players stay where
+//! they are, see their position in the action bar and are moved as soon as
+//! a slot opens, players with a higher priority first.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::Mutex, task, time};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{command::legacy_text, event::QueuePriorityChecked, player::PlayerConnection, players_by_server, SharedProxyInstance};
+
+/// How often queues move and positions are shown again, which is about as
+/// long as the action bar stays visible.
+const QUEUE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Gives queued players a priority of 1 instead of 0.
+pub const PRIORITY_PERMISSION: &str = "rustyproxy.queue.priority";
+
+#[derive(Clone, Debug)]
+pub struct QueuedPlayer {
+    pub uuid: Uuid,
+    /// Players with a higher priority are let in first.
+    pub priority: i32,
+    pub since: Instant,
+}
+
+/// The queues of the proxy, by the server key or group name they wait for.
+/// A player waits in one queue at most.
+#[derive(Default)]
+pub struct ServerQueues {
+    queues: HashMap<String, VecDeque<QueuedPlayer>>,
+}
+
+impl ServerQueues {
+    /// Puts `player` in the queue for `target`, behind everyone with the
+    /// same or a higher priority, and returns its position from 1 on. The
+    /// player leaves any queue it was in before.
+    pub fn join(&mut self, target: &str, player: QueuedPlayer) -> usize {
+        self.leave(player.uuid);
+
+        let queue = self.queues.entry(target.to_owned()).or_default();
+        let index = queue
+            .iter()
+            .position(|queued| queued.priority < player.priority)
+            .unwrap_or(queue.len());
+
+        queue.insert(index, player);
+        index + 1
+    }
+
+    /// Takes the player out of its queue, returning what it waited for.
+    pub fn leave(&mut self, uuid: Uuid) -> Option<String> {
+        let (target, index) = self.queues.iter().find_map(|(target, queue)| {
+            let index = queue.iter().position(|queued| queued.uuid == uuid)?;
+            Some((target.clone(), index))
+        })?;
+
+        let queue = self.queues.get_mut(&target)?;
+        queue.remove(index);
+        if queue.is_empty() {
+            self.queues.remove(&target);
+        }
+
+        Some(target)
+    }
+
+    /// What the player waits for and its position from 1 on, if queued.
+    pub fn position(&self, uuid: Uuid) -> Option<(&str, usize)> {
+        self.queues.iter().find_map(|(target, queue)| {
+            let index = queue.iter().position(|queued| queued.uuid == uuid)?;
+            Some((target.as_str(), index + 1))
+        })
+    }
+
+    /// How many players wait for `target`.
+    pub fn len(&self, target: &str) -> usize {
+        self.queues.get(target).map_or(0, VecDeque::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
+/// The priority `player` waits for `target` with: 1 with the priority
+/// permission, else 0, unless a `QueuePriorityChecked` listener says
+/// otherwise.
+pub async fn priority(player: &PlayerConnection, target: &str) -> i32 {
+    let priority = if player.has_permission(PRIORITY_PERMISSION).await { 1 } else { 0 };
+
+    let event = Arc::new(QueuePriorityChecked {
+        connection: Arc::new(Mutex::new(player.clone())),
+        target: target.to_owned(),
+        priority: Arc::new(Mutex::new(priority)),
+    });
+    player.event_bus().dispatch(&event).await;
+
+    let priority = *event.priority.lock().await;
+    priority
+}
+
+/// Moves queued players once there is room for them and shows the others
+/// their position, until the proxy stops.
+pub async fn run(instance: SharedProxyInstance) {
+    let mut interval = time::interval(QUEUE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if instance.read().await.queues.is_empty() {
+            continue;
+        }
+
+        let mut moves = Vec::new();
+        let mut waiting = Vec::new();
+
+        {
+            let mut players = players_by_server(&instance).await;
+            let mut instance = instance.write().await;
+            let mut queues = std::mem::take(&mut instance.queues.queues);
+
+            for (target, queue) in queues.iter_mut() {
+                // Players that left the proxy give up their place.
+                queue.retain(|queued| instance.players.contains_key(&queued.uuid));
+
+                while let Some(queued) = queue.front() {
+                    let Some((key, server)) = instance
+                        .pick_server(target, queued.uuid, &players)
+                        .filter(|(key, _)| instance.has_room(key, &players))
+                    else {
+                        break;
+                    };
+
+                    let player = instance.players[&queued.uuid].clone();
+                    *players.entry(key.clone()).or_default() += 1;
+                    moves.push((player, queued.uuid, key, server));
+                    queue.pop_front();
+                }
+
+                for (index, queued) in queue.iter().enumerate() {
+                    let player = instance.players[&queued.uuid].clone();
+                    waiting.push((player, target.clone(), index + 1, queue.len()));
+                }
+            }
+
+            queues.retain(|_, queue| !queue.is_empty());
+            instance.queues.queues = queues;
+        }
+
+        for (player, uuid, key, server) in moves {
+            task::spawn(async move {
+                // The player may have got there on its own in the meantime.
+                if player.current_server().await.is_some_and(|current| Arc::ptr_eq(&current, &server)) {
+                    return;
+                }
+
+                info!("Moving {} out of the queue to {}", uuid, key);
+                if let Err(e) = player.send_to(&server).await {
+                    warn!("Failed to move {} out of the queue to {}: {}", uuid, key, e);
+                    let _ = player
+                        .send_message(legacy_text(&format!("§cCould not connect to {}: {}", key, e)))
+                        .await;
+                }
+            });
+        }
+
+        for (player, target, position, length) in waiting {
+            let text = format!("§eQueued for {}: §f{} §eof §f{}", target, position, length);
+            let _ = player.send_action_bar(legacy_text(&text)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(priority: i32) -> QueuedPlayer {
+        QueuedPlayer {
+            uuid: Uuid::new_v4(),
+            priority,
+            since: Instant::now(),
+        }
+    }
+
+    fn order(queues: &ServerQueues, target: &str) -> Vec<Uuid> {
+        queues.queues[target].iter().map(|queued| queued.uuid).collect()
+    }
+
+    #[test]
+    fn players_queue_in_order() {
+        let mut queues = ServerQueues::default();
+        let (first, second) = (queued(0), queued(0));
+
+        assert_eq!(queues.join("lobby", first.clone()), 1);
+        assert_eq!(queues.join("lobby", second.clone()), 2);
+        assert_eq!(queues.position(first.uuid), Some(("lobby", 1)));
+        assert_eq!(queues.position(second.uuid), Some(("lobby", 2)));
+        assert_eq!(queues.len("lobby"), 2);
+        assert_eq!(queues.len("survival"), 0);
+    }
+
+    #[test]
+    fn priority_goes_behind_the_same_or_higher() {
+        let mut queues = ServerQueues::default();
+        let (low, high, other_low, other_high, highest) = (queued(0), queued(1), queued(0), queued(1), queued(2));
+
+        queues.join("lobby", low.clone());
+        assert_eq!(queues.join("lobby", high.clone()), 1);
+        assert_eq!(queues.join("lobby", other_low.clone()), 3);
+        assert_eq!(queues.join("lobby", other_high.clone()), 2);
+        assert_eq!(queues.join("lobby", highest.clone()), 1);
+
+        assert_eq!(
+            order(&queues, "lobby"),
+            [highest.uuid, high.uuid, other_high.uuid, low.uuid, other_low.uuid]
+        );
+    }
+
+    #[test]
+    fn leaving_moves_the_others_up() {
+        let mut queues = ServerQueues::default();
+        let (first, second) = (queued(0), queued(0));
+        queues.join("lobby", first.clone());
+        queues.join("lobby", second.clone());
+
+        assert_eq!(queues.leave(first.uuid), Some("lobby".to_owned()));
+        assert_eq!(queues.position(first.uuid), None);
+        assert_eq!(queues.position(second.uuid), Some(("lobby", 1)));
+        assert_eq!(queues.leave(first.uuid), None);
+
+        // An emptied queue goes away.
+        queues.leave(second.uuid);
+        assert!(queues.is_empty());
+    }
+
+    #[test]
+    fn rejoining_leaves_the_old_place() {
+        let mut queues = ServerQueues::default();
+        let (first, second) = (queued(0), queued(0));
+        queues.join("lobby", first.clone());
+        queues.join("lobby", second.clone());
+
+        // Joining the same queue again goes to the back.
+        assert_eq!(queues.join("lobby", first.clone()), 2);
+        assert_eq!(order(&queues, "lobby"), [second.uuid, first.uuid]);
+
+        // Joining another queue leaves this one.
+        assert_eq!(queues.join("survival", first.clone()), 1);
+        assert_eq!(queues.position(first.uuid), Some(("survival", 1)));
+        assert_eq!(order(&queues, "lobby"), [second.uuid]);
+
+        // So does joining with another priority.
+        assert_eq!(queues.join("lobby", QueuedPlayer { priority: 1, ..first.clone() }), 1);
+        assert_eq!(queues.len("survival"), 0);
+        assert_eq!(order(&queues, "lobby"), [first.uuid, second.uuid]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Hands the listening socket from a running proxy to a newly started one,
+//! so a redeploy never refuses connections. The running proxy listens on a
+//! Unix socket; a new process connects to it on start and receives the
+//! listener's file descriptor, after which the old process stops accepting
+//! and waits for its players to leave.
+
+use std::{io::Error, time::Duration};
+
+use serde::Deserialize;
+use tokio::net::TcpListener;
+
+#[cfg(unix)]
+use std::{
+    fs,
+    io::{ErrorKind, IoSlice, IoSliceMut},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::PermissionsExt,
+    },
+};
+
+#[cfg(unix)]
+use nix::{
+    cmsg_space,
+    sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags, UnixAddr},
+};
+#[cfg(unix)]
+use tokio::{
+    io::Interest,
+    net::{UnixListener, UnixStream},
+};
+
+/// Sent along with the file descriptor, as a message needs a body.
+#[cfg(unix)]
+const HANDOFF: &[u8] = b"L";
+
+/// Where a running proxy offers its listener, e.g. `[upgrade]` with
+/// `socket = "/run/rustyproxy/upgrade.sock"`. Anyone who can connect to the
+/// socket can take the listener, so it should live in a private directory.
+#[derive(Deserialize, Clone)]
+pub struct UpgradeConfiguration {
+    pub socket: String,
+    /// Seconds the old process waits for its players to leave before
+    /// disconnecting them, or indefinitely without it.
+    pub drain_timeout: Option<u64>,
+}
+
+impl UpgradeConfiguration {
+    pub fn drain_timeout(&self) -> Option<Duration> {
+        self.drain_timeout.map(Duration::from_secs)
+    }
+}
+
+/// Offers the listener to new processes.
+pub struct UpgradeListener {
+    #[cfg(unix)]
+    listener: UnixListener,
+}
+
+/// A new process waiting for the listener.
+pub struct Handoff {
+    #[cfg(unix)]
+    stream: UnixStream,
+}
+
+/// Takes the listener of the proxy running at `path`, or `None` when no
+/// proxy is listening there.
+#[cfg(unix)]
+pub async fn inherit(path: &str) -> Result<Option<TcpListener>, Error> {
+    let stream = match UnixStream::connect(path).await {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let fd = stream
+        .async_io(Interest::READABLE, || receive_fd(stream.as_raw_fd()))
+        .await?;
+
+    let listener = std::net::TcpListener::from(fd);
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener).map(Some)
+}
+
+#[cfg(not(unix))]
+pub async fn inherit(_: &str) -> Result<Option<TcpListener>, Error> {
+    Ok(None)
+}
+
+impl UpgradeListener {
+    /// Listens at `path`, replacing whatever socket file is left there.
+    #[cfg(unix)]
+    pub fn bind(path: &str) -> Result<UpgradeListener, Error> {
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        Ok(UpgradeListener { listener })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_: &str) -> Result<UpgradeListener, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "handing over the listener needs Unix sockets",
+        ))
+    }
+
+    #[cfg(unix)]
+    pub async fn accept(&self) -> Result<Handoff, Error> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(Handoff { stream })
+    }
+
+    #[cfg(not(unix))]
+    pub async fn accept(&self) -> Result<Handoff, Error> {
+        std::future::pending().await
+    }
+}
+
+impl Handoff {
+    /// Sends `listener` to the new process.
+    #[cfg(unix)]
+    pub async fn hand_over(self, listener: &TcpListener) -> Result<(), Error> {
+        let fd = listener.as_raw_fd();
+
+        self.stream
+            .async_io(Interest::WRITABLE, || send_fd(self.stream.as_raw_fd(), fd))
+            .await
+    }
+
+    #[cfg(not(unix))]
+    pub async fn hand_over(self, _: &TcpListener) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn send_fd(socket: RawFd, fd: RawFd) -> Result<(), Error> {
+    let fds = [fd];
+    let cmsgs = [ControlMessage::ScmRights(&fds)];
+
+    sendmsg::<UnixAddr>(socket, &[IoSlice::new(HANDOFF)], &cmsgs, MsgFlags::empty(), None)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn receive_fd(socket: RawFd) -> Result<OwnedFd, Error> {
+    let mut body = [0u8; HANDOFF.len()];
+    let mut iov = [IoSliceMut::new(&mut body)];
+    let mut cmsg_buffer = cmsg_space!([RawFd; 1]);
+
+    let message = recvmsg::<UnixAddr>(socket, &mut iov, Some(&mut cmsg_buffer), MsgFlags::MSG_CMSG_CLOEXEC)?;
+
+    for cmsg in message.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            if let Some(&fd) = fds.first() {
+                // SAFETY: the descriptor was just received and nothing else
+                // owns it.
+                return Ok(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+        }
+    }
+
+    Err(Error::new(ErrorKind::InvalidData, "the running proxy sent no listener"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use uuid::Uuid;
+
+    fn socket_path() -> String {
+        std::env::temp_dir()
+            .join(format!("rustyproxy-upgrade-{}.sock", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[tokio::test]
+    async fn nothing_to_inherit_without_a_proxy() {
+        assert!(inherit(&socket_path()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn listeners_are_handed_over() {
+        let path = socket_path();
+        let upgrades = UpgradeListener::bind(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (inherited, handed_over) = tokio::join!(inherit(&path), async {
+            upgrades.accept().await?.hand_over(&listener).await
+        });
+        handed_over.unwrap();
+        let inherited = inherited.unwrap().unwrap();
+        assert_eq!(inherited.local_addr().unwrap(), addr);
+
+        // The new process accepts on the address even once the old one let go.
+        drop(listener);
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut accepted, _) = inherited.accept().await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut received = [0; 4];
+        accepted.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"ping");
+
+        drop(upgrades);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn binding_replaces_stale_sockets() {
+        let path = socket_path();
+        drop(UpgradeListener::bind(&path).unwrap());
+
+        // Left behind by a process that is gone, so nobody accepts on it.
+        assert!(inherit(&path).await.unwrap().is_none());
+        let upgrades = UpgradeListener::bind(&path).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (inherited, handed_over) = tokio::join!(inherit(&path), async {
+            upgrades.accept().await?.hand_over(&listener).await
+        });
+        handed_over.unwrap();
+        assert!(inherited.unwrap().is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn handing_over_to_a_vanished_process_fails() {
+        let path = socket_path();
+        let upgrades = UpgradeListener::bind(&path).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        drop(UnixStream::connect(&path).await.unwrap());
+        let handoff = upgrades.accept().await.unwrap();
+        assert!(handoff.hand_over(&listener).await.is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}